@@ -0,0 +1,96 @@
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+/// Derives `to_context(&self) -> logical_expr::Context` for a struct with named fields.
+/// Every field becomes an entry named like the field, its type needs a conversion into `ContextValue`
+/// like `String`, `i64`, `f64` and `bool` have. Use it with the `derive` feature of logical_expr.
+#[proc_macro_derive(IntoContext)]
+pub fn derive_into_context(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let name = loop {
+        match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => match tokens.next() {
+                Some(TokenTree::Ident(name)) => break name.to_string(),
+                _ => return Err("IntoContext requires a struct name".to_string()),
+            },
+            Some(TokenTree::Ident(ident)) if matches!(ident.to_string().as_str(), "enum" | "union") => {
+                return Err("IntoContext can only be derived for structs".to_string())
+            }
+            Some(_) => {}
+            None => return Err("IntoContext can only be derived for structs".to_string()),
+        }
+    };
+    let fields = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => field_names(group.stream()),
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '<' => return Err("IntoContext can't be derived for generic structs".to_string()),
+        _ => return Err("IntoContext requires a struct with named fields".to_string()),
+    };
+    let inserts: String = fields.iter().map(|field| {
+        let key = field.strip_prefix("r#").unwrap_or(field);
+        format!("context.insert({:?}.to_string(), ::logical_expr::ContextValue::from(::std::clone::Clone::clone(&self.{})));", key, field)
+    }).collect();
+    let output = format!(
+        "impl {} {{
+            /// The fields of the struct as a context, each entry is named like its field.
+            pub fn to_context(&self) -> ::logical_expr::Context {{
+                let mut context = ::logical_expr::Context::new();
+                {}
+                context
+            }}
+        }}",
+        name, inserts
+    );
+    output.parse().map_err(|err| format!("IntoContext generated invalid code: {:?}", err))
+}
+
+/// The names of the fields in the braces of a struct, attributes and visibilities are skipped.
+fn field_names(body: TokenStream) -> Vec<String> {
+    let mut names = vec![];
+    let mut tokens = body.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            // the group of an attribute like #[serde(rename = "x")]
+            TokenTree::Punct(punct) if punct.as_char() == '#' => {
+                tokens.next();
+            }
+            TokenTree::Ident(ident) if ident.to_string() == "pub" => {
+                if matches!(tokens.peek(), Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis) {
+                    tokens.next();
+                }
+            }
+            TokenTree::Ident(ident) => {
+                names.push(ident.to_string());
+                skip_type(&mut tokens);
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Skips the colon and the type of a field up to the comma which ends it.
+/// Commas between angle brackets like in `HashMap<String, i64>` belong to the type.
+fn skip_type(tokens: &mut impl Iterator<Item = TokenTree>) {
+    let mut depth = 0usize;
+    let mut arrow = false;
+    for token in tokens {
+        if let TokenTree::Punct(punct) = token {
+            match punct.as_char() {
+                ',' if depth == 0 => return,
+                '<' => depth += 1,
+                // the > of -> does not close an angle bracket
+                '>' if !arrow => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            arrow = punct.as_char() == '-' && punct.spacing() == Spacing::Joint;
+        } else {
+            arrow = false;
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::cmp::Ordering;
+
+use crate::EvalError;
+
+/// Orders two strings according to the given locale.
+/// Locales using the arabic script sort it before the latin script, all other locales use the CLDR root order.
+#[cfg(feature = "collation")]
+pub(crate) fn collate(locale: &str, lhs: &str, rhs: &str) -> Result<Ordering, EvalError> {
+    use feruca::{Collator, Locale, Tailoring};
+
+    let language = locale.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+    let tailoring = match language.as_str() {
+        "ar" | "fa" | "ps" | "ur" => Tailoring::Cldr(Locale::ArabicScript),
+        _ => Tailoring::Cldr(Locale::Root),
+    };
+    Ok(Collator::new(tailoring, true, true).collate(lhs, rhs))
+}
+
+#[cfg(not(feature = "collation"))]
+pub(crate) fn collate(locale: &str, _lhs: &str, _rhs: &str) -> Result<Ordering, EvalError> {
+    Err(EvalError::InvalidOption(format!("Collation for locale {} requires the collation feature", locale)))
+}
+
+#[cfg(feature = "collation")]
+#[test]
+fn test_collate() {
+    assert_eq!(collate("en", "apple", "Banana"), Ok(Ordering::Less));
+    assert_eq!("apple".cmp("Banana"), Ordering::Greater);
+    assert_eq!(collate("en-US", "éclair", "zebra"), Ok(Ordering::Less));
+    assert_eq!("éclair".cmp("zebra"), Ordering::Greater);
+}
@@ -0,0 +1,590 @@
+use std::{collections::HashMap, sync::Arc};
+
+use regex::Regex;
+
+use crate::{env::{compile_regex, compile_regex_with_limit, EvalEnv}, expression::parse_whole_boolean_expression, schema::{check_types, resolve_literals}, value::{check_custom_operators, with_parse_options}, BinaryOperator, BooleanExpression, Context, ContextProvider, EvalError, EvalOptions, FunctionRegistry, ParseOptions, ResolvedContext, UnaryOperator, ValueType};
+
+/// An expression which was parsed once and can be evaluated many times.
+/// All regex literals of the expression are compiled up front.
+/// Evaluation doesn't mutate it, it is Send and Sync and can be shared between threads, e.g. behind an Arc.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{CompiledExpression, Context, ContextValue};
+///
+/// let expr = CompiledExpression::compile("name =~ '^ba+r$'").unwrap();
+/// for name in ["bar", "baaar", "foo"] {
+///     let mut context = Context::new();
+///     context.insert("name".to_string(), ContextValue::String(name.to_string()));
+///     assert_eq!(expr.evaluate(&context), Ok(name != "foo"));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompiledExpression {
+    expression: BooleanExpression,
+    regexes: HashMap<String, Regex>,
+    identifiers: Arc<[String]>,
+    /// The size limit the regexes were compiled with, see CompilerContext::with_regex_size_limit().
+    regex_size_limit: Option<usize>,
+}
+
+impl CompiledExpression {
+    /// Parses the expression and compiles its regex literals.
+    pub fn compile(expression: &str) -> Result<Self, EvalError> {
+        CompilerContext::new().compile(expression)
+    }
+
+    /// Parses the expression like compile() but rejects it with EvalError::TooComplex
+    /// if its tree is nested deeper than max_depth or has more than max_nodes nodes.
+    /// Use it as a guard against overly complex untrusted expressions.
+    pub fn compile_with_limits(expression: &str, max_depth: usize, max_nodes: usize) -> Result<Self, EvalError> {
+        CompilerContext::new().compile_with_limits(expression, max_depth, max_nodes)
+    }
+
+    /// Parses the expression like compile() and replaces constants with their values from the given map.
+    /// Constants are identifiers written in upper case like `HTTP_OK`, unknown constants are looked up
+    /// in the context during evaluation like any other identifier.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::{CompiledExpression, Context, ContextValue};
+    ///
+    /// let mut constants = Context::new();
+    /// constants.insert("HTTP_OK".to_string(), ContextValue::Integer(200));
+    /// let expr = CompiledExpression::compile_with_constants("code == HTTP_OK", &constants).unwrap();
+    /// assert_eq!(expr.expression().to_string(), "code == 200");
+    ///
+    /// let mut context = Context::new();
+    /// context.insert("code".to_string(), ContextValue::Integer(200));
+    /// assert_eq!(expr.evaluate(&context), Ok(true));
+    /// ```
+    pub fn compile_with_constants(expression: &str, constants: &Context) -> Result<Self, EvalError> {
+        CompilerContext::new().compile_with_constants(expression, constants)
+    }
+
+    /// Parses the expression like compile() but with the given options, e.g. to accept `yes` and `no` as booleans.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::{CompiledExpression, Context, ParseOptions};
+    ///
+    /// let expr = CompiledExpression::compile_with_parse_options("YES && !off", &ParseOptions::lenient()).unwrap();
+    /// assert_eq!(expr.evaluate(&Context::new()), Ok(true));
+    /// ```
+    pub fn compile_with_parse_options(expression: &str, options: &ParseOptions) -> Result<Self, EvalError> {
+        CompilerContext::new().compile_with_parse_options(expression, options)
+    }
+
+    /// Parses the expression like compile() and checks it against the declared types of its identifiers.
+    /// Identifiers missing from the schema are rejected with EvalError::UnknownIdentifier, comparisons of
+    /// different types with EvalError::TypeMismatch and operators the type does not support with EvalError::InvalidOperator.
+    /// Literals compared to an identifier take its declared type, e.g. a string compared to a datetime is parsed
+    /// as a timestamp once here instead of on every evaluation.
+    /// The context still has to provide values of the declared types when the expression is evaluated.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use logical_expr::{CompiledExpression, EvalError, ValueType};
+    ///
+    /// let mut schema = HashMap::new();
+    /// schema.insert("name".to_string(), ValueType::String);
+    /// schema.insert("age".to_string(), ValueType::Integer);
+    /// assert!(CompiledExpression::compile_typed("name =~ '^A' && age >= 18", &schema).is_ok());
+    /// assert!(matches!(CompiledExpression::compile_typed("name < 18", &schema), Err(EvalError::TypeMismatch(_))));
+    /// ```
+    pub fn compile_typed(expression: &str, schema: &HashMap<String, ValueType>) -> Result<Self, EvalError> {
+        CompilerContext::new().compile_typed(expression, schema)
+    }
+
+    /// Joins both expressions with `&&` without parsing them again, the compiled regexes of both are kept.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::CompiledExpression;
+    ///
+    /// let adult = CompiledExpression::compile("age >= 18").unwrap();
+    /// let member = CompiledExpression::compile("member || admin").unwrap();
+    /// assert_eq!(adult.and(member).expression().to_string(), "age >= 18 && (member || admin)");
+    /// ```
+    pub fn and(self, other: Self) -> Self {
+        self.combine(BinaryOperator::And, other)
+    }
+
+    /// Joins both expressions with `||` without parsing them again, the compiled regexes of both are kept.
+    /// Joining chains of the same operator keeps them flat, so folding many expressions with or() does not nest deeply.
+    pub fn or(self, other: Self) -> Self {
+        self.combine(BinaryOperator::Or, other)
+    }
+
+    /// Negates the expression without parsing it again.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        CompiledExpression { expression: BooleanExpression::Unary(UnaryOperator::Not, Box::new(self.expression)), ..self }
+    }
+
+    fn combine(self, op: BinaryOperator, other: Self) -> Self {
+        let mut regexes = self.regexes;
+        regexes.extend(other.regexes);
+        let mut identifiers: Vec<String> = self.identifiers.iter().chain(other.identifiers.iter()).cloned().collect();
+        identifiers.sort();
+        identifiers.dedup();
+        // joining chains of the same operator keeps them flat, `&&` and `||` are associative
+        let mut operands = vec![];
+        for expression in [self.expression, other.expression] {
+            match expression {
+                BooleanExpression::Chain(chain_op, chain) if chain_op == op => operands.extend(chain),
+                expression => operands.push(expression),
+            }
+        }
+        let expression = BooleanExpression::Chain(op, operands);
+        // the regexes of both are within the larger limit
+        let regex_size_limit = self.regex_size_limit.zip(other.regex_size_limit).map(|(lhs, rhs)| lhs.max(rhs));
+        CompiledExpression { expression, regexes, identifiers: identifiers.into(), regex_size_limit }
+    }
+
+    /// The parsed syntax tree.
+    pub fn expression(&self) -> &BooleanExpression {
+        &self.expression
+    }
+
+    /// Whether an identifier with the name is used anywhere in the expression, see BooleanExpression::contains_identifier().
+    pub fn contains_identifier(&self, name: &str) -> bool {
+        self.expression.contains_identifier(name)
+    }
+
+    /// The result of the expression if it simplifies to a boolean literal like `true`, `1 == 1` or `false && a`,
+    /// so callers can skip the evaluation. None if the result depends on the context.
+    pub fn is_trivially(&self) -> Option<bool> {
+        match self.expression.clone().simplify() {
+            BooleanExpression::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn evaluate(&self, context: &impl ContextProvider) -> Result<bool, EvalError> {
+        self.evaluate_with_options(context, &EvalOptions::default())
+    }
+
+    pub fn evaluate_with_options(&self, context: &impl ContextProvider, options: &EvalOptions) -> Result<bool, EvalError> {
+        self.expression.evaluate(&self.env(context, options))
+    }
+
+    /// Evaluates the expression and returns the result of every evaluated leaf condition with its source,
+    /// e.g. to show which conditions of a rule failed. Leaves skipped by short-circuiting are left out.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::{CompiledExpression, Context, ContextValue};
+    ///
+    /// let mut context = Context::new();
+    /// context.insert("age".to_string(), ContextValue::Integer(17));
+    /// context.insert("admin".to_string(), ContextValue::Boolean(true));
+    ///
+    /// let expr = CompiledExpression::compile("age >= 18 || admin").unwrap();
+    /// assert_eq!(expr.evaluate_trace(&context), Ok((true, vec![("age >= 18".to_string(), false), ("admin".to_string(), true)])));
+    /// ```
+    pub fn evaluate_trace(&self, context: &impl ContextProvider) -> Result<(bool, Vec<(String, bool)>), EvalError> {
+        let options = EvalOptions::default();
+        let mut trace = vec![];
+        let result = self.expression.evaluate_trace(&self.env(context, &options), &mut trace)?;
+        Ok((result, trace))
+    }
+
+    /// Evaluates the expression and calls the functions of the registry, functions which are
+    /// not registered fall back to the builtin ones.
+    pub fn evaluate_with_functions(&self, context: &impl ContextProvider, functions: &FunctionRegistry, options: &EvalOptions) -> Result<bool, EvalError> {
+        let env = EvalEnv { functions: Some(functions), ..self.env(context, options) };
+        self.expression.evaluate(&env)
+    }
+
+    /// The environment to evaluate the expression with its precompiled regexes.
+    pub(crate) fn env<'a>(&'a self, context: &'a dyn ContextProvider, options: &'a EvalOptions) -> EvalEnv<'a> {
+        EvalEnv { context, options, regexes: Some(&self.regexes), regex_size_limit: self.regex_size_limit, functions: None }
+    }
+
+    /// Extracts the values of the identifiers this expression references from the context.
+    /// Use it to evaluate the expression repeatedly against the same large context.
+    pub fn resolve(&self, context: &impl ContextProvider) -> ResolvedContext {
+        ResolvedContext::new(self.identifiers.clone(), context)
+    }
+
+    /// Evaluates the expression against a context created by resolve() of this expression.
+    pub fn evaluate_resolved(&self, context: &ResolvedContext, options: &EvalOptions) -> Result<bool, EvalError> {
+        self.expression.evaluate(&self.env(context, options))
+    }
+}
+
+/// Holds compiled regexes which are shared by all expressions compiled with it,
+/// so expressions using the same pattern only compile it once.
+#[derive(Debug, Clone, Default)]
+pub struct CompilerContext {
+    regexes: HashMap<String, Regex>,
+    regex_size_limit: Option<usize>,
+}
+
+impl CompilerContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles all regexes with the size limit in bytes, larger patterns fail with EvalError::InvalidRegex when compiling.
+    /// Evaluating with an EvalOptions::regex_size_limit of at least this limit uses the precompiled regexes.
+    pub fn with_regex_size_limit(size_limit: usize) -> Self {
+        CompilerContext { regex_size_limit: Some(size_limit), ..Self::default() }
+    }
+
+    pub fn compile(&mut self, expression: &str) -> Result<CompiledExpression, EvalError> {
+        self.compile_with_limits(expression, usize::MAX, usize::MAX)
+    }
+
+    /// Compiles the expression, see CompiledExpression::compile_with_limits().
+    pub fn compile_with_limits(&mut self, expression: &str, max_depth: usize, max_nodes: usize) -> Result<CompiledExpression, EvalError> {
+        // the regexes are validated while compiling them below
+        let expression = parse_whole_boolean_expression(expression)?;
+        let depth = expression.depth();
+        if depth > max_depth {
+            return Err(EvalError::TooComplex(format!("Expression is nested {} levels deep, the limit is {}", depth, max_depth)));
+        }
+        let nodes = expression.node_count();
+        if nodes > max_nodes {
+            return Err(EvalError::TooComplex(format!("Expression has {} nodes, the limit is {}", nodes, max_nodes)));
+        }
+        self.compile_parsed(expression)
+    }
+
+    /// Compiles the expression, see CompiledExpression::compile_with_parse_options().
+    pub fn compile_with_parse_options(&mut self, expression: &str, options: &ParseOptions) -> Result<CompiledExpression, EvalError> {
+        let expression = with_parse_options(options, || {
+            check_custom_operators()?;
+            parse_whole_boolean_expression(expression)
+        })?;
+        self.compile_parsed(expression)
+    }
+
+    /// Compiles the expression, see CompiledExpression::compile_with_constants().
+    pub fn compile_with_constants(&mut self, expression: &str, constants: &Context) -> Result<CompiledExpression, EvalError> {
+        let expression = parse_whole_boolean_expression(expression)?
+            .substitute(constants, &|ident| ident.is_constant() && constants.contains_key(ident.name()))?;
+        self.compile_parsed(expression)
+    }
+
+    /// Compiles the expression, see CompiledExpression::compile_typed().
+    pub fn compile_typed(&mut self, expression: &str, schema: &HashMap<String, ValueType>) -> Result<CompiledExpression, EvalError> {
+        let expression = parse_whole_boolean_expression(expression)?;
+        check_types(&expression, schema)?;
+        self.compile_parsed(resolve_literals(expression, schema)?)
+    }
+
+    fn compile_parsed(&mut self, expression: BooleanExpression) -> Result<CompiledExpression, EvalError> {
+        let mut regexes = HashMap::new();
+        for pattern in expression.regex_patterns() {
+            let regex = self.regex(&pattern)?;
+            regexes.insert(pattern, regex);
+        }
+        let mut identifiers: Vec<String> = expression.identifiers().iter().map(|ident| ident.name().to_string()).collect();
+        identifiers.sort();
+        identifiers.dedup();
+        Ok(CompiledExpression { expression, regexes, identifiers: identifiers.into(), regex_size_limit: self.regex_size_limit })
+    }
+
+    fn regex(&mut self, pattern: &str) -> Result<Regex, EvalError> {
+        if let Some(regex) = self.regexes.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = match self.regex_size_limit {
+            Some(size_limit) => compile_regex_with_limit(pattern, size_limit)?,
+            None => compile_regex(pattern)?,
+        };
+        self.regexes.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+
+    /// Returns the sorted patterns of all cached regexes.
+    /// Only the patterns are exported, they can be compiled again with import_cache().
+    pub fn export_cache(&self) -> Vec<String> {
+        let mut patterns: Vec<String> = self.regexes.keys().cloned().collect();
+        patterns.sort();
+        patterns
+    }
+
+    /// Compiles the given patterns in bulk, e.g. the output of export_cache() of a previous run.
+    /// Fails on the first invalid pattern, the patterns compiled until then stay cached.
+    pub fn import_cache<I, S>(&mut self, patterns: I) -> Result<(), EvalError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for pattern in patterns {
+            self.regex(pattern.as_ref())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ContextValue, NonBooleanExpression, Value};
+
+    #[test]
+    fn test_compiled_evaluate() {
+        let expr = CompiledExpression::compile("name =~ 'ba+r' && count > 1").unwrap();
+        let mut context = Context::new();
+        context.insert("name".to_string(), ContextValue::String("baaar".to_string()));
+        context.insert("count".to_string(), ContextValue::Integer(2));
+        assert_eq!(expr.evaluate(&context), Ok(true));
+        context.insert("count".to_string(), ContextValue::Integer(0));
+        assert_eq!(expr.evaluate(&context), Ok(false));
+    }
+
+    #[test]
+    fn test_is_trivially() {
+        let is_trivially = |expression: &str| CompiledExpression::compile(expression).unwrap().is_trivially();
+        assert_eq!(is_trivially("true"), Some(true));
+        assert_eq!(is_trivially("1 == 1"), Some(true));
+        assert_eq!(is_trivially("false && a"), Some(false));
+        assert_eq!(is_trivially("a && b"), None);
+        assert_eq!(is_trivially("1 == 1 && a"), None);
+    }
+
+    #[test]
+    fn combine() {
+        let compile = |expression: &str| CompiledExpression::compile(expression).unwrap();
+        let (a, b) = ("name =~ '^a' || admin", "age >= 18");
+        let and = compile(a).and(compile(b));
+        let or = compile(a).or(compile(b));
+        let not = compile(a).not();
+        assert_eq!(and.expression(), compile("(name =~ '^a' || admin) && (age >= 18)").expression());
+        assert_eq!(or.expression(), compile("name =~ '^a' || admin || age >= 18").expression());
+        assert_eq!(not.expression().to_string(), "!(name =~ '^a' || admin)");
+        for (name, admin, age) in [("abc", false, 20), ("abc", false, 10), ("bcd", true, 20), ("bcd", false, 20)] {
+            let mut context = Context::new();
+            context.insert("name".to_string(), ContextValue::String(name.to_string()));
+            context.insert("admin".to_string(), ContextValue::Boolean(admin));
+            context.insert("age".to_string(), ContextValue::Integer(age));
+            assert_eq!(and.evaluate(&context), compile(&format!("({}) && ({})", a, b)).evaluate(&context));
+            assert_eq!(or.evaluate(&context), compile(&format!("({}) || ({})", a, b)).evaluate(&context));
+            assert_eq!(not.evaluate(&context), compile(a).evaluate(&context).map(|result| !result));
+            assert_eq!(and.evaluate_resolved(&and.resolve(&context), &EvalOptions::default()), and.evaluate(&context));
+        }
+    }
+
+    fn _assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_and_sync() {
+        _assert_send_sync::<CompiledExpression>();
+        _assert_send_sync::<CompilerContext>();
+        _assert_send_sync::<BooleanExpression>();
+        _assert_send_sync::<crate::NonBooleanExpression>();
+        _assert_send_sync::<crate::Value>();
+        _assert_send_sync::<crate::Identifier>();
+        _assert_send_sync::<EvalError>();
+
+        let expr = Arc::new(CompiledExpression::compile("name =~ '^a'").unwrap());
+        let handles: Vec<_> = ["abc", "bcd"].into_iter().map(|name| {
+            let expr = Arc::clone(&expr);
+            std::thread::spawn(move || {
+                let mut context = Context::new();
+                context.insert("name".to_string(), ContextValue::String(name.to_string()));
+                expr.evaluate(&context)
+            })
+        }).collect();
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(results, vec![Ok(true), Ok(false)]);
+    }
+
+    #[test]
+    fn test_evaluate_trace() {
+        let mut context = Context::new();
+        context.insert("a".to_string(), ContextValue::Boolean(true));
+        context.insert("b".to_string(), ContextValue::Boolean(false));
+        context.insert("n".to_string(), ContextValue::Integer(3));
+        let trace = |expression: &str| CompiledExpression::compile(expression).unwrap().evaluate_trace(&context);
+        assert_eq!(trace("a && b"), Ok((false, vec![("a".to_string(), true), ("b".to_string(), false)])));
+        assert_eq!(trace("b && a"), Ok((false, vec![("b".to_string(), false)])));
+        assert_eq!(
+            trace("!(n between 1 and 5) || (n > 2 && n is positive)"),
+            Ok((true, vec![("n between 1 and 5".to_string(), true), ("n > 2".to_string(), true), ("n is positive".to_string(), true)]))
+        );
+        assert!(matches!(trace("a && missing"), Err(EvalError::UnknownIdentifier(_))));
+    }
+
+    #[test]
+    fn test_contains_identifier() {
+        let compiled = CompiledExpression::compile("(user.age > 18 && active) || role == admin").unwrap();
+        assert!(compiled.contains_identifier("user.age"));
+        assert!(compiled.contains_identifier("admin"));
+        assert!(!compiled.contains_identifier("user"));
+    }
+
+    #[test]
+    fn test_resolved_matches_map_lookup() {
+        let expr = CompiledExpression::compile("(b && a > 1) || c =~ 'x+' || a < 0").unwrap();
+        let options = EvalOptions::default();
+        let mut context = Context::new();
+        context.insert("unused".to_string(), ContextValue::Integer(1));
+        for (a, b, c) in [(2, true, "y"), (0, true, "x"), (-1, false, "y"), (5, false, "y")] {
+            context.insert("a".to_string(), ContextValue::Integer(a));
+            context.insert("b".to_string(), ContextValue::Boolean(b));
+            context.insert("c".to_string(), ContextValue::String(c.to_string()));
+            let resolved = expr.resolve(&context);
+            assert_eq!(resolved.values.len(), 3);
+            assert_eq!(expr.evaluate_resolved(&resolved, &options), expr.evaluate(&context));
+        }
+        context.remove("b");
+        assert_eq!(expr.evaluate_resolved(&expr.resolve(&context), &options), expr.evaluate(&context));
+        assert!(expr.evaluate(&context).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_custom_provider() {
+        use std::borrow::Cow;
+
+        struct Point {
+            x: i64,
+            y: i64,
+        }
+        impl ContextProvider for Point {
+            fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+                match key {
+                    "x" => Some(Cow::Owned(ContextValue::Integer(self.x))),
+                    "y" => Some(Cow::Owned(ContextValue::Integer(self.y))),
+                    _ => None,
+                }
+            }
+        }
+        let expr = CompiledExpression::compile("x > 0 && y < 0").unwrap();
+        assert_eq!(expr.evaluate(&Point { x: 1, y: -1 }), Ok(true));
+        assert_eq!(expr.evaluate(&Point { x: 1, y: 1 }), Ok(false));
+        let point = Point { x: 1, y: -1 };
+        assert_eq!(expr.evaluate_resolved(&expr.resolve(&point), &EvalOptions::default()), Ok(true));
+    }
+
+    #[test]
+    fn test_invalid_regex_fails_to_compile() {
+        assert_eq!(CompiledExpression::compile("x =~ '('").unwrap_err(), EvalError::InvalidRegex("Invalid regex: (".to_string()));
+        assert!(matches!(CompiledExpression::compile("true || x ===~ '['"), Err(EvalError::InvalidRegex(_))));
+        assert!(matches!(crate::parse("false && x =~ '('"), Err(EvalError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn test_compile_with_constants() {
+        let mut constants = Context::new();
+        constants.insert("HTTP_OK".to_string(), ContextValue::Integer(200));
+        constants.insert("ADMIN".to_string(), ContextValue::String("admin".to_string()));
+        constants.insert("lower".to_string(), ContextValue::Integer(1));
+        let expr = CompiledExpression::compile_with_constants("code == HTTP_OK && role == ADMIN && lower == LIMIT", &constants).unwrap();
+        assert_eq!(expr.expression().to_string(), "code == 200 && role == 'admin' && lower == LIMIT");
+        let mut context = Context::new();
+        context.insert("code".to_string(), ContextValue::Integer(200));
+        context.insert("role".to_string(), ContextValue::String("admin".to_string()));
+        context.insert("lower".to_string(), ContextValue::Integer(2));
+        context.insert("LIMIT".to_string(), ContextValue::Integer(2));
+        assert_eq!(expr.evaluate(&context), Ok(true));
+        constants.insert("MIN".to_string(), ContextValue::Integer(-10));
+        constants.insert("QUOTE".to_string(), ContextValue::String("'".to_string()));
+        let expr = CompiledExpression::compile_with_constants("code > MIN && role != QUOTE", &constants).unwrap();
+        assert_eq!(expr.expression().to_string(), "code > -10 && role != ''''");
+        assert_eq!(CompiledExpression::compile(&expr.expression().to_string()).unwrap().expression(), expr.expression());
+        constants.insert("ENABLED".to_string(), ContextValue::Boolean(true));
+        let expr = CompiledExpression::compile_with_constants("ENABLED && !HTTP_OK_2", &constants).unwrap();
+        assert_eq!(expr.expression().to_string(), "true && !HTTP_OK_2");
+        assert!(matches!(CompiledExpression::compile_with_constants("HTTP_OK", &constants), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_compile_with_parse_options() {
+        let mut context = Context::new();
+        context.insert("on".to_string(), ContextValue::Boolean(false));
+        let lenient = CompiledExpression::compile_with_parse_options("YES && on", &ParseOptions::lenient()).unwrap();
+        assert_eq!(lenient.evaluate(&context), Ok(true));
+        let strict = CompiledExpression::compile("YES && on").unwrap();
+        assert!(matches!(strict.evaluate(&context), Err(EvalError::UnknownIdentifier(_))));
+        assert!(CompiledExpression::compile_with_parse_options("x == True", &ParseOptions::lenient()).is_ok());
+        assert!(CompiledExpression::compile("x == True").unwrap().evaluate(&context).is_err());
+    }
+
+    #[test]
+    fn test_compile_typed() {
+        let schema: HashMap<String, ValueType> = [("name".to_string(), ValueType::String), ("score".to_string(), ValueType::Float)].into_iter().collect();
+        assert!(matches!(CompiledExpression::compile_typed("name < 3", &schema), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(CompiledExpression::compile_typed("score > 3", &schema), Err(EvalError::TypeMismatch(_))));
+        let expr = CompiledExpression::compile_typed("name != '' && score > 3.5", &schema).unwrap();
+        let mut context = Context::new();
+        context.insert("name".to_string(), ContextValue::String("ada".to_string()));
+        context.insert("score".to_string(), ContextValue::Float(4.0));
+        assert_eq!(expr.evaluate(&context), Ok(true));
+        // the literal compared to a datetime is resolved when compiling
+        let schema: HashMap<String, ValueType> = [("created".to_string(), ValueType::DateTime)].into_iter().collect();
+        let expr = CompiledExpression::compile_typed("created >= '2024-01-01T00:00:00Z'", &schema).unwrap();
+        assert!(matches!(expr.expression(), BooleanExpression::NonBooleanExpression(NonBooleanExpression(_, _, Value::DateTime(1_704_067_200)))));
+        context.insert("created".to_string(), ContextValue::DateTime(1_704_067_200));
+        assert_eq!(expr.evaluate(&context), Ok(true));
+        assert!(matches!(CompiledExpression::compile_typed("created > 'yesterday'", &schema), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_compile_with_limits() {
+        // depth 3 and 5 nodes: &&, a, ||, b, c
+        let expr = "a && (b || c > 1)";
+        assert!(CompiledExpression::compile_with_limits(expr, 3, 5).is_ok());
+        assert_eq!(
+            CompiledExpression::compile_with_limits(expr, 2, 5).unwrap_err(),
+            EvalError::TooComplex("Expression is nested 3 levels deep, the limit is 2".to_string())
+        );
+        assert_eq!(
+            CompiledExpression::compile_with_limits(expr, 3, 4).unwrap_err(),
+            EvalError::TooComplex("Expression has 5 nodes, the limit is 4".to_string())
+        );
+        assert!(CompiledExpression::compile_with_limits("!!a", 3, 3).is_ok());
+        assert!(CompiledExpression::compile_with_limits("!!!a", 3, 4).is_err());
+    }
+
+    #[test]
+    fn test_compiler_context_shares_regexes() {
+        let mut compiler = CompilerContext::new();
+        compiler.compile("a =~ 'x+' && b =~ 'y+'").unwrap();
+        compiler.compile("c =~ 'x+'").unwrap();
+        assert_eq!(compiler.export_cache(), vec!["x+".to_string(), "y+".to_string()]);
+    }
+
+    #[test]
+    fn test_export_import_cache() {
+        let mut compiler = CompilerContext::new();
+        compiler.compile("a =~ '^ab+$' || b =~ 'c.d'").unwrap();
+        let exported = compiler.export_cache();
+
+        let mut warm = CompilerContext::new();
+        warm.import_cache(&exported).unwrap();
+        assert_eq!(warm.export_cache(), exported);
+        assert!(warm.regexes.contains_key("^ab+$"));
+        assert!(warm.regexes.contains_key("c.d"));
+
+        assert_eq!(warm.import_cache(["("]), Err(EvalError::InvalidRegex("Invalid regex: (".to_string())));
+    }
+
+    #[test]
+    fn test_compile_with_regex_size_limit() {
+        let mut compiler = CompilerContext::with_regex_size_limit(10_000);
+        assert_eq!(
+            compiler.compile("name =~ '(a|b|c){1000}'").unwrap_err(),
+            EvalError::InvalidRegex("Regex exceeds the size limit of 10000 bytes: (a|b|c){1000}".to_string())
+        );
+        assert_eq!(compiler.export_cache(), Vec::<String>::new());
+        let mut context = Context::new();
+        context.insert("name".to_string(), ContextValue::String("abc".to_string()));
+        let options = EvalOptions { regex_size_limit: Some(10_000), ..Default::default() };
+        // the precompiled regex is replaced by one which doesn't match, so a false result shows it was used
+        let mut expr = compiler.compile("name =~ '^a.c$'").unwrap();
+        expr.regexes.insert("^a.c$".to_string(), Regex::new("^x").unwrap());
+        assert_eq!(expr.evaluate_with_options(&context, &options), Ok(false));
+        assert_eq!(expr.evaluate_with_options(&context, &EvalOptions { regex_size_limit: Some(5_000), ..Default::default() }), Ok(true));
+        // regexes compiled without a limit are compiled again with the limit of the options
+        let mut unlimited = CompiledExpression::compile("name =~ '^a.c$'").unwrap();
+        unlimited.regexes.insert("^a.c$".to_string(), Regex::new("^x").unwrap());
+        assert_eq!(unlimited.evaluate_with_options(&context, &options), Ok(true));
+        assert_eq!(expr.and(unlimited).regex_size_limit, None);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::expression::strip_comments;
+
+/// A token which can follow the input at the cursor, see completions_at().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Completion {
+    /// One of the known identifiers.
+    Identifier(String),
+    /// An operator like `&&`, `==` or `!`.
+    Operator(String),
+    /// A keyword like `true`, `between` or `positive`.
+    Keyword(String),
+    OpenParenthesis,
+    CloseParenthesis,
+}
+
+/// What the grammar expects after the tokens before the cursor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expected {
+    /// A value, an identifier, a parenthesis or a unary operator.
+    Operand,
+    /// An operator or the end of a parenthesis after a complete operand.
+    Operator,
+    /// The name of a predicate after `is`.
+    Predicate,
+    /// A duration after `within_last` or `within_next`, it can't be completed.
+    Duration,
+}
+
+const OPERATORS: [&str; 19] = ["==", "!=", "<", ">", "<=", ">=", "=~", "===~", "&&", "||", "^^", "->", "+", "-", "*", "**", "%", "?:", "??"];
+const OPERAND_KEYWORDS: [&str; 3] = ["true", "false", "not"];
+const OPERATOR_KEYWORDS: [&str; 10] = ["is", "between", "within_last", "within_next", "in", "contains", "like", "not", "and", "or"];
+const PREDICATES: [&str; 3] = ["positive", "negative", "zero"];
+
+/// Returns the tokens which can follow the input at the byte offset of the cursor, e.g. to drive autocompletion in an editor.
+/// Only the input before the cursor is considered. If the cursor is at the end of a word, identifiers and keywords
+/// which extend the word are suggested as well as the tokens which can follow the complete word.
+/// Nothing is suggested inside a string literal, quoted identifier or comment, or if the cursor is not at a character boundary.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{completions_at, Completion};
+///
+/// let known = ["age".to_string(), "active".to_string()];
+/// let completions = completions_at("age > 18 && a", 13, &known);
+/// assert!(completions.contains(&Completion::Identifier("active".to_string())));
+/// assert!(completions.contains(&Completion::Operator("==".to_string())));
+/// ```
+pub fn completions_at(input: &str, cursor: usize, known_identifiers: &[String]) -> Vec<Completion> {
+    let Some(prefix) = input.get(..cursor) else {
+        return vec![];
+    };
+    let Ok(stripped) = strip_comments(prefix) else {
+        return vec![];
+    };
+    // comments are replaced by spaces, a replaced last character means the cursor is inside a line comment
+    let replaced = |s: &str| s.chars().last().is_some_and(|c| !c.is_whitespace());
+    if replaced(prefix) && !replaced(&stripped) && !prefix.ends_with("*/") {
+        return vec![];
+    }
+    let Some(state) = scan(&stripped) else {
+        return vec![];
+    };
+    let mut completions = vec![];
+    if let Some(partial) = state.partial {
+        let starts_with = |word: &&str| word.len() > partial.len() && word.starts_with(partial);
+        match state.before_partial {
+            Expected::Operand => {
+                completions.extend(known_identifiers.iter().map(String::as_str).filter(starts_with).map(|name| Completion::Identifier(name.to_string())));
+                completions.extend(OPERAND_KEYWORDS.iter().copied().filter(starts_with).map(|word| Completion::Keyword(word.to_string())));
+            }
+            Expected::Operator => completions.extend(OPERATOR_KEYWORDS.iter().copied().filter(starts_with).map(|word| Completion::Keyword(word.to_string()))),
+            Expected::Predicate => completions.extend(PREDICATES.iter().copied().filter(starts_with).map(|word| Completion::Keyword(word.to_string()))),
+            Expected::Duration => {}
+        }
+        // the tokens following the word are only valid if the word is complete by itself
+        let complete = match state.before_partial {
+            Expected::Operand => true,
+            Expected::Operator => state.expected != Expected::Operator,
+            Expected::Predicate => PREDICATES.contains(&partial),
+            Expected::Duration => false,
+        };
+        if !complete {
+            return completions;
+        }
+    }
+    match state.expected {
+        Expected::Operand if state.partial.is_none() => {
+            completions.extend(known_identifiers.iter().map(|name| Completion::Identifier(name.clone())));
+            completions.extend(OPERAND_KEYWORDS.iter().map(|word| Completion::Keyword(word.to_string())));
+            completions.push(Completion::OpenParenthesis);
+            completions.push(Completion::Operator("!".to_string()));
+        }
+        Expected::Operator => {
+            completions.extend(OPERATORS.iter().map(|op| Completion::Operator(op.to_string())));
+            if state.partial.is_none() {
+                completions.extend(OPERATOR_KEYWORDS.iter().map(|word| Completion::Keyword(word.to_string())));
+            }
+            if state.depth > 0 {
+                completions.push(Completion::CloseParenthesis);
+            }
+        }
+        Expected::Predicate if state.partial.is_none() => completions.extend(PREDICATES.iter().map(|word| Completion::Keyword(word.to_string()))),
+        _ => {}
+    }
+    completions
+}
+
+struct State<'a> {
+    expected: Expected,
+    /// The word the input ends with, it may be the start of a longer word.
+    partial: Option<&'a str>,
+    /// What was expected before the partial word.
+    before_partial: Expected,
+    /// The number of open parentheses.
+    depth: usize,
+}
+
+/// Reads the tokens of the input and tracks what the grammar expects next.
+/// Returns None if the input ends inside a string literal or quoted identifier.
+fn scan(input: &str) -> Option<State<'_>> {
+    let mut state = State { expected: Expected::Operand, partial: None, before_partial: Expected::Operand, depth: 0 };
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        state.partial = None;
+        match c {
+            c if c.is_whitespace() => {}
+            '\'' | '`' => {
+                chars.by_ref().find(|(_, quote)| *quote == c)?;
+                state.expected = Expected::Operator;
+            }
+            '(' => {
+                state.depth += 1;
+                state.expected = Expected::Operand;
+            }
+            ')' => {
+                state.depth = state.depth.saturating_sub(1);
+                state.expected = Expected::Operator;
+            }
+            '[' => {
+                chars.by_ref().find(|(_, c)| *c == ']');
+                state.expected = Expected::Operator;
+            }
+            '$' => {
+                chars.by_ref().find(|(_, c)| *c == '}');
+                state.expected = Expected::Operand;
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((index, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.') {
+                    end = index + c.len_utf8();
+                }
+                let word = &input[start..end];
+                state.before_partial = state.expected;
+                state.expected = match (state.expected, word) {
+                    (Expected::Operator, "is") => Expected::Predicate,
+                    (Expected::Operator, "within_last" | "within_next") => Expected::Duration,
+                    // `and` also follows the lower bound of `between`
+                    (Expected::Operator, "between" | "in" | "contains" | "like" | "and" | "or") | (Expected::Operand, "not") => Expected::Operand,
+                    _ => Expected::Operator,
+                };
+                if end == input.len() && !c.is_ascii_digit() {
+                    state.partial = Some(word);
+                }
+            }
+            _ => {
+                while chars.next_if(|(_, c)| "=!<>&|^-~+*%?:,".contains(*c)).is_some() {}
+                // operators, `!` and the comma between the arguments of a call are all followed by an operand
+                state.expected = Expected::Operand;
+            }
+        }
+    }
+    Some(state)
+}
+
+#[test]
+fn test_completions_at() {
+    let known = ["a".to_string(), "age".to_string(), "name".to_string()];
+    let after_and = completions_at("a && ", 5, &known);
+    assert!(after_and.contains(&Completion::Identifier("name".to_string())));
+    assert!(after_and.contains(&Completion::Keyword("true".to_string())));
+    assert!(after_and.contains(&Completion::OpenParenthesis));
+    assert!(!after_and.iter().any(|completion| matches!(completion, Completion::Operator(op) if op != "!")));
+
+    let after_a = completions_at("a", 1, &known);
+    assert!(after_a.contains(&Completion::Operator("&&".to_string())));
+    assert!(after_a.contains(&Completion::Operator("==".to_string())));
+    assert!(after_a.contains(&Completion::Identifier("age".to_string())));
+    assert!(!after_a.contains(&Completion::CloseParenthesis));
+
+    assert!(completions_at("(age > 1 ", 9, &known).contains(&Completion::CloseParenthesis));
+    assert_eq!(completions_at("age is ", 7, &known), vec![
+        Completion::Keyword("positive".to_string()),
+        Completion::Keyword("negative".to_string()),
+        Completion::Keyword("zero".to_string()),
+    ]);
+    assert_eq!(completions_at("age is z", 8, &known), vec![Completion::Keyword("zero".to_string())]);
+    assert!(completions_at("age between 1 ", 14, &known).contains(&Completion::Keyword("and".to_string())));
+    let and = Completion::Keyword("and".to_string());
+    assert_eq!(completions_at("age between 1 ", 14, &known).iter().filter(|completion| **completion == and).count(), 1);
+    assert!(completions_at("!", 1, &known).contains(&Completion::Identifier("a".to_string())));
+    assert!(completions_at("a and not ", 10, &known).contains(&Completion::Identifier("age".to_string())));
+    assert!(completions_at("name == 'a", 10, &known).is_empty());
+    assert!(completions_at("`order total", 12, &known).is_empty());
+    assert!(completions_at("`order total` ", 14, &known).contains(&Completion::Operator("==".to_string())));
+    assert!(completions_at("a /* b", 6, &known).is_empty());
+    assert!(completions_at("a # b", 5, &known).is_empty());
+    assert!(completions_at("a", 2, &known).is_empty());
+}
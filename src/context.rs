@@ -0,0 +1,160 @@
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap, sync::Arc};
+
+use crate::{Context, ContextValue};
+
+/// A source of context values which are looked up by their identifier.
+/// Implement it to evaluate against your own data instead of copying it into a Context first.
+/// Values which are stored as ContextValue can be borrowed, others are computed and returned owned.
+///
+/// # Examples
+/// ```rust
+/// use std::borrow::Cow;
+/// use logical_expr::{evaluate, ContextProvider, ContextValue};
+///
+/// struct Sensor {
+///     temperature: f64,
+/// }
+///
+/// impl ContextProvider for Sensor {
+///     fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+///         match key {
+///             "temperature" => Some(Cow::Owned(ContextValue::Float(self.temperature))),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// assert_eq!(evaluate("temperature > 20.5", &Sensor { temperature: 21.0 }), Ok(true));
+/// ```
+pub trait ContextProvider {
+    /// Returns the value of the identifier or None if it is unknown.
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>>;
+}
+
+impl ContextProvider for Context {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        Context::get(self, key).map(Cow::Borrowed)
+    }
+}
+
+impl ContextProvider for BTreeMap<String, ContextValue> {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        BTreeMap::get(self, key).map(Cow::Borrowed)
+    }
+}
+
+/// Looks up the key by a linear scan, which is faster than hashing for a handful of values.
+/// The first pair with the key wins.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{evaluate, ContextValue};
+///
+/// let context: &[(&str, ContextValue)] = &[("a", ContextValue::Boolean(true)), ("b", ContextValue::Integer(2))];
+/// assert_eq!(evaluate("a && b > 1", &context), Ok(true));
+/// ```
+impl ContextProvider for &[(&str, ContextValue)] {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        self.iter().find(|(name, _)| *name == key).map(|(_, value)| Cow::Borrowed(value))
+    }
+}
+
+/// The values of all identifiers a CompiledExpression references, extracted from a context once.
+/// The values are stored by the position of their identifier, so evaluating against it
+/// only searches the few referenced identifiers instead of hashing into the whole context.
+/// Create it with CompiledExpression::resolve().
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedContext {
+    pub(crate) identifiers: Arc<[String]>,
+    pub(crate) values: Vec<Option<ContextValue>>,
+}
+
+impl ResolvedContext {
+    /// Extracts the values of the sorted identifiers from the context.
+    pub(crate) fn new(identifiers: Arc<[String]>, context: &dyn ContextProvider) -> Self {
+        let values = identifiers.iter().map(|identifier| context.get(identifier).map(Cow::into_owned)).collect();
+        ResolvedContext { identifiers, values }
+    }
+}
+
+impl ContextProvider for ResolvedContext {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        let position = self.identifiers.binary_search_by(|identifier| identifier.as_str().cmp(key)).ok()?;
+        self.values[position].as_ref().map(Cow::Borrowed)
+    }
+}
+
+/// Resolves identifiers by calling a closure whenever the evaluation needs them.
+pub(crate) struct FnContext<F>(pub(crate) F);
+
+impl<F: Fn(&str) -> Option<ContextValue>> ContextProvider for FnContext<F> {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        (self.0)(key).map(Cow::Owned)
+    }
+}
+
+/// Records every value looked up in the wrapped context, each identifier only once in the order of the first lookup.
+pub(crate) struct RecordingContext<'a> {
+    pub(crate) context: &'a dyn ContextProvider,
+    pub(crate) inputs: RefCell<Vec<(String, ContextValue)>>,
+}
+
+impl<'a> RecordingContext<'a> {
+    pub(crate) fn new(context: &'a dyn ContextProvider) -> Self {
+        RecordingContext { context, inputs: RefCell::new(Vec::new()) }
+    }
+}
+
+impl ContextProvider for RecordingContext<'_> {
+    fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+        let value = self.context.get(key)?;
+        let mut inputs = self.inputs.borrow_mut();
+        if !inputs.iter().any(|(name, _)| name == key) {
+            inputs.push((key.to_string(), value.clone().into_owned()));
+        }
+        Some(value)
+    }
+}
+
+#[test]
+fn test_resolved_context() {
+    let mut context = Context::new();
+    context.insert("a".to_string(), ContextValue::Integer(1));
+    context.insert("b".to_string(), ContextValue::Boolean(true));
+    context.insert("unused".to_string(), ContextValue::Integer(2));
+    let identifiers: Arc<[String]> = vec!["a".to_string(), "b".to_string(), "missing".to_string()].into();
+    let resolved = ResolvedContext::new(identifiers, &context);
+    assert_eq!(resolved.values, vec![Some(ContextValue::Integer(1)), Some(ContextValue::Boolean(true)), None]);
+    assert_eq!(ContextProvider::get(&resolved, "b"), Some(Cow::Borrowed(&ContextValue::Boolean(true))));
+    assert_eq!(ContextProvider::get(&resolved, "missing"), None);
+    assert_eq!(ContextProvider::get(&resolved, "unused"), None);
+}
+
+#[test]
+fn test_slice_provider() {
+    let pairs: &[(&str, ContextValue)] = &[("a", ContextValue::Boolean(true)), ("b", ContextValue::Boolean(false)), ("a", ContextValue::Boolean(false))];
+    let map: Context = [("a", true), ("b", false)].into_iter().map(|(name, value)| (name.to_string(), ContextValue::Boolean(value))).collect();
+    for expression in ["a && b", "a || b", "a && !b"] {
+        assert_eq!(crate::evaluate(expression, &pairs), crate::evaluate(expression, &map));
+    }
+    assert!(matches!(ContextProvider::get(&pairs, "a"), Some(Cow::Borrowed(ContextValue::Boolean(true)))));
+    assert_eq!(ContextProvider::get(&pairs, "c"), None);
+}
+
+#[test]
+fn test_btree_map_provider() {
+    let mut context = BTreeMap::new();
+    context.insert("a".to_string(), ContextValue::Integer(1));
+    assert_eq!(crate::evaluate("a == 1", &context), Ok(true));
+    assert_eq!(ContextProvider::get(&context, "b"), None);
+    assert!(matches!(ContextProvider::get(&context, "a"), Some(Cow::Borrowed(ContextValue::Integer(1)))));
+}
+
+#[test]
+fn test_owned_values() {
+    let map = ContextValue::Map([("b".to_string(), ContextValue::Integer(2)), ("a".to_string(), ContextValue::String("x".to_string()))].into());
+    let values = [map, ContextValue::List(vec![ContextValue::Float(0.5)]), ContextValue::DateTime(0)];
+    for value in values {
+        assert_eq!(crate::Value::from(&value), crate::Value::from(Cow::Owned(value.clone())));
+    }
+}
@@ -0,0 +1,118 @@
+/// Parses an RFC 3339 timestamp like `2024-01-01T12:30:00Z` or `2024-01-01T12:30:00.5+02:00`,
+/// or a date like `2024-01-01` which means midnight UTC, into seconds since the unix epoch.
+/// Fractions of a second are truncated.
+pub(crate) fn parse_datetime(input: &str) -> Option<i64> {
+    let bytes = input.as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = input.get(range)?;
+        digits.bytes().all(|b| b.is_ascii_digit()).then(|| digits.parse().ok())?
+    };
+    if bytes.len() < 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let date = days_from_civil(year, month, day) * 86_400;
+    if bytes.len() == 10 {
+        return Some(date);
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') || bytes.len() < 20 || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // 60 is a leap second, which is counted like the first second of the next minute
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let mut rest = &input[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let start = input.len() - rest.len();
+            let (hours, minutes) = (number(start + 1..start + 3)?, number(start + 4..start + 6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'+' { offset } else { -offset }
+        }
+        _ => return None,
+    };
+    Some(date + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Formats seconds since the unix epoch as an RFC 3339 timestamp in UTC, e.g. `2024-01-01T00:00:00Z`.
+pub(crate) fn format_datetime(timestamp: i64) -> String {
+    let (days, seconds) = (timestamp.div_euclid(86_400), timestamp.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The days since 1970-01-01 of a date in the proleptic Gregorian calendar, see
+/// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of days_from_civil().
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[test]
+fn test_parse_datetime() {
+    assert_eq!(parse_datetime("1970-01-01"), Some(0));
+    assert_eq!(parse_datetime("2024-01-01"), Some(1_704_067_200));
+    assert_eq!(parse_datetime("2024-01-01T00:00:00Z"), Some(1_704_067_200));
+    assert_eq!(parse_datetime("2024-01-01t01:30:00.999+01:30"), Some(1_704_067_200));
+    assert_eq!(parse_datetime("1969-12-31T23:59:59-00:00"), Some(-1));
+    assert_eq!(parse_datetime("2024-02-29"), Some(1_709_164_800));
+    assert_eq!(parse_datetime("2023-02-29"), None);
+    assert_eq!(parse_datetime("2024-13-01"), None);
+    assert_eq!(parse_datetime("2024-01-01T00:00:00"), None);
+    assert_eq!(parse_datetime("2024-01-01T00:00:00.Z"), None);
+    assert_eq!(parse_datetime("2024-1-01"), None);
+    assert_eq!(parse_datetime("yesterday"), None);
+}
+
+#[test]
+fn test_format_datetime() {
+    assert_eq!(format_datetime(0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_datetime(-1), "1969-12-31T23:59:59Z");
+    assert_eq!(format_datetime(1_709_164_800 + 3661), "2024-02-29T01:01:01Z");
+    for timestamp in [-86_400 * 1000, -1, 0, 951_782_400, 1_704_067_200, 4_102_444_800] {
+        assert_eq!(parse_datetime(&format_datetime(timestamp)), Some(timestamp));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{CompiledExpression, CompilerContext, ContextProvider, EvalError, EvalOptions};
+
+/// A table of rules where every row holds one condition per column.
+/// All conditions are compiled with one CompilerContext, so rows repeating a regex share it.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, DecisionTable};
+///
+/// let mut table = DecisionTable::new();
+/// table.add_row(["age < 18", "country == 'CH'"]).unwrap();
+/// table.add_row(["age >= 18", "country =~ '^(CH|DE)$'"]).unwrap();
+///
+/// let mut context = Context::new();
+/// context.insert("age".to_string(), ContextValue::Integer(30));
+/// context.insert("country".to_string(), ContextValue::String("DE".to_string()));
+/// assert_eq!(table.evaluate(&context), Ok(Some(1)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DecisionTable {
+    compiler: CompilerContext,
+    rows: Vec<Vec<CompiledExpression>>,
+}
+
+impl DecisionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles the conditions of a row and appends it, returns the index of the new row.
+    /// Nothing is added if one of the conditions does not compile.
+    pub fn add_row<I, S>(&mut self, conditions: I) -> Result<usize, EvalError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let row = conditions.into_iter()
+            .map(|condition| self.compiler.compile(condition.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.rows.push(row);
+        Ok(self.rows.len() - 1)
+    }
+
+    /// The number of rows of the table.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the index of the first row whose conditions all hold.
+    /// The rows are checked in order and the remaining conditions of a row are skipped once one fails.
+    pub fn evaluate(&self, context: &impl ContextProvider) -> Result<Option<usize>, EvalError> {
+        self.evaluate_with_options(context, &EvalOptions::default())
+    }
+
+    pub fn evaluate_with_options(&self, context: &impl ContextProvider, options: &EvalOptions) -> Result<Option<usize>, EvalError> {
+        for (index, row) in self.rows.iter().enumerate() {
+            if Self::matches(row, context, options)? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    fn matches(row: &[CompiledExpression], context: &impl ContextProvider, options: &EvalOptions) -> Result<bool, EvalError> {
+        for condition in row {
+            if !condition.evaluate_with_options(context, options)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Context, ContextValue};
+
+    fn table() -> DecisionTable {
+        let mut table = DecisionTable::new();
+        table.add_row(["score >= 90", "name =~ '^[A-Z]'"]).unwrap();
+        table.add_row(["score >= 50", "name =~ '^[A-Z]'"]).unwrap();
+        table.add_row(["score >= 50", "true"]).unwrap();
+        table
+    }
+
+    #[test]
+    fn test_first_matching_row() {
+        let table = table();
+        let mut context = Context::new();
+        for (score, name, expected) in [(95, "Ada", Some(0)), (60, "Ada", Some(1)), (95, "ada", Some(2)), (10, "Ada", None)] {
+            context.insert("score".to_string(), ContextValue::Integer(score));
+            context.insert("name".to_string(), ContextValue::String(name.to_string()));
+            assert_eq!(table.evaluate(&context), Ok(expected));
+        }
+        assert_eq!(table.compiler.export_cache(), vec!["^[A-Z]".to_string()]);
+    }
+
+    #[test]
+    fn test_add_row_errors() {
+        let mut table = table();
+        assert!(matches!(table.add_row(["score >= 50", "name =~ '('"]), Err(EvalError::InvalidRegex(_))));
+        assert_eq!(table.len(), 3);
+        let mut context = Context::new();
+        context.insert("score".to_string(), ContextValue::Integer(95));
+        assert!(matches!(table.evaluate(&context), Err(EvalError::UnknownIdentifier(_))));
+    }
+}
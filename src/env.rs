@@ -0,0 +1,77 @@
+use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{context::ContextProvider, error::message, function, EvalError, EvalOptions, FunctionRegistry, Identifier, Value};
+
+/// Everything besides the expression itself which is needed during evaluation.
+pub(crate) struct EvalEnv<'a> {
+    pub(crate) context: &'a dyn ContextProvider,
+    pub(crate) options: &'a EvalOptions,
+    pub(crate) regexes: Option<&'a HashMap<String, Regex>>,
+    /// The size limit the precompiled regexes were compiled with.
+    pub(crate) regex_size_limit: Option<usize>,
+    pub(crate) functions: Option<&'a FunctionRegistry>,
+}
+
+impl<'a> EvalEnv<'a> {
+    pub(crate) fn new(context: &'a dyn ContextProvider, options: &'a EvalOptions) -> Self {
+        EvalEnv { context, options, regexes: None, regex_size_limit: None, functions: None }
+    }
+
+    /// Returns the regex of EvalOptions::regex_provider or the precompiled regex for the pattern, or compiles it on the fly.
+    /// With EvalOptions::regex_size_limit the precompiled regexes are only used if they were compiled with at most
+    /// that limit, otherwise the pattern is compiled with the limit.
+    pub(crate) fn regex(&self, pattern: &str) -> Result<Regex, EvalError> {
+        if let Some(regex) = self.options.regex_provider.as_ref().and_then(|provider| provider.get(pattern)) {
+            return Ok(regex);
+        }
+        let within_limit = match (self.options.regex_size_limit, self.regex_size_limit) {
+            (None, _) => true,
+            (Some(limit), compiled) => compiled.is_some_and(|compiled| compiled <= limit),
+        };
+        if let Some(regex) = self.regexes.filter(|_| within_limit).and_then(|regexes| regexes.get(pattern)) {
+            return Ok(regex.clone());
+        }
+        match self.options.regex_size_limit {
+            Some(size_limit) => compile_regex_with_limit(pattern, size_limit),
+            None => compile_regex(pattern),
+        }
+    }
+
+    /// The current time in seconds since the unix epoch, EvalOptions::now if it is set.
+    pub(crate) fn now(&self) -> i64 {
+        match self.options.now {
+            Some(now) => now,
+            None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64),
+        }
+    }
+
+    /// Calls the registered function with the name or the builtin one if none is registered.
+    pub(crate) fn call(&self, name: &Identifier, args: Vec<Value>) -> Result<Value, EvalError> {
+        match self.functions.and_then(|functions| functions.call(name, &args, self.options.catch_panics)) {
+            Some(result) => result,
+            None => function::call(name, args, self),
+        }
+    }
+
+    /// Applies the custom operator registered with the name to the resolved operands.
+    pub(crate) fn call_operator(&self, name: &str, lhs: &Value, rhs: &Value) -> Result<bool, EvalError> {
+        match self.functions.and_then(|functions| functions.call_operator(name, lhs, rhs, self.options.catch_panics)) {
+            Some(result) => result,
+            None => Err(EvalError::InvalidOperator(message(|| format!("Operator not found: {}", name)))),
+        }
+    }
+}
+
+pub(crate) fn compile_regex(pattern: &str) -> Result<Regex, EvalError> {
+    Regex::new(pattern).map_err(|_| EvalError::InvalidRegex(message(|| format!("Invalid regex: {}", pattern))))
+}
+
+/// Compiles the pattern like compile_regex() but fails if the compiled regex is larger than size_limit bytes.
+pub(crate) fn compile_regex_with_limit(pattern: &str, size_limit: usize) -> Result<Regex, EvalError> {
+    RegexBuilder::new(pattern).size_limit(size_limit).build().map_err(|err| match err {
+        regex::Error::CompiledTooBig(limit) => EvalError::InvalidRegex(message(|| format!("Regex exceeds the size limit of {} bytes: {}", limit, pattern))),
+        _ => EvalError::InvalidRegex(message(|| format!("Invalid regex: {}", pattern))),
+    })
+}
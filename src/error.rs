@@ -0,0 +1,137 @@
+use std::{cell::Cell, fmt};
+
+/// This is an enum containing the errors which can occur while parsing or evaluating an expression.
+/// Every variant carries a human readable message, the name of the failing function or the position of unexpected input.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    /// The expression could not be parsed.
+    Parse(String),
+    /// The start of the expression was parsed, but it is followed by input which does not belong to it.
+    /// The position is the byte offset of that input, the parsed prefix is the input before it.
+    UnexpectedInput { position: usize, message: String },
+    /// An identifier could not be found in the context.
+    UnknownIdentifier(String),
+    /// An operand has the wrong type for the operation.
+    TypeMismatch(String),
+    /// An operator was applied to operands it does not support.
+    InvalidOperator(String),
+    /// A regular expression could not be compiled.
+    InvalidRegex(String),
+    /// The evaluation options can not be applied.
+    InvalidOption(String),
+    /// A list was indexed outside of its bounds.
+    IndexOutOfBounds(String),
+    /// The expression exceeds the configured depth or size limits.
+    TooComplex(String),
+    /// An integer was divided by zero.
+    DivisionByZero(String),
+    /// The result of an integer or decimal calculation does not fit into its type.
+    Overflow(String),
+    /// A registered function panicked while EvalOptions::catch_panics was set.
+    FunctionPanic { name: String },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Parse(msg)
+            | EvalError::UnknownIdentifier(msg)
+            | EvalError::TypeMismatch(msg)
+            | EvalError::InvalidOperator(msg)
+            | EvalError::InvalidRegex(msg)
+            | EvalError::InvalidOption(msg)
+            | EvalError::IndexOutOfBounds(msg)
+            | EvalError::TooComplex(msg)
+            | EvalError::DivisionByZero(msg)
+            | EvalError::Overflow(msg) => write!(f, "{}", msg),
+            EvalError::FunctionPanic { name } => write!(f, "Function {} panicked", name),
+            EvalError::UnexpectedInput { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// The kind of an EvalError without its message, it is cheap to copy and compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EvalErrorCode {
+    Parse,
+    UnexpectedInput,
+    UnknownIdentifier,
+    TypeMismatch,
+    InvalidOperator,
+    InvalidRegex,
+    InvalidOption,
+    IndexOutOfBounds,
+    TooComplex,
+    DivisionByZero,
+    Overflow,
+    FunctionPanic,
+}
+
+impl EvalError {
+    /// The kind of the error.
+    pub fn code(&self) -> EvalErrorCode {
+        match self {
+            EvalError::Parse(_) => EvalErrorCode::Parse,
+            EvalError::UnexpectedInput { .. } => EvalErrorCode::UnexpectedInput,
+            EvalError::UnknownIdentifier(_) => EvalErrorCode::UnknownIdentifier,
+            EvalError::TypeMismatch(_) => EvalErrorCode::TypeMismatch,
+            EvalError::InvalidOperator(_) => EvalErrorCode::InvalidOperator,
+            EvalError::InvalidRegex(_) => EvalErrorCode::InvalidRegex,
+            EvalError::InvalidOption(_) => EvalErrorCode::InvalidOption,
+            EvalError::IndexOutOfBounds(_) => EvalErrorCode::IndexOutOfBounds,
+            EvalError::TooComplex(_) => EvalErrorCode::TooComplex,
+            EvalError::DivisionByZero(_) => EvalErrorCode::DivisionByZero,
+            EvalError::Overflow(_) => EvalErrorCode::Overflow,
+            EvalError::FunctionPanic { .. } => EvalErrorCode::FunctionPanic,
+        }
+    }
+}
+
+thread_local! {
+    /// Whether errors get their message, evaluate_fast() only reads their code.
+    static MESSAGES: Cell<bool> = const { Cell::new(true) };
+}
+
+/// The message of an error, it is only built if the caller reads it, see without_messages().
+pub(crate) fn message(build: impl FnOnce() -> String) -> String {
+    if MESSAGES.with(Cell::get) {
+        build()
+    } else {
+        String::new()
+    }
+}
+
+/// Runs the evaluation with errors which only carry their code, their messages stay empty.
+pub(crate) fn without_messages<T>(evaluate: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            MESSAGES.with(|messages| messages.set(self.0));
+        }
+    }
+    // restored even if a registered function panics through the evaluation
+    let _restore = Restore(MESSAGES.with(|messages| messages.replace(false)));
+    evaluate()
+}
+
+#[test]
+fn test_code() {
+    assert_eq!(EvalError::Overflow("Integer overflow".to_string()).code(), EvalErrorCode::Overflow);
+    assert_eq!(EvalError::FunctionPanic { name: "f".to_string() }.code(), EvalErrorCode::FunctionPanic);
+}
+
+#[test]
+fn test_without_messages() {
+    assert_eq!(without_messages(|| message(|| unreachable!("the message is not built"))), "");
+    assert_eq!(message(|| "built".to_string()), "built");
+    assert!(std::panic::catch_unwind(|| without_messages(|| panic!("function panicked"))).is_err());
+    assert_eq!(message(|| "built".to_string()), "built");
+}
+
+#[test]
+fn test_display() {
+    let err = EvalError::UnknownIdentifier("Identifier not found in context: foo".to_string());
+    assert_eq!(err.to_string(), "Identifier not found in context: foo");
+}
@@ -1,67 +1,735 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, cell::Cell, collections::HashMap, fmt};
 
-use nom::{branch::alt, character::complete::{char, multispace0}, combinator::{map, map_res}, sequence::{delimited, tuple}, IResult};
+use nom::{branch::alt, bytes::complete::tag, character::complete::{char, multispace0, multispace1}, combinator::{map, map_res}, error::ErrorKind, sequence::{delimited, tuple}, IResult};
 
-use crate::{operator::{binary_and_operator, binary_or_operator, unary_operator_primary, BinaryOperator, UnaryOperator}, value::*, ContextValue, non_boolean_expression::{binary_non_bool, NonBooleanExpression}};
+use crate::{operator::{binary_and_operator, binary_implies_operator, binary_operator_equality, binary_or_operator, binary_xor_operator, predicate, time_window, unary_operator_primary, BinaryOperator, Predicate, TimeWindow, UnaryOperator}, value::*, env::compile_regex, error::message, Context, ContextValue, EvalError, EvalEnv, EvalOptions, non_boolean_expression::{binary_non_bool, membership, quantified, NonBooleanExpression}, schema::{infer_types, validate}, ValueType};
 
-#[derive(Debug, PartialEq, PartialOrd)]
-pub(crate) enum BooleanExpression {
+/// The syntax tree of a boolean expression as produced by parse().
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BooleanExpression {
   Identifier(Identifier),
   Boolean(bool),
   NonBooleanExpression(NonBooleanExpression),
+  /// Two operands compared with `==` or `!=`. Created by hand it may also join them with `&&`, `||`, `^^` or `->`.
   Binary(Box<BooleanExpression>, BinaryOperator, Box<BooleanExpression>),
+  /// Operands joined by the same `&&`, `||`, `^^` or `->` like `a && b && c`, parse() produces it for every chain.
+  /// The operands are a list instead of a nested tree, so long chains don't count towards the maximum depth.
+  /// Like the operators themselves the chain is joined to the right, `a -> b -> c` is `a -> (b -> c)`.
+  Chain(BinaryOperator, Vec<BooleanExpression>),
   Unary(UnaryOperator, Box<BooleanExpression>),
+  Predicate(Value, Predicate),
+  /// `value between lower and upper`, the bounds are inclusive.
+  Between(Value, Value, Value),
+  /// `timestamp within_last 7d`, the timestamp is an integer of seconds since the unix epoch.
+  Within(Value, TimeWindow),
+  /// Parentheses written around the expression, only parse_with_groups() keeps them. They are transparent to evaluation.
+  Group(Box<BooleanExpression>),
 }
+/// Parses the expression and checks that its regex literals compile.
 impl TryFrom <&str> for BooleanExpression {
   fn try_from(value: &str) -> Result<Self, Self::Error> {
-    parse_whole_boolean_expression(value).map_err(|e| format!("{:?}", e))
+    check_regexes(parse_whole_boolean_expression(value)?)
   }
-  type Error = String;
+  type Error = EvalError;
+}
+/// Parses the bytes like TryFrom<&str>, they have to be valid UTF-8.
+impl TryFrom <&[u8]> for BooleanExpression {
+  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+    let value = std::str::from_utf8(value).map_err(|err| EvalError::Parse(format!("Expression should be valid UTF-8: {}", err)))?;
+    BooleanExpression::try_from(value)
+  }
+  type Error = EvalError;
+}
+/// Parses the expression like TryFrom<&str>.
+impl TryFrom <String> for BooleanExpression {
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    BooleanExpression::try_from(value.as_str())
+  }
+  type Error = EvalError;
 }
 impl BooleanExpression {
-  pub(crate) fn evaluate(&self) -> Result<bool, String> {
+  pub(crate) fn evaluate(&self, env: &EvalEnv) -> Result<bool, EvalError> {
     match self {
       BooleanExpression::Boolean(b) => Ok(*b),
-      BooleanExpression::Identifier(ident) => Err(format!("Context should be used before evaluation: {:?}", ident)),
-      BooleanExpression::NonBooleanExpression(nbe) => nbe.evaluate(),
-      BooleanExpression::Binary(lhs, op, rhs) => self.evaluate_binary(lhs, op, rhs),
-      BooleanExpression::Unary(op, rhs) => self.evaluate_unary(op, rhs),
+      BooleanExpression::Identifier(ident) => match ident.use_context(env.context)? {
+        Value::Boolean(b) => Ok(b),
+        _ => Err(EvalError::TypeMismatch(message(|| format!("Value should be a boolean: {:?}", ident)))),
+      },
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.evaluate(env),
+      BooleanExpression::Binary(lhs, op, rhs) => evaluate_binary(lhs, op, rhs, |operand| operand.evaluate(env)),
+      BooleanExpression::Chain(op, operands) => evaluate_chain(op, operands, |operand| operand.evaluate(env)),
+      BooleanExpression::Unary(op, rhs) => self.evaluate_unary(op, rhs, env),
+      BooleanExpression::Predicate(value, predicate) => Self::evaluate_predicate(value, predicate, env),
+      BooleanExpression::Between(value, lower, upper) => Self::evaluate_between(value, lower, upper, env),
+      BooleanExpression::Within(value, window) => Self::evaluate_within(value, window, env),
+      BooleanExpression::Group(value) => value.evaluate(env),
     }
   }
-  fn evaluate_binary(&self, lhs: &BooleanExpression, op: &BinaryOperator, rhs: &BooleanExpression) -> Result<bool, String> {
-    match op {
-      BinaryOperator::And => Ok(lhs.evaluate()? && rhs.evaluate()?),
-      BinaryOperator::Or => Ok(lhs.evaluate()? || rhs.evaluate()?),
-      _ => Err(format!("Invalid binary operator for boolean: {:?}", op))
+  fn evaluate_predicate(value: &Value, predicate: &Predicate, env: &EvalEnv) -> Result<bool, EvalError> {
+    match (value.clone().resolve(env)?, predicate) {
+      (Value::IntegerLiteral(i), Predicate::Positive) => Ok(i > 0),
+      (Value::IntegerLiteral(i), Predicate::Negative) => Ok(i < 0),
+      (Value::IntegerLiteral(i), Predicate::Zero) => Ok(i == 0),
+      (Value::FloatLiteral(f), Predicate::Positive) => Ok(f > 0.0),
+      (Value::FloatLiteral(f), Predicate::Negative) => Ok(f < 0.0),
+      (Value::FloatLiteral(f), Predicate::Zero) => Ok(f == 0.0),
+      (value, _) => Err(EvalError::TypeMismatch(message(|| format!("Value should be a number: {:?}", value)))),
     }
   }
-  fn evaluate_unary(&self, op: &UnaryOperator, rhs: &BooleanExpression) -> Result<bool, String> {
+  /// Compares the value with both bounds, integers are promoted to floats if any of the values is a float.
+  /// An inverted range like `between 65 and 18` contains no value and is always false.
+  fn evaluate_between(value: &Value, lower: &Value, upper: &Value, env: &EvalEnv) -> Result<bool, EvalError> {
+    let mut values = (value.clone().resolve(env)?, lower.clone().resolve(env)?, upper.clone().resolve(env)?);
+    if env.options.coerce_strings {
+      let (value, lower) = coerce_numbers(values.0, values.1)?;
+      let (value, upper) = coerce_numbers(value, values.2)?;
+      values = (value, lower, upper);
+    }
+    match values {
+      (Value::IntegerLiteral(v), Value::IntegerLiteral(l), Value::IntegerLiteral(u)) => Ok(l <= v && v <= u),
+      values => match (as_float(&values.0), as_float(&values.1), as_float(&values.2)) {
+        (Some(v), Some(l), Some(u)) => Ok(l <= v && v <= u),
+        _ => Err(EvalError::TypeMismatch(message(|| format!("Values should be numbers: {:?} between {:?} and {:?}", values.0, values.1, values.2)))),
+      },
+    }
+  }
+  /// Checks that the timestamp lies in the window around EvalOptions::now or the system time if it isn't set.
+  fn evaluate_within(value: &Value, window: &TimeWindow, env: &EvalEnv) -> Result<bool, EvalError> {
+    let (Value::IntegerLiteral(timestamp) | Value::DateTime(timestamp)) = value.clone().resolve(env)? else {
+      return Err(EvalError::TypeMismatch(message(|| format!("Timestamp should be a datetime or an integer of seconds since the unix epoch: {}", value))));
+    };
+    let now = env.now();
+    // a duration beyond i64::MAX seconds reaches as far as any timestamp
+    let seconds = |duration: &std::time::Duration| i64::try_from(duration.as_secs()).unwrap_or(i64::MAX);
+    Ok(match window {
+      TimeWindow::Last(duration) => now.saturating_sub(seconds(duration)) <= timestamp && timestamp <= now,
+      TimeWindow::Next(duration) => now <= timestamp && timestamp <= now.saturating_add(seconds(duration)),
+    })
+  }
+  fn evaluate_unary(&self, op: &UnaryOperator, rhs: &BooleanExpression, env: &EvalEnv) -> Result<bool, EvalError> {
     match op {
-      UnaryOperator::Not => Ok(!rhs.evaluate()?),
-      _ => Err(format!("Invalid unary operator for boolean: {:?}", op))
+      UnaryOperator::Not => Ok(!rhs.evaluate(env)?),
+    }
+  }
+  /// Evaluates the expression like evaluate() and records the source and the result of every leaf, a condition
+  /// which isn't combined of other conditions by a boolean operator. Leaves skipped by short-circuiting are not recorded.
+  pub(crate) fn evaluate_trace(&self, env: &EvalEnv, trace: &mut Vec<(String, bool)>) -> Result<bool, EvalError> {
+    match self {
+      BooleanExpression::Binary(lhs, op, rhs) => evaluate_binary(lhs, op, rhs, |operand| operand.evaluate_trace(env, trace)),
+      BooleanExpression::Chain(op, operands) => evaluate_chain(op, operands, |operand| operand.evaluate_trace(env, trace)),
+      BooleanExpression::Unary(UnaryOperator::Not, value) => Ok(!value.evaluate_trace(env, trace)?),
+      BooleanExpression::Group(value) => value.evaluate_trace(env, trace),
+      leaf => {
+        let result = leaf.evaluate(env)?;
+        trace.push((leaf.to_string(), result));
+        Ok(result)
+      }
     }
   }
   
-  pub(crate) fn use_context(self, context: &HashMap<String, ContextValue>) -> Result<Self, String> {
+  /// Evaluates the expression as fuzzy logic with truth values between 0 and 1.
+  /// Identifiers may resolve to a boolean or a float between 0 and 1, comparisons stay crisp.
+  pub(crate) fn evaluate_fuzzy(&self, env: &EvalEnv) -> Result<f64, EvalError> {
+    match self {
+      BooleanExpression::Boolean(b) => Ok(if *b { 1.0 } else { 0.0 }),
+      BooleanExpression::Identifier(ident) => match ident.use_context(env.context)? {
+        Value::Boolean(b) => Ok(if b { 1.0 } else { 0.0 }),
+        Value::FloatLiteral(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err(EvalError::TypeMismatch(message(|| format!("Value should be a boolean or a float between 0 and 1: {:?}", ident)))),
+      },
+      BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) => Ok(if self.evaluate(env)? { 1.0 } else { 0.0 }),
+      BooleanExpression::Binary(lhs, op, rhs) => {
+        let lhs = lhs.evaluate_fuzzy(env)?;
+        // the right side is skipped if the left one decides the result, like in the crisp evaluation
+        match op {
+          BinaryOperator::And if lhs == 0.0 => return Ok(0.0),
+          BinaryOperator::Or if lhs == 1.0 => return Ok(1.0),
+          BinaryOperator::Implies if lhs == 0.0 => return Ok(1.0),
+          _ => {}
+        }
+        let rhs = rhs.evaluate_fuzzy(env)?;
+        match op {
+          BinaryOperator::And => Ok(lhs.min(rhs)),
+          BinaryOperator::Or => Ok(lhs.max(rhs)),
+          BinaryOperator::Xor => Ok(lhs.min(1.0 - rhs).max((1.0 - lhs).min(rhs))),
+          BinaryOperator::Implies => Ok((1.0 - lhs).max(rhs)),
+          BinaryOperator::Equals => Ok(1.0 - (lhs - rhs).abs()),
+          BinaryOperator::NotEquals => Ok((lhs - rhs).abs()),
+          // false is less than true like in the crisp evaluation
+          BinaryOperator::LessThan => Ok((1.0 - lhs).min(rhs)),
+          BinaryOperator::GreaterThan => Ok(lhs.min(1.0 - rhs)),
+          BinaryOperator::LessEqual => Ok((1.0 - lhs).max(rhs)),
+          BinaryOperator::GreaterEqual => Ok(lhs.max(1.0 - rhs)),
+          _ => Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for boolean: {:?}", op))))
+        }
+      }
+      BooleanExpression::Chain(BinaryOperator::Xor, operands) => {
+        let values = operands.iter().map(|operand| operand.evaluate_fuzzy(env)).collect::<Result<Vec<f64>, EvalError>>()?;
+        // joined to the right like the crisp chain
+        Ok(values.into_iter().rev().reduce(|rhs, lhs| lhs.min(1.0 - rhs).max((1.0 - lhs).min(rhs))).unwrap_or(0.0))
+      }
+      BooleanExpression::Chain(op, operands) => {
+        // a -> b -> c is a -> (b -> c), the maximum of the negated premises and the conclusion
+        let (mut value, decided, combine): (f64, f64, fn(f64, f64) -> f64) = match op {
+          BinaryOperator::And => (1.0, 0.0, f64::min),
+          BinaryOperator::Or | BinaryOperator::Implies => (0.0, 1.0, f64::max),
+          _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid chain operator: {:?}", op)))),
+        };
+        // stops at the value which decides the chain, like the crisp chain short-circuits
+        for (index, operand) in operands.iter().enumerate() {
+          let operand = operand.evaluate_fuzzy(env)?;
+          let premise = *op == BinaryOperator::Implies && index + 1 < operands.len();
+          value = combine(value, if premise { 1.0 - operand } else { operand });
+          if value == decided {
+            break;
+          }
+        }
+        Ok(value)
+      }
+      BooleanExpression::Unary(UnaryOperator::Not, value) => Ok(1.0 - value.evaluate_fuzzy(env)?),
+      BooleanExpression::Group(value) => value.evaluate_fuzzy(env),
+    }
+  }
+
+  /// The byte range in the parsed expression from the first to the last identifier of the node, see Identifier::span().
+  /// None if the node has no parsed identifiers, e.g. `true` or a node created instead of parsed.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::{parse, BooleanExpression};
+  ///
+  /// let BooleanExpression::Chain(_, operands) = parse("a && bar == 2").unwrap() else { panic!() };
+  /// assert_eq!(operands[1].span(), Some(5..8));
+  /// ```
+  pub fn span(&self) -> Option<std::ops::Range<usize>> {
+    covering_span(self.identifiers())
+  }
+
+  pub(crate) fn identifiers(&self) -> Vec<&Identifier> {
+    match self {
+      BooleanExpression::Identifier(ident) => vec![ident],
+      BooleanExpression::Boolean(_) => vec![],
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.identifiers(),
+      BooleanExpression::Predicate(value, _) => value.identifiers(),
+      BooleanExpression::Within(value, _) => value.identifiers(),
+      BooleanExpression::Between(value, lower, upper) => value.identifiers().into_iter().chain(lower.identifiers()).chain(upper.identifiers()).collect(),
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.identifiers().into_iter().chain(rhs.identifiers()).collect(),
+      BooleanExpression::Chain(_, operands) => operands.iter().flat_map(BooleanExpression::identifiers).collect(),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.identifiers(),
+    }
+  }
+
+  /// The string literals of the expression in their order, e.g. to audit the constants of a rule set.
+  /// The patterns of `=~` and `===~` are not included, see regex_literals().
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::parse;
+  ///
+  /// let expression = parse("role == 'admin' && email =~ '@example\\.com$'").unwrap();
+  /// assert_eq!(expression.string_literals(), vec!["admin"]);
+  /// assert_eq!(expression.regex_literals(), vec!["@example\\.com$"]);
+  /// ```
+  pub fn string_literals(&self) -> Vec<&str> {
+    self.literals(false)
+  }
+
+  /// The string literals used as the pattern of `=~` and `===~` in their order.
+  pub fn regex_literals(&self) -> Vec<&str> {
+    self.literals(true)
+  }
+
+  fn literals(&self, regexes: bool) -> Vec<&str> {
+    match self {
+      BooleanExpression::Identifier(_) | BooleanExpression::Boolean(_) => vec![],
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.literals(regexes),
+      // values can't contain a regex match
+      BooleanExpression::Predicate(..) | BooleanExpression::Within(..) | BooleanExpression::Between(..) if regexes => vec![],
+      BooleanExpression::Predicate(value, _) | BooleanExpression::Within(value, _) => value.string_literals(),
+      BooleanExpression::Between(value, lower, upper) => [value, lower, upper].into_iter().flat_map(Value::string_literals).collect(),
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.literals(regexes).into_iter().chain(rhs.literals(regexes)).collect(),
+      BooleanExpression::Chain(_, operands) => operands.iter().flat_map(|operand| operand.literals(regexes)).collect(),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.literals(regexes),
+    }
+  }
+
+  /// Whether an identifier with the name is used anywhere in the expression, e.g. to check if a rule
+  /// depends on a context value. The search stops at the first match.
+  pub fn contains_identifier(&self, name: &str) -> bool {
+    match self {
+      BooleanExpression::Identifier(ident) => ident.name() == name,
+      BooleanExpression::Boolean(_) => false,
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.contains_identifier(name),
+      BooleanExpression::Predicate(value, _) | BooleanExpression::Within(value, _) => value.contains_identifier(name),
+      BooleanExpression::Between(value, lower, upper) => [value, lower, upper].iter().any(|value| value.contains_identifier(name)),
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.contains_identifier(name) || rhs.contains_identifier(name),
+      BooleanExpression::Chain(_, operands) => operands.iter().any(|operand| operand.contains_identifier(name)),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.contains_identifier(name),
+    }
+  }
+
+  /// Evaluates the expression for every assignment of its boolean identifiers.
+  /// The identifiers are sorted by name, a row holds their values in this order and the result.
+  /// Rows count upwards from all false to all true, with the first identifier changing slowest.
+  /// Comparisons are evaluated as written, so they may only use literals.
+  /// Expressions with more than 16 boolean identifiers are rejected with EvalError::TooComplex.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::parse;
+  ///
+  /// let table = parse("a -> b").unwrap().truth_table().unwrap();
+  /// assert_eq!(table, vec![
+  ///     (vec![false, false], true),
+  ///     (vec![false, true], true),
+  ///     (vec![true, false], false),
+  ///     (vec![true, true], true),
+  /// ]);
+  /// ```
+  pub fn truth_table(&self) -> Result<Vec<(Vec<bool>, bool)>, EvalError> {
+    const MAX_VARIABLES: usize = 16;
+    let mut names: Vec<&str> = self.boolean_identifiers().iter().map(|ident| ident.name()).collect();
+    names.sort();
+    names.dedup();
+    if names.len() > MAX_VARIABLES {
+      return Err(EvalError::TooComplex(format!("Truth table for {} variables exceeds the limit of {}", names.len(), MAX_VARIABLES)));
+    }
+    let options = EvalOptions::default();
+    (0..1u32 << names.len()).map(|row| {
+      let assignment: Vec<bool> = (0..names.len()).map(|i| row & (1 << (names.len() - 1 - i)) != 0).collect();
+      let context: Context = names.iter().zip(&assignment).map(|(name, value)| (name.to_string(), ContextValue::Boolean(*value))).collect();
+      let value = self.evaluate(&EvalEnv::new(&context, &options))?;
+      Ok((assignment, value))
+    }).collect()
+  }
+
+  /// Folds sub-expressions without identifiers into boolean literals and removes literals which don't change the
+  /// result, e.g. `2 > 1 && x` becomes `x` and `false || x` becomes `x`. The result evaluates like the expression itself.
+  /// Comparisons whose result depends on the evaluation options, the clock or registered functions are kept,
+  /// as well as constant sub-expressions which fail to evaluate so the error is still reported.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::parse;
+  ///
+  /// assert_eq!(parse("2 > 1 && x").unwrap().simplify().to_string(), "x");
+  /// assert_eq!(parse("x || 1 + 1 == 2").unwrap().simplify().to_string(), "x || true");
+  /// ```
+  pub fn simplify(self) -> BooleanExpression {
+    match self {
+      BooleanExpression::Binary(lhs, op, rhs) => match (lhs.simplify(), op, rhs.simplify()) {
+        (BooleanExpression::Boolean(true), BinaryOperator::And | BinaryOperator::Implies, rhs) => rhs,
+        (BooleanExpression::Boolean(false), BinaryOperator::Or, rhs) => rhs,
+        (BooleanExpression::Boolean(b), BinaryOperator::And | BinaryOperator::Or, _) => BooleanExpression::Boolean(b),
+        (BooleanExpression::Boolean(false), BinaryOperator::Implies, _) => BooleanExpression::Boolean(true),
+        // the left side is evaluated first, so only literals on the right which keep its value can be dropped
+        (lhs, BinaryOperator::And, BooleanExpression::Boolean(true)) | (lhs, BinaryOperator::Or, BooleanExpression::Boolean(false)) => lhs,
+        (lhs, op, rhs) => BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs)).fold(),
+      },
+      BooleanExpression::Chain(op, operands) => simplify_chain(op, operands),
+      BooleanExpression::Unary(op, value) => BooleanExpression::Unary(op, Box::new(value.simplify())).fold(),
+      // the parentheses are dropped, the simplified expression is displayed with the parentheses it needs
+      BooleanExpression::Group(value) => value.simplify(),
+      expression => expression.fold(),
+    }
+  }
+
+  /// Replaces the expression with its value if it is constant and can be evaluated.
+  fn fold(self) -> BooleanExpression {
+    if !self.is_constant() {
+      return self;
+    }
+    let (context, options) = (Context::new(), EvalOptions::default());
+    match self.evaluate(&EvalEnv::new(&context, &options)) {
+      Ok(b) => BooleanExpression::Boolean(b),
+      Err(_) => self,
+    }
+  }
+
+  /// Infers the types of the identifiers from their usage, e.g. to build a form for the inputs of a rule.
+  /// An identifier compared with a literal has the type of the literal, one matched with `=~` is a string
+  /// and one used as an operand of `&&` is a boolean. Identifiers whose type the usage does not determine,
+  /// like both sides of `a < b` or the argument of a function, are left out.
+  /// Returns EvalError::TypeMismatch if an identifier is used as two different types.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::{parse, ValueType};
+  ///
+  /// let types = parse("age > 18 && name =~ 'a'").unwrap().infer_types().unwrap();
+  /// assert_eq!(types["age"], ValueType::Integer);
+  /// assert_eq!(types["name"], ValueType::String);
+  /// ```
+  pub fn infer_types(&self) -> Result<HashMap<String, ValueType>, EvalError> {
+    let mut types = HashMap::new();
+    infer_types(self, &mut types)?;
+    Ok(types)
+  }
+
+  /// Checks that every identifier is declared in the schema and every operator is applied to types it supports,
+  /// like CompiledExpression::compile_typed(). Instead of failing on the first problem the problems of all conditions
+  /// are returned, e.g. to list every mistake of a rule at once.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use std::collections::HashMap;
+  /// use logical_expr::{parse, ValueType};
+  ///
+  /// let schema = HashMap::from([("name".to_string(), ValueType::String)]);
+  /// let errors = parse("name < 5 && missing").unwrap().validate(&schema).unwrap_err();
+  /// assert_eq!(errors.len(), 2);
+  /// ```
+  pub fn validate(&self, schema: &HashMap<String, ValueType>) -> Result<(), Vec<EvalError>> {
+    let mut errors = vec![];
+    validate(self, schema, &mut errors);
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(errors)
+    }
+  }
+
+  /// Whether the expression evaluates to the same result regardless of the context and the options,
+  /// e.g. to precompute the outcome of a rule. Function calls and time windows are never constant.
+  pub fn is_constant(&self) -> bool {
+    match self {
+      BooleanExpression::Boolean(_) => true,
+      BooleanExpression::Identifier(_) | BooleanExpression::Within(..) => false,
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.is_constant(),
+      BooleanExpression::Predicate(value, _) => value.is_constant(),
+      BooleanExpression::Between(value, lower, upper) => value.is_constant() && lower.is_constant() && upper.is_constant(),
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.is_constant() && rhs.is_constant(),
+      BooleanExpression::Chain(_, operands) => operands.iter().all(BooleanExpression::is_constant),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.is_constant(),
+    }
+  }
+
+  /// Replaces the identifiers which are in the context with their values and simplifies the result,
+  /// e.g. to evaluate a rule in stages. Unlike evaluate(), identifiers missing from the context are kept.
+  /// The result displays as an expression which parses again, unless the context substitutes a NaN or infinite float.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::{parse, Context, ContextValue};
+  ///
+  /// let mut context = Context::new();
+  /// context.insert("age".to_string(), ContextValue::Integer(20));
+  /// assert_eq!(parse("age >= 18 && verified").unwrap().partial_eval(&context).unwrap().to_string(), "verified");
+  /// ```
+  pub fn partial_eval(self, context: &Context) -> Result<BooleanExpression, EvalError> {
+    let known = |ident: &Identifier| !matches!(ident.use_context(context), Err(EvalError::UnknownIdentifier(_)));
+    Ok(self.substitute(context, &known)?.simplify())
+  }
+
+  /// Replaces the identifiers for which known() returns true with their value from the context.
+  pub(crate) fn substitute(self, context: &Context, known: &dyn Fn(&Identifier) -> bool) -> Result<Self, EvalError> {
+    Ok(match self {
+      BooleanExpression::Identifier(ident) if known(&ident) => match ident.use_context(context)? {
+        Value::Boolean(b) => BooleanExpression::Boolean(b),
+        _ => return Err(EvalError::TypeMismatch(message(|| format!("Identifier should be a boolean: {}", ident)))),
+      },
+      BooleanExpression::NonBooleanExpression(nbe) => BooleanExpression::NonBooleanExpression(nbe.substitute(context, known)?),
+      BooleanExpression::Predicate(value, predicate) => BooleanExpression::Predicate(value.substitute(context, known)?, predicate),
+      BooleanExpression::Within(value, window) => BooleanExpression::Within(value.substitute(context, known)?, window),
+      BooleanExpression::Between(value, lower, upper) =>
+        BooleanExpression::Between(value.substitute(context, known)?, lower.substitute(context, known)?, upper.substitute(context, known)?),
+      BooleanExpression::Binary(lhs, op, rhs) =>
+        BooleanExpression::Binary(Box::new(lhs.substitute(context, known)?), op, Box::new(rhs.substitute(context, known)?)),
+      BooleanExpression::Chain(op, operands) =>
+        BooleanExpression::Chain(op, operands.into_iter().map(|operand| operand.substitute(context, known)).collect::<Result<_, _>>()?),
+      BooleanExpression::Unary(op, value) => BooleanExpression::Unary(op, Box::new(value.substitute(context, known)?)),
+      BooleanExpression::Group(value) => BooleanExpression::Group(Box::new(value.substitute(context, known)?)),
+      expression => expression,
+    })
+  }
+
+  /// Renames every identifier, e.g. to evaluate a rule against a context with differently named keys.
+  /// The closure gets the name of an identifier and returns its new name, the structure of the tree is kept.
+  ///
+  /// # Examples
+  /// ```rust
+  /// use logical_expr::parse;
+  ///
+  /// let renamed = parse("age > 18 && verified").unwrap().remap_identifiers(|name| format!("user.{}", name));
+  /// assert_eq!(renamed.to_string(), "user.age > 18 && user.verified");
+  /// ```
+  pub fn remap_identifiers(self, f: impl Fn(&str) -> String) -> Self {
+    self.rename_identifiers(&f)
+  }
+
+  fn rename_identifiers(self, f: &dyn Fn(&str) -> String) -> Self {
+    match self {
+      BooleanExpression::Identifier(ident) => BooleanExpression::Identifier(ident.rename(f)),
+      BooleanExpression::Boolean(b) => BooleanExpression::Boolean(b),
+      BooleanExpression::NonBooleanExpression(nbe) => BooleanExpression::NonBooleanExpression(nbe.rename_identifiers(f)),
+      BooleanExpression::Predicate(value, predicate) => BooleanExpression::Predicate(value.rename_identifiers(f), predicate),
+      BooleanExpression::Within(value, window) => BooleanExpression::Within(value.rename_identifiers(f), window),
+      BooleanExpression::Between(value, lower, upper) =>
+        BooleanExpression::Between(value.rename_identifiers(f), lower.rename_identifiers(f), upper.rename_identifiers(f)),
+      BooleanExpression::Binary(lhs, op, rhs) => BooleanExpression::Binary(Box::new(lhs.rename_identifiers(f)), op, Box::new(rhs.rename_identifiers(f))),
+      BooleanExpression::Chain(op, operands) => BooleanExpression::Chain(op, operands.into_iter().map(|operand| operand.rename_identifiers(f)).collect()),
+      BooleanExpression::Unary(op, value) => BooleanExpression::Unary(op, Box::new(value.rename_identifiers(f))),
+      BooleanExpression::Group(value) => BooleanExpression::Group(Box::new(value.rename_identifiers(f))),
+    }
+  }
+
+  /// The identifiers which are used as boolean values, comparisons are not included.
+  pub(crate) fn boolean_identifiers(&self) -> Vec<&Identifier> {
+    match self {
+      BooleanExpression::Identifier(ident) => vec![ident],
+      BooleanExpression::Boolean(_) | BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) => vec![],
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.boolean_identifiers().into_iter().chain(rhs.boolean_identifiers()).collect(),
+      BooleanExpression::Chain(_, operands) => operands.iter().flat_map(BooleanExpression::boolean_identifiers).collect(),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.boolean_identifiers(),
+    }
+  }
+
+  /// The number of nested levels of the tree, a single value has depth 1 and a chain adds one level for its operands.
+  pub(crate) fn depth(&self) -> usize {
     match self {
-        BooleanExpression::Identifier(ident) => {
-          if let Ok(Value::Boolean(b)) = ident.use_context(context) {
-            Ok(BooleanExpression::Boolean(b))
-          } else {
-            Err(format!("Value should be a boolean: {:?}", ident))
-          }},
-        BooleanExpression::Boolean(_) => Ok(self),
-        BooleanExpression::NonBooleanExpression(nbe) => Ok(BooleanExpression::NonBooleanExpression(nbe.use_context(context)?)), 
-        BooleanExpression::Binary(lhs, op, rhs) => Ok(BooleanExpression::Binary(Box::new(lhs.use_context(context)?), op, Box::new(rhs.use_context(context)?))),
-        BooleanExpression::Unary(op, value) => Ok(BooleanExpression::Unary(op, Box::new(value.use_context(context)?))),
+      BooleanExpression::Identifier(_) | BooleanExpression::Boolean(_) | BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) => 1,
+      BooleanExpression::Binary(lhs, _, rhs) => 1 + lhs.depth().max(rhs.depth()),
+      BooleanExpression::Chain(_, operands) => 1 + operands.iter().map(BooleanExpression::depth).max().unwrap_or(0),
+      BooleanExpression::Unary(_, value) => 1 + value.depth(),
+      BooleanExpression::Group(value) => value.depth(),
     }
   }
+
+  /// The number of nodes of the tree, a comparison counts as a single node and a chain of n operands like n - 1 binary nodes.
+  pub(crate) fn node_count(&self) -> usize {
+    match self {
+      BooleanExpression::Identifier(_) | BooleanExpression::Boolean(_) | BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) => 1,
+      BooleanExpression::Binary(lhs, _, rhs) => 1 + lhs.node_count() + rhs.node_count(),
+      BooleanExpression::Chain(_, operands) => operands.len().saturating_sub(1) + operands.iter().map(BooleanExpression::node_count).sum::<usize>(),
+      BooleanExpression::Unary(_, value) => 1 + value.node_count(),
+      BooleanExpression::Group(value) => value.node_count(),
+    }
+  }
+
+  pub(crate) fn regex_patterns(&self) -> Vec<String> {
+    match self {
+      BooleanExpression::Identifier(_) | BooleanExpression::Boolean(_) => vec![],
+      BooleanExpression::NonBooleanExpression(nbe) => nbe.regex_patterns(),
+      BooleanExpression::Predicate(value, _) | BooleanExpression::Within(value, _) => value.regex_patterns(),
+      BooleanExpression::Between(value, lower, upper) =>
+        value.regex_patterns().into_iter().chain(lower.regex_patterns()).chain(upper.regex_patterns()).collect(),
+      BooleanExpression::Binary(lhs, _, rhs) => lhs.regex_patterns().into_iter().chain(rhs.regex_patterns()).collect(),
+      BooleanExpression::Chain(_, operands) => operands.iter().flat_map(BooleanExpression::regex_patterns).collect(),
+      BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => value.regex_patterns(),
+    }
+  }
+}
+
+/// Evaluates both sides of a binary boolean expression with eval(), `&&`, `||` and `->` skip the right side
+/// if the left one decides the result.
+fn evaluate_binary(lhs: &BooleanExpression, op: &BinaryOperator, rhs: &BooleanExpression, mut eval: impl FnMut(&BooleanExpression) -> Result<bool, EvalError>) -> Result<bool, EvalError> {
+  match op {
+    BinaryOperator::And => Ok(eval(lhs)? && eval(rhs)?),
+    BinaryOperator::Or => Ok(eval(lhs)? || eval(rhs)?),
+    BinaryOperator::Xor => Ok(eval(lhs)? != eval(rhs)?),
+    BinaryOperator::Implies => Ok(!eval(lhs)? || eval(rhs)?),
+    BinaryOperator::Equals => Ok(eval(lhs)? == eval(rhs)?),
+    BinaryOperator::NotEquals => Ok(eval(lhs)? != eval(rhs)?),
+    // false is less than true
+    BinaryOperator::LessThan => Ok(!eval(lhs)? & eval(rhs)?),
+    BinaryOperator::GreaterThan => Ok(eval(lhs)? & !eval(rhs)?),
+    BinaryOperator::LessEqual => Ok(eval(lhs)? <= eval(rhs)?),
+    BinaryOperator::GreaterEqual => Ok(eval(lhs)? >= eval(rhs)?),
+    _ => Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for boolean: {:?}", op))))
+  }
+}
+
+/// Evaluates the operands of a chain from the left with eval() and stops as soon as the result is known:
+/// `&&` at the first false operand, `||` at the first true one and `->` at the first false premise.
+fn evaluate_chain(op: &BinaryOperator, operands: &[BooleanExpression], mut eval: impl FnMut(&BooleanExpression) -> Result<bool, EvalError>) -> Result<bool, EvalError> {
+  match op {
+    BinaryOperator::And => {
+      for operand in operands {
+        if !eval(operand)? {
+          return Ok(false);
+        }
+      }
+      Ok(true)
+    }
+    BinaryOperator::Or => {
+      for operand in operands {
+        if eval(operand)? {
+          return Ok(true);
+        }
+      }
+      Ok(false)
+    }
+    BinaryOperator::Xor => operands.iter().try_fold(false, |result, operand| Ok(result != eval(operand)?)),
+    // `a -> b -> c` is `a -> (b -> c)`, which is true unless all premises and not the conclusion are true
+    BinaryOperator::Implies => {
+      let Some((conclusion, premises)) = operands.split_last() else {
+        return Ok(true);
+      };
+      for premise in premises {
+        if !eval(premise)? {
+          return Ok(true);
+        }
+      }
+      eval(conclusion)
+    }
+    _ => Err(EvalError::InvalidOperator(message(|| format!("Invalid chain operator: {:?}", op)))),
+  }
 }
 
+/// Simplifies the operands of a chain like BooleanExpression::simplify() does for binary expressions.
+/// Literals which don't change the result are dropped and the operands after a literal which decides it are removed,
+/// they would not be evaluated. A chain left with a single operand is replaced by it.
+fn simplify_chain(op: BinaryOperator, operands: Vec<BooleanExpression>) -> BooleanExpression {
+  let last = operands.len().saturating_sub(1);
+  let mut kept = Vec::with_capacity(operands.len());
+  for (index, operand) in operands.into_iter().enumerate() {
+    match (&op, operand.simplify()) {
+      (BinaryOperator::And, BooleanExpression::Boolean(true)) | (BinaryOperator::Or, BooleanExpression::Boolean(false)) => {}
+      (BinaryOperator::And, BooleanExpression::Boolean(false)) | (BinaryOperator::Or, BooleanExpression::Boolean(true)) => {
+        kept.push(BooleanExpression::Boolean(op == BinaryOperator::Or));
+        break;
+      }
+      // a true premise does not change the implication, a false one makes it true
+      (BinaryOperator::Implies, BooleanExpression::Boolean(true)) if index < last => {}
+      (BinaryOperator::Implies, BooleanExpression::Boolean(false)) if index < last => {
+        kept.push(BooleanExpression::Boolean(true));
+        break;
+      }
+      (_, operand) => kept.push(operand),
+    }
+  }
+  match kept.len() {
+    0 => BooleanExpression::Boolean(op == BinaryOperator::And),
+    1 => kept.pop().expect("one operand is left"),
+    _ => BooleanExpression::Chain(op, kept).fold(),
+  }
+}
+
+/// The number as a float, integers are promoted.
+fn as_float(value: &Value) -> Option<f64> {
+  match value {
+    Value::IntegerLiteral(i) => Some(*i as f64),
+    Value::FloatLiteral(f) => Some(*f),
+    _ => None,
+  }
+}
+
+/// Prints the expression in a canonical form which parses back into an equal tree.
+/// Parentheses are only added where the grammar requires them: a binary expression on the left
+/// of another one, a binary expression on the right unless both are chained with the same `&&`, `||`, `^^` or `->`,
+/// a binary expression or chain inside a chain, comparisons compared with `==` or `!=` and any binary expression,
+/// chain or comparison after a unary operator.
+impl fmt::Display for BooleanExpression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BooleanExpression::Identifier(ident) => write!(f, "{}", ident),
+      BooleanExpression::Boolean(b) => write!(f, "{}", b),
+      BooleanExpression::NonBooleanExpression(nbe) => write!(f, "{}", nbe),
+      BooleanExpression::Predicate(value, predicate) => write!(f, "{} {}", value, predicate),
+      BooleanExpression::Between(value, lower, upper) => write!(f, "{} between {} and {}", value, lower, upper),
+      BooleanExpression::Within(value, window) => write!(f, "{} {}", value, window),
+      BooleanExpression::Binary(lhs, op, rhs) => {
+        let is_chain = matches!(op, BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor | BinaryOperator::Implies);
+        match lhs.as_ref() {
+          BooleanExpression::Binary(..) | BooleanExpression::Chain(..) => write!(f, "({})", lhs)?,
+          BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) if !is_chain => write!(f, "({})", lhs)?,
+          _ => write!(f, "{}", lhs)?,
+        }
+        write!(f, " {} ", op)?;
+        match rhs.as_ref() {
+          BooleanExpression::Binary(_, rhs_op, _) if rhs_op != op || !is_chain => write!(f, "({})", rhs),
+          BooleanExpression::Chain(..) => write!(f, "({})", rhs),
+          BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) if !is_chain => write!(f, "({})", rhs),
+          _ => write!(f, "{}", rhs),
+        }
+      }
+      BooleanExpression::Chain(op, operands) => {
+        for (index, operand) in operands.iter().enumerate() {
+          if index > 0 {
+            write!(f, " {} ", op)?;
+          }
+          match operand {
+            BooleanExpression::Binary(..) | BooleanExpression::Chain(..) => write!(f, "({})", operand)?,
+            _ => write!(f, "{}", operand)?,
+          }
+        }
+        Ok(())
+      }
+      BooleanExpression::Unary(op, value) => match value.as_ref() {
+        BooleanExpression::Binary(..) | BooleanExpression::Chain(..) | BooleanExpression::NonBooleanExpression(_) | BooleanExpression::Predicate(..) | BooleanExpression::Between(..) | BooleanExpression::Within(..) => write!(f, "{}({})", op, value),
+        _ => write!(f, "{}{}", op, value),
+      },
+      BooleanExpression::Group(value) => write!(f, "({})", value),
+    }
+  }
+}
+
+/// The maximum depth of the syntax tree, deeper expressions are rejected instead of overflowing the stack.
+/// Parentheses and unary operators add a level for their content, the operands of a chain like `a && b && c`
+/// are parsed on the same level however long it is. Calculations like `a + b + c` and fallbacks like `a ?: b ?: c`
+/// nest their values, every further operand adds a level.
+pub(crate) const MAX_DEPTH: usize = 128;
+
+thread_local! {
+  static DEPTH: Cell<usize> = const { Cell::new(0) };
+  /// Whether parentheses are kept as BooleanExpression::Group, see parse_with_groups().
+  static KEEP_GROUPS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs the parser one level deeper and fails once the maximum depth is exceeded.
+pub(crate) fn nested<'a, O>(input: &'a str, parser: fn(&'a str) -> IResult<&'a str, O>) -> IResult<&'a str, O> {
+  let depth = DEPTH.with(|depth| {
+    depth.set(depth.get() + 1);
+    depth.get()
+  });
+  let result = if depth > MAX_DEPTH {
+    Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::TooLarge)))
+  } else {
+    parser(input)
+  };
+  DEPTH.with(|depth| depth.set(depth.get() - 1));
+  result
+}
+/// Fails once the value nests deeper than the levels left below the current one, see Value::depth().
+pub(crate) fn check_value_depth<'a>(input: &'a str, value: &Value) -> IResult<&'a str, ()> {
+  if DEPTH.with(Cell::get) + value.depth() > MAX_DEPTH {
+    Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::TooLarge)))
+  } else {
+    Ok((input, ()))
+  }
+}
 fn boolean_value(input: &str) -> IResult<&str, BooleanExpression> {
+  nested(input, boolean_value_inner)
+}
+/// Parentheses recurse through unary_operand, keeping it out of the large alt of the other
+/// alternatives keeps the stack frames of deeply nested expressions small.
+fn boolean_value_inner(input: &str) -> IResult<&str, BooleanExpression> {
+  alt((non_boolean_value, unary_operand))(input)
+}
+fn non_boolean_value(input: &str) -> IResult<&str, BooleanExpression> {
   alt((
-    map( binary_non_bool, |nbe| BooleanExpression::NonBooleanExpression(nbe)),
-    delimited(tuple((char('('), multispace0)), boolean_expression, tuple((multispace0, char(')')))), 
+    // before binary_non_bool, which would parse `x > all` of `x > all (1, 2)` as a comparison with the identifier all
+    map(quantified, BooleanExpression::NonBooleanExpression),
+    map( binary_non_bool, BooleanExpression::NonBooleanExpression),
+    map(tuple((arithmetic, multispace1, predicate)), |(value, _, predicate)| BooleanExpression::Predicate(value, predicate)),
+    map(tuple((arithmetic, multispace1, tag("between"), multispace1, arithmetic, multispace1, tag("and"), multispace1, arithmetic)),
+      |(value, _, _, _, lower, _, _, _, upper)| BooleanExpression::Between(value, lower, upper)),
+    map(tuple((arithmetic, multispace1, time_window)), |(value, _, window)| BooleanExpression::Within(value, window)),
+    map(membership, |(nbe, negated)| match negated {
+      true => BooleanExpression::Unary(UnaryOperator::Not, Box::new(BooleanExpression::NonBooleanExpression(nbe))),
+      false => BooleanExpression::NonBooleanExpression(nbe),
+    }),
+  ))(input)
+}
+/// The operand of a unary operator. `!` binds tighter than any binary operator, so `!a == b` is `(!a) == b`
+/// and negating a comparison like `!(a > 1)` requires parentheses.
+fn unary_operand(input: &str) -> IResult<&str, BooleanExpression> {
+  alt((
+    map(delimited(tuple((char('('), multispace0)), boolean_expression, tuple((multispace0, char(')')))), |value| match KEEP_GROUPS.with(Cell::get) {
+      true => BooleanExpression::Group(Box::new(value)),
+      false => value,
+    }),
     map_res( boolean, |b| {
         if let Value::Boolean(b) = b {
           Ok(BooleanExpression::Boolean(b))
@@ -72,59 +740,160 @@ fn boolean_value(input: &str) -> IResult<&str, BooleanExpression> {
         }
       }
     ), 
-    map(tuple((unary_operator_primary, multispace0, boolean_value)), 
+    map(tuple((unary_operator_primary, multispace0, nested_unary_operand)), 
     |(op, _, value)| BooleanExpression::Unary(op, Box::new(value))
     )
   ))(input)
 }
+fn nested_unary_operand(input: &str) -> IResult<&str, BooleanExpression> {
+  nested(input, unary_operand)
+}
+fn boolean_equality(input: &str) -> IResult<&str, BooleanExpression> {
+  let (input, lhs) = boolean_value(input)?;
+  equality_rest(input, lhs)
+}
+/// The comparison after the first operand of boolean_equality(), in a function of its own like chain_rest().
+fn equality_rest(input: &str, lhs: BooleanExpression) -> IResult<&str, BooleanExpression> {
+  match tuple((delimited(multispace0, binary_operator_equality, multispace0), boolean_value))(input) {
+    Ok((input, (op, rhs))) => Ok((input, BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs)))),
+    Err(nom::Err::Error(_)) => Ok((input, lhs)),
+    Err(err) => Err(err),
+  }
+}
+fn chain_operator(input: &str) -> IResult<&str, BinaryOperator> {
+  alt((binary_and_operator, binary_or_operator, binary_xor_operator, binary_implies_operator))(input)
+}
+/// Parses operands joined by `&&`, `||`, `^^` or `->` into a BooleanExpression::Chain. The chain is joined to the right,
+/// `a -> b -> c` is read as `a -> (b -> c)`. The first operator decides the chain,
+/// mixing operators requires parentheses and leaves the rest of the input unparsed.
+/// Every operand is only parsed once, so nested parentheses don't cause backtracking.
 fn boolean_expression(input: &str) -> IResult<&str, BooleanExpression> {
-  alt((
-    boolean_and,
-    boolean_or,
-    boolean_value,
-  ))(input)
+  let (input, first) = boolean_equality(input)?;
+  chain_rest(input, first)
+}
+/// The operands after the first one of boolean_expression(), in a function of their own so the
+/// first operand, which may be nested deeply, is parsed in a small stack frame.
+fn chain_rest(mut input: &str, first: BooleanExpression) -> IResult<&str, BooleanExpression> {
+  let mut operands = vec![first];
+  let mut chain: Option<BinaryOperator> = None;
+  loop {
+    match tuple((multispace0, chain_operator, multispace0, boolean_equality))(input) {
+      Ok((rest, (_, op, _, operand))) if chain.as_ref().is_none_or(|chain| *chain == op) => {
+        chain = Some(op);
+        operands.push(operand);
+        input = rest;
+      }
+      Ok(_) | Err(nom::Err::Error(_)) => break,
+      Err(err) => return Err(err),
+    }
+  }
+  match chain {
+    Some(op) => Ok((input, BooleanExpression::Chain(op, operands))),
+    None => Ok((input, operands.pop().expect("a chain has at least one operand"))),
+  }
 }
-fn parse_whole_boolean_expression(input: &str) -> Result<BooleanExpression, String> {
-  match boolean_expression(input) {
-    Ok((remaining, parsed)) if remaining.is_empty() => Ok(parsed),
-    Ok((remaining, _)) => Err(format!("Expected end of input, found: {:?}", remaining)),
-    Err(err) => Err(format!("{:?}", err)),
+pub(crate) fn parse_whole_boolean_expression(input: &str) -> Result<BooleanExpression, EvalError> {
+  let stripped = strip_comments(input)?;
+  let parsed = with_source(&stripped, || delimited(multispace0, boolean_expression, multispace0)(&stripped));
+  match parsed {
+    Ok(("", parsed)) => Ok(parsed),
+    Ok((remaining, _)) => {
+      let position = stripped.len() - remaining.len();
+      Err(EvalError::UnexpectedInput { position, message: parse_error_message(input, position, "Unexpected input") })
+    }
+    Err(nom::Err::Failure(err)) if err.code == ErrorKind::TooLarge =>
+      Err(EvalError::Parse(format!("Expression is nested deeper than {} levels", MAX_DEPTH))),
+    Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(EvalError::Parse(parse_error_message(input, stripped.len() - err.input.len(), "Invalid expression"))),
+    Err(err) => Err(EvalError::Parse(format!("{:?}", err))),
   }
 }
 
-fn boolean_and(input: &str) -> IResult<&str, BooleanExpression> {
-  alt((
-    map(tuple((boolean_value, multispace0, binary_and_operator, multispace0, boolean_and)),
-      |(lhs, _, op, _, rhs)| BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs))
-    ),
-    map(tuple((boolean_value, multispace0, binary_and_operator, multispace0, boolean_value)),
-      |(lhs, _, op, _, rhs)| BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs))
-    ),
-  ))(input)
+/// Parses like TryFrom<&str> but keeps the parentheses as BooleanExpression::Group.
+pub(crate) fn parse_with_groups(input: &str) -> Result<BooleanExpression, EvalError> {
+  KEEP_GROUPS.with(|keep| keep.set(true));
+  let result = parse_whole_boolean_expression(input);
+  KEEP_GROUPS.with(|keep| keep.set(false));
+  check_regexes(result?)
 }
 
-fn boolean_or(input: &str) -> IResult<&str, BooleanExpression> {
-  alt((
-    map(tuple((boolean_value, multispace0, binary_or_operator, multispace0, boolean_or)),
-    |(lhs, _, op, _, rhs)| BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs))
-    ),
-    map(tuple((boolean_value, multispace0, binary_or_operator, multispace0, boolean_value)),
-    |(lhs, _, op, _, rhs)| BooleanExpression::Binary(Box::new(lhs), op, Box::new(rhs))
-    ),
-  ))(input)
+fn check_regexes(expression: BooleanExpression) -> Result<BooleanExpression, EvalError> {
+  for pattern in expression.regex_patterns() {
+    compile_regex(&pattern)?;
+  }
+  Ok(expression)
+}
+
+/// The message of a parse error with the line and column of the byte offset and the line with a caret below the position.
+/// Comments keep their length when they are stripped, so offsets in the stripped input are valid in the original.
+fn parse_error_message(input: &str, offset: usize, message: &str) -> String {
+  let line_start = input[..offset].rfind('\n').map_or(0, |index| index + 1);
+  let line_end = input[offset..].find('\n').map_or(input.len(), |index| offset + index);
+  let line = input[..line_start].matches('\n').count() + 1;
+  let column = input[line_start..offset].chars().count() + 1;
+  // tabs are kept so the caret lines up with the text above it
+  let indent: String = input[line_start..offset].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+  format!("{} at line {}, column {}:\n{}\n{}^", message, line, column, &input[line_start..line_end], indent)
 }
 
+/// Replaces `/* block */`, `# line` and `// line` comments outside of string literals with spaces,
+/// so the parser sees them as whitespace and the remaining text keeps its position.
+pub(crate) fn strip_comments(input: &str) -> Result<Cow<'_, str>, EvalError> {
+  if !input.contains('#') && !input.contains("/*") && !input.contains("//") {
+    return Ok(Cow::Borrowed(input));
+  }
+  let mut output = String::with_capacity(input.len());
+  let mut chars = input.chars().peekable();
+  // the quote of the string literal or quoted identifier the current character is in
+  let mut quote = None;
+  while let Some(c) = chars.next() {
+    match c {
+      '\'' | '`' if quote.is_none_or(|quote| quote == c) => {
+        quote = if quote.is_none() { Some(c) } else { None };
+        output.push(c);
+      }
+      '#' | '/' if quote.is_none() && (c == '#' || chars.peek() == Some(&'/')) => {
+        if c == '/' {
+          chars.next();
+          output.push(' ');
+        }
+        output.push(' ');
+        while let Some(c) = chars.next_if(|c| *c != '\n') {
+          output.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+      }
+      '/' if quote.is_none() && chars.peek() == Some(&'*') => {
+        chars.next();
+        output.push_str("  ");
+        loop {
+          match chars.next() {
+            Some('*') if chars.peek() == Some(&'/') => {
+              chars.next();
+              output.push_str("  ");
+              break;
+            }
+            Some('/') if chars.peek() == Some(&'*') => return Err(EvalError::Parse("Nested block comments are not supported".to_string())),
+            Some(c) if c == '\n' => output.push(c),
+            Some(c) => output.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            None => return Err(EvalError::Parse("Unterminated block comment".to_string())),
+          }
+        }
+      }
+      _ => output.push(c),
+    }
+  }
+  Ok(Cow::Owned(output))
+}
 
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::{operator::{BinaryOperator, UnaryOperator}, value::{Value,Identifier}};
+  use crate::{operator::{BinaryOperator, UnaryOperator}, value::{Value,Identifier}, Context, EvalErrorCode, EvalOptions};
 
   #[test]
   fn test_boolean_value() {
     let value = "(true)";
     let result = boolean_value(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, BooleanExpression::Boolean(true));
   }
@@ -132,7 +901,7 @@ mod test {
   fn test_boolean_value_2() {
     let value = "!  ( !   true)  ";
     let result = boolean_value(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, BooleanExpression::Unary(UnaryOperator::Not, Box::new(BooleanExpression::Unary(UnaryOperator::Not, Box::new(BooleanExpression::Boolean(true))))));
   }
@@ -140,7 +909,7 @@ mod test {
   fn test_boolean_value_3() {
     let value = "4 == mode";
     let result = boolean_value(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, BooleanExpression::NonBooleanExpression(NonBooleanExpression(Value::IntegerLiteral(4), BinaryOperator::Equals, Value::Identifier(Identifier::from("mode")))));
   }
@@ -148,24 +917,19 @@ mod test {
   fn test_boolean_value_err() {
     let value = "4 && mode";
     let result = boolean_value(value);
-    assert_eq!(result.is_err(), true);
+    assert!(result.is_err());
   }
 
   #[test]
   fn test_boolean_expression() {
     let value = "false || true || false";
     let result = boolean_expression(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, 
-      BooleanExpression::Binary(
-        Box::new(BooleanExpression::Boolean(false)),
+      BooleanExpression::Chain(
         BinaryOperator::Or,
-        Box::new(BooleanExpression::Binary(
-          Box::new(BooleanExpression::Boolean(true)),
-          BinaryOperator::Or,
-          Box::new(BooleanExpression::Boolean(false))
-        )),
+        vec![BooleanExpression::Boolean(false), BooleanExpression::Boolean(true), BooleanExpression::Boolean(false)],
       )
     );
   }
@@ -173,58 +937,657 @@ mod test {
   #[test]
   fn test_boolean_and() {
     let value = "true && false";
-    let result = boolean_and(value);
-    assert_eq!(result.is_ok(), true);
+    let result = boolean_expression(value);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, 
-      BooleanExpression::Binary(
-        Box::new(BooleanExpression::Boolean(true)),
-        BinaryOperator::And,
-        Box::new(BooleanExpression::Boolean(false))
-      )
+      BooleanExpression::Chain(BinaryOperator::And, vec![BooleanExpression::Boolean(true), BooleanExpression::Boolean(false)])
     );
   }
 
   #[test]
   fn test_boolean_or() {
     let value = "true || false";
-    let result = boolean_or(value);
-    assert_eq!(result.is_ok(), true);
+    let result = boolean_expression(value);
+    assert!(result.is_ok());
     let (_, boolean_exp) = result.unwrap();
     assert_eq!(boolean_exp, 
-      BooleanExpression::Binary(
-        Box::new(BooleanExpression::Boolean(true)),
-        BinaryOperator::Or,
-        Box::new(BooleanExpression::Boolean(false))
-      )
+      BooleanExpression::Chain(BinaryOperator::Or, vec![BooleanExpression::Boolean(true), BooleanExpression::Boolean(false)])
     );
   }
 
   #[test]
   fn test_boolean_value_error() {
-    let value = "identifier < true";
+    let value = "identifier < true 5";
     let result = parse_whole_boolean_expression(value);
-    assert_eq!(result.is_err(), true);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn test_parse_error_position() {
+    assert_eq!(
+      parse_whole_boolean_expression("a &&"),
+      Err(EvalError::UnexpectedInput { position: 2, message: "Unexpected input at line 1, column 3:\na &&\n  ^".to_string() })
+    );
+    assert_eq!(
+      parse_whole_boolean_expression("a == 1 # one\n\t&& b c"),
+      Err(EvalError::UnexpectedInput { position: 19, message: "Unexpected input at line 2, column 7:\n\t&& b c\n\t     ^".to_string() })
+    );
+    let value = "true garbage";
+    let Err(EvalError::UnexpectedInput { position, .. }) = parse_whole_boolean_expression(value) else {
+      panic!("expected unexpected input");
+    };
+    assert_eq!((position, &value[..position], &value[position..]), (5, "true ", "garbage"));
+    assert!(matches!(parse_whole_boolean_expression("(a"), Err(EvalError::Parse(msg)) if msg.starts_with("Invalid expression at line 1, column 1:")));
+  }
+
+  #[test]
+  fn test_parse_with_groups() {
+    for value in ["(a) && b", "((a && b)) || !(c)", "(x > 1) && (y)", "((true))"] {
+      assert_eq!(parse_with_groups(value).unwrap().to_string(), value);
+    }
+    let grouped = parse_with_groups("(a) && (b || c)").unwrap();
+    assert_eq!(grouped.clone().simplify(), parse_whole_boolean_expression("a && (b || c)").unwrap());
+    assert_eq!(grouped.depth(), parse_whole_boolean_expression("(a) && (b || c)").unwrap().depth());
+    let context: Context = [("a", true), ("b", false), ("c", true)].into_iter().map(|(name, value)| (name.to_string(), ContextValue::Boolean(value))).collect();
+    let options = EvalOptions::default();
+    assert_eq!(grouped.evaluate(&EvalEnv::new(&context, &options)), Ok(true));
+    assert_eq!(parse_whole_boolean_expression("(a) && b").unwrap().to_string(), "a && b");
   }
 
   #[test]
   fn test_boolean_expression_identifier() {
     let value = "(identifier)";
     let result = boolean_expression(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
   }
 
   #[test]
   fn test_boolean_and_or_mix_error() {
     let value = "identifier && identifier || identifier";
     let result = parse_whole_boolean_expression(value);
-    assert_eq!(result.is_err(), true);
+    assert!(result.is_err());
   }
 
   #[test]
   fn test_boolean_and_or_mix() {
     let value = "identifier && (identifier || identifier)";
     let result = parse_whole_boolean_expression(value);
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
+  }
+
+  #[cfg(feature = "serde")]
+  fn assert_json_round_trip(expr: BooleanExpression) {
+    let json = serde_json::to_string(&expr).unwrap();
+    let back: BooleanExpression = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, expr);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip_boolean_operators() {
+    for value in ["a && b", "a || b", "!a", "true && (false || !flag)"] {
+      assert_json_round_trip(parse_whole_boolean_expression(value).unwrap());
+    }
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip_comparison_operators() {
+    let operators = [
+      BinaryOperator::Equals,
+      BinaryOperator::NotEquals,
+      BinaryOperator::LessThan,
+      BinaryOperator::GreaterThan,
+      BinaryOperator::LessEqual,
+      BinaryOperator::GreaterEqual,
+      BinaryOperator::RegexMatch,
+    ];
+    for op in operators {
+      assert_json_round_trip(BooleanExpression::NonBooleanExpression(NonBooleanExpression(
+        Value::Identifier(Identifier::from("count")), op, Value::FloatLiteral(1.5),
+      )));
+    }
+    assert_json_round_trip(parse_whole_boolean_expression("name =~ 'ba+r' && 1 == count").unwrap());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip_every_variant() {
+    use rand::SeedableRng;
+    // the trees of the grammar test have every node the parser produces
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+    for case in 0..500 {
+      assert_json_round_trip(crate::grammar_test::boolean(&mut rng, case % 6));
+    }
+    // and these the ones only built in code or resolved from the context
+    let a = || Box::new(BooleanExpression::Identifier(Identifier::from("a")));
+    assert_json_round_trip(BooleanExpression::Group(a()));
+    assert_json_round_trip(BooleanExpression::Binary(a(), BinaryOperator::And, a()));
+    for value in [Value::DateTime(1_700_000_000), Value::Boolean(true), Value::List(vec![Value::FloatLiteral(-0.0), Value::StringLiteral("'".to_string())])] {
+      assert_json_round_trip(BooleanExpression::NonBooleanExpression(NonBooleanExpression(Value::Identifier(Identifier::from("x")), BinaryOperator::Equals, value)));
+    }
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip_context_value() {
+    use crate::ContextValue;
+    let values = [
+      ContextValue::String("foo".to_string()),
+      ContextValue::Integer(1),
+      ContextValue::Float(1.5),
+      ContextValue::Boolean(true),
+      ContextValue::List(vec![ContextValue::Integer(-1), ContextValue::List(vec![])]),
+      ContextValue::Map(HashMap::from([("city".to_string(), ContextValue::String("Berlin".to_string())), ("zip".to_string(), ContextValue::Integer(10115))])),
+      ContextValue::DateTime(1_700_000_000),
+      #[cfg(feature = "decimal")]
+      ContextValue::Decimal(rust_decimal::Decimal::new(-110, 2)),
+    ];
+    for value in values {
+      let json = serde_json::to_string(&value).unwrap();
+      assert_eq!(serde_json::from_str::<ContextValue>(&json).unwrap(), value);
+    }
+  }
+
+  fn assert_display_round_trip(value: &str, expected: &str) {
+    let expr = parse_whole_boolean_expression(value).unwrap();
+    assert_eq!(expr.to_string(), expected);
+    assert_eq!(parse_whole_boolean_expression(&expr.to_string()).unwrap(), expr);
+  }
+
+  #[test]
+  fn test_display_round_trip() {
+    assert_display_round_trip("true", "true");
+    assert_display_round_trip("(flag)", "flag");
+    assert_display_round_trip("4  ==   mode", "4 == mode");
+    assert_display_round_trip("1.0 < count", "1.0 < count");
+    assert_display_round_trip("x > 100000000000000000000000.0", "x > 100000000000000000000000.0");
+    assert_display_round_trip("x > 0.0000001", "x > 0.0000001");
+    for x in [1e23, 1e-7, f64::MAX, f64::MIN_POSITIVE, 5e-324, 0.1 + 0.2] {
+      let expr = BooleanExpression::NonBooleanExpression(NonBooleanExpression(Value::Identifier(Identifier::from("x")), BinaryOperator::GreaterThan, Value::FloatLiteral(x)));
+      assert_eq!(parse_whole_boolean_expression(&expr.to_string()), Ok(expr));
+    }
+    assert_display_round_trip("name =~ 'ba+r'", "name =~ 'ba+r'");
+    assert_display_round_trip("a && b && c", "a && b && c");
+    assert_display_round_trip("(a && b) && c", "(a && b) && c");
+  }
+
+  #[test]
+  fn test_display_round_trip_unary() {
+    assert_display_round_trip("!a", "!a");
+    assert_display_round_trip("!  ( !   true)", "!!true");
+    assert_display_round_trip("!(a && b)", "!(a && b)");
+    assert_display_round_trip("!(count == 1)", "!(count == 1)");
+  }
+
+  #[test]
+  fn test_display_round_trip_mixed_binary() {
+    assert_display_round_trip("a && (b || c)", "a && (b || c)");
+    assert_display_round_trip("(a || b) && !c", "(a || b) && !c");
+    assert_display_round_trip("a || (1 == count && name =~ 'x')", "a || (1 == count && name =~ 'x')");
+  }
+
+  #[test]
+  fn test_surrounding_whitespace() {
+    assert_eq!(parse_whole_boolean_expression("  true  "), Ok(BooleanExpression::Boolean(true)));
+    assert_eq!(parse_whole_boolean_expression("\ta && b\n"), parse_whole_boolean_expression("a && b"));
+    assert!(parse_whole_boolean_expression("\ta && b\n").is_ok());
+    assert!(parse_whole_boolean_expression(" \n ").is_err());
+  }
+
+  #[test]
+  fn test_boolean_equality() {
+    let value = "enabled == true";
+    let result = parse_whole_boolean_expression(value).unwrap();
+    assert_eq!(result, BooleanExpression::Binary(
+      Box::new(BooleanExpression::Identifier(Identifier::from("enabled"))),
+      BinaryOperator::Equals,
+      Box::new(BooleanExpression::Boolean(true)),
+    ));
+    let (context, options) = (Context::new(), EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
+    assert_eq!(parse_whole_boolean_expression("true == true").unwrap().evaluate(&env), Ok(true));
+    assert_eq!(parse_whole_boolean_expression("true != true").unwrap().evaluate(&env), Ok(false));
+    assert_eq!(parse_whole_boolean_expression("(true && false) == false").unwrap().evaluate(&env), Ok(true));
+    assert_display_round_trip("a == (b == c)", "a == (b == c)");
+    assert_display_round_trip("(1 < count) != flag", "(1 < count) != flag");
+    assert_display_round_trip("a && b != c", "a && b != c");
+  }
+
+  #[test]
+  fn test_boolean_ordering() {
+    let mut context = Context::new();
+    context.insert("flag_a".to_string(), ContextValue::Boolean(false));
+    context.insert("flag_b".to_string(), ContextValue::Boolean(true));
+    let options = EvalOptions::default();
+    let env = EvalEnv::new(&context, &options);
+    let evaluate = |expression: &str| parse_whole_boolean_expression(expression).unwrap().evaluate(&env);
+    assert_eq!(evaluate("false < true"), Ok(true));
+    assert_eq!(evaluate("true <= true"), Ok(true));
+    assert_eq!(evaluate("true < true || false > true || false >= true"), Ok(false));
+    assert_eq!(evaluate("flag_a < flag_b && flag_b >= flag_a"), Ok(true));
+    assert_eq!(evaluate("(flag_b && true) > flag_a"), Ok(true));
+    assert!(parse_whole_boolean_expression("true < 5").is_err());
+    assert_display_round_trip("false < true", "false < true");
+    assert_display_round_trip("(a == 1) <= b", "(a == 1) <= b");
+  }
+
+  fn evaluate_str(value: &str) -> Result<bool, EvalError> {
+    let (context, options) = (Context::new(), EvalOptions::default());
+    parse_whole_boolean_expression(value).unwrap().evaluate(&EvalEnv::new(&context, &options))
+  }
+
+  #[test]
+  fn test_is_constant() {
+    let is_constant = |value: &str| parse_whole_boolean_expression(value).unwrap().is_constant();
+    assert!(is_constant("1 == 1"));
+    assert!(is_constant("!(1 + 2 > 2) || true"));
+    assert!(!is_constant("a == 1"));
+    assert!(!is_constant("1 == 1 && a"));
+    assert!(!is_constant("!(a[0] == 1)"));
+  }
+
+  #[test]
+  fn test_operators_without_whitespace() {
+    for (compact, spaced) in [
+      ("a>b", "a > b"),
+      ("x==y", "x == y"),
+      ("1<2", "1 < 2"),
+      ("a>=b&&c!=d", "a >= b && c != d"),
+      ("1.5<=x.y", "1.5 <= x.y"),
+      ("name=~'a+'", "name =~ 'a+'"),
+      ("x%2==0||!a", "x % 2 == 0 || !a"),
+      ("a[0]-1>b?:c", "a[0] - 1 > b ?: c"),
+    ] {
+      assert_eq!(parse_whole_boolean_expression(compact), parse_whole_boolean_expression(spaced), "{}", compact);
+      assert_eq!(parse_whole_boolean_expression(compact).unwrap().to_string(), spaced);
+    }
+  }
+
+  #[test]
+  fn test_operator_keywords() {
+    for (keywords, symbols) in [
+      ("(a and not b) or c", "(a && !b) || c"),
+      ("a and not(b or c)", "a && !(b || c)"),
+      ("not not a and x > 1", "!!a && x > 1"),
+      ("android and orders and notes", "android && orders && notes"),
+    ] {
+      assert_eq!(parse_whole_boolean_expression(keywords), parse_whole_boolean_expression(symbols), "{}", keywords);
+    }
+    // like the symbols the keywords can't be mixed without parentheses
+    assert!(parse_whole_boolean_expression("a and not b or c").is_err());
+    assert!(parse_whole_boolean_expression("a && !b || c").is_err());
+    assert_eq!(parse_whole_boolean_expression("android and order.id").unwrap().to_string(), "android && order.id");
+    assert!(parse_whole_boolean_expression("age between 1 and 2 and active").is_ok());
+    assert!(parse_whole_boolean_expression("and").is_err());
+    assert!(parse_whole_boolean_expression("a andb").is_err());
+  }
+
+  #[test]
+  fn test_remap_identifiers() {
+    let expression = parse_whole_boolean_expression("old_name > 1 && other ?: old_name == 4 && (old_name, 2) == (3, old_name[0])").unwrap();
+    let remapped = expression.remap_identifiers(|name| if name == "old_name" { "new_name".to_string() } else { name.to_string() });
+    assert!(!remapped.contains_identifier("old_name"));
+    assert_eq!(remapped, parse_whole_boolean_expression("new_name > 1 && other ?: new_name == 4 && (new_name, 2) == (3, new_name[0])").unwrap());
+    let mut context = Context::new();
+    context.insert("new_name".to_string(), ContextValue::Integer(5));
+    let remapped = parse_whole_boolean_expression("old_name > 1").unwrap().remap_identifiers(|_| "new_name".to_string());
+    assert_eq!(remapped.evaluate(&EvalEnv::new(&context, &EvalOptions::default())), Ok(true));
+  }
+
+  #[test]
+  fn test_spans() {
+    let expression = parse_whole_boolean_expression("a && bar == 2").unwrap();
+    let BooleanExpression::Chain(_, operands) = &expression else { panic!("{:?}", expression) };
+    let [lhs, rhs] = operands.as_slice() else { panic!("{:?}", operands) };
+    let BooleanExpression::NonBooleanExpression(NonBooleanExpression(Value::Identifier(bar), _, _)) = rhs else { panic!("{:?}", rhs) };
+    assert_eq!(bar.span(), Some(5..8));
+    assert_eq!(lhs.span(), Some(0..1));
+    assert_eq!(expression.span(), Some(0..8));
+    assert_eq!(expression, BooleanExpression::Chain(BinaryOperator::And, vec![
+      BooleanExpression::Identifier(Identifier::from("a")),
+      BooleanExpression::NonBooleanExpression(NonBooleanExpression(Value::Identifier(Identifier::from("bar")), BinaryOperator::Equals, Value::IntegerLiteral(2))),
+    ]));
+
+    let expression = parse_whole_boolean_expression("/* x */ `order total` > 1 &&\n  lower(name) == 'a'").unwrap();
+    let BooleanExpression::Chain(_, operands) = &expression else { panic!("{:?}", expression) };
+    let [lhs, rhs] = operands.as_slice() else { panic!("{:?}", operands) };
+    assert_eq!(lhs.span(), Some(8..21));
+    assert_eq!(rhs.span(), Some(37..41));
+    assert_eq!(parse_whole_boolean_expression("true || 1 > 0").unwrap().span(), None);
+    assert_eq!(Identifier::from("a").span(), None);
+  }
+
+  #[test]
+  fn test_literals() {
+    let expression = parse_whole_boolean_expression(
+      "name =~ '^a' && role in ('admin', 'owner') && code ===~ '[0-9]+' && lower(city) == 'bern' && path like '*.rs' && a ?: 'x' != 'y'"
+    ).unwrap();
+    assert_eq!(expression.string_literals(), vec!["admin", "owner", "bern", "*.rs", "x", "y"]);
+    assert_eq!(expression.regex_literals(), vec!["^a", "[0-9]+"]);
+    let expression = parse_whole_boolean_expression("'a' =~ name || !(tag =~ 'b')").unwrap();
+    assert_eq!(expression.string_literals(), vec!["a"]);
+    assert_eq!(expression.regex_literals(), vec!["b"]);
+    assert!(parse_whole_boolean_expression("a && b > 1").unwrap().string_literals().is_empty());
+  }
+
+  #[test]
+  fn test_partial_eval() {
+    let mut context = Context::new();
+    context.insert("a".to_string(), ContextValue::Boolean(true));
+    context.insert("n".to_string(), ContextValue::Integer(3));
+    context.insert("user".to_string(), ContextValue::Map([("name".to_string(), ContextValue::String("bob".to_string()))].into()));
+    let partial_eval = |expression: &str| parse_whole_boolean_expression(expression).unwrap().partial_eval(&context).map(|expression| expression.to_string());
+    assert_eq!(partial_eval("a && b"), Ok("b".to_string()));
+    assert_eq!(partial_eval("!a || b"), Ok("b".to_string()));
+    assert_eq!(partial_eval("n + 1 > m && user.name == 'bob'"), Ok("3 + 1 > m".to_string()));
+    assert_eq!(partial_eval("n is positive || b"), Ok("true".to_string()));
+    assert_eq!(partial_eval("missing ?: n > 2"), Ok("missing ?: 3 > 2".to_string()));
+    assert!(matches!(partial_eval("n && b"), Err(EvalError::TypeMismatch(_))));
+  }
+
+  #[test]
+  fn test_partial_eval_parses_again() {
+    // negative numbers and quotes in strings are written so the result parses again
+    let mut context = Context::new();
+    context.insert("n".to_string(), ContextValue::Integer(-5));
+    context.insert("x".to_string(), ContextValue::Float(-0.5));
+    context.insert("name".to_string(), ContextValue::String("O'Brien".to_string()));
+    let partial_eval = |expression: &str| parse_whole_boolean_expression(expression).unwrap().partial_eval(&context).map(|expression| expression.to_string());
+    for (expression, expected) in [("n == y", "-5 == y"), ("x < y - 1", "-0.5 < y - 1"), ("name == y", "'O''Brien' == y"), ("y - n > 0", "y - -5 > 0")] {
+      let display = partial_eval(expression).unwrap();
+      assert_eq!(display, expected);
+      let reparsed = parse_whole_boolean_expression(&display).unwrap();
+      assert_eq!(reparsed, parse_whole_boolean_expression(expression).unwrap().partial_eval(&context).unwrap());
+    }
+    assert_eq!(evaluate_str("'it''s' == 'it''s' && -3 < -2 && 1 - -1 == 2"), Ok(true));
+  }
+
+  #[test]
+  fn test_try_from_input_types() {
+    let source = "name =~ '^a' && `order total` > 1.5";
+    let from_str = BooleanExpression::try_from(source).unwrap();
+    assert_eq!(BooleanExpression::try_from(source.as_bytes()), Ok(from_str.clone()));
+    assert_eq!(BooleanExpression::try_from(source.to_string()), Ok(from_str));
+    assert!(matches!(BooleanExpression::try_from(&b"name == '\xff'"[..]), Err(EvalError::Parse(_))));
+    assert!(matches!(BooleanExpression::try_from("name =~ '['".to_string()), Err(EvalError::InvalidRegex(_))));
+  }
+  #[test]
+  fn test_validate() {
+    let schema: HashMap<String, ValueType> = [("name", ValueType::String), ("age", ValueType::Integer), ("count", ValueType::Integer)]
+      .into_iter().map(|(name, value_type)| (name.to_string(), value_type)).collect();
+    let validate = |value: &str| parse_whole_boolean_expression(value).unwrap().validate(&schema);
+    assert_eq!(validate("unknownA > 1 && unknownB =~ count"), Err(vec![
+      EvalError::UnknownIdentifier("Identifier not found in schema: unknownA".to_string()),
+      EvalError::UnknownIdentifier("Identifier not found in schema: unknownB".to_string()),
+    ]));
+    let errors = validate("name < 5 && (age =~ 'a' || !missing) && missing == other && age > 18").unwrap_err();
+    assert_eq!(errors.iter().map(EvalError::code).collect::<Vec<_>>(), vec![
+      EvalErrorCode::TypeMismatch,
+      EvalErrorCode::InvalidOperator,
+      EvalErrorCode::UnknownIdentifier,
+      EvalErrorCode::UnknownIdentifier,
+    ]);
+    assert_eq!(validate("name =~ 'a' && age > 18"), Ok(()));
+    assert!(parse_whole_boolean_expression("unknownB =~ 2").is_err());
+  }
+  #[test]
+  fn test_contains_identifier() {
+    let expression = parse_whole_boolean_expression("a && !(b || price + tax between 1 and limit) && (name in (x, 'y') || total ?: 0 > 1)").unwrap();
+    for name in ["a", "b", "price", "tax", "limit", "name", "x", "total"] {
+      assert!(expression.contains_identifier(name), "{}", name);
+    }
+    for name in ["y", "c", "pric", "between"] {
+      assert!(!expression.contains_identifier(name), "{}", name);
+    }
+  }
+
+  #[test]
+  fn test_simplify() {
+    let simplify = |value: &str| parse_whole_boolean_expression(value).unwrap().simplify().to_string();
+    assert_eq!(simplify("2 > 1 && x"), "x");
+    assert_eq!(simplify("1 > 2 && x"), "false");
+    assert_eq!(simplify("x && 1 > 2"), "x && false");
+    assert_eq!(simplify("x || 3 % 2 == 0"), "x");
+    assert_eq!(simplify("(2 > 1 -> y) && !(1 is zero) && z"), "y && z");
+    assert_eq!(simplify("!(x && (true ^^ false))"), "!x");
+    assert_eq!(simplify("5 between 1 and 9 ^^ x"), "true ^^ x");
+    // these depend on the context, the options or fail to evaluate
+    assert_eq!(simplify("'a' < 'B' && x"), "'a' < 'B' && x");
+    assert_eq!(simplify("1 % 0 == 1 || x"), "1 % 0 == 1 || x");
+    assert_eq!(simplify("sha256('') == 'x' || 1 ${op} 2"), "sha256('') == 'x' || 1 ${op} 2");
+    assert_eq!(simplify("'a' == 'a' && (y || false) && 0 within_last 1d"), "y && 0 within_last 1d");
+  }
+
+  #[test]
+  fn test_between() {
+    let mut context = Context::new();
+    let options = EvalOptions::default();
+    let expr = parse_whole_boolean_expression("age between 18 and 65").unwrap();
+    for (age, expected) in [(17, false), (18, true), (40, true), (65, true), (66, false)] {
+      context.insert("age".to_string(), ContextValue::Integer(age));
+      assert_eq!(expr.evaluate(&EvalEnv::new(&context, &options)), Ok(expected), "age {}", age);
+    }
+    context.insert("age".to_string(), ContextValue::Float(65.5));
+    assert_eq!(expr.evaluate(&EvalEnv::new(&context, &options)), Ok(false));
+    assert_eq!(evaluate_str("18.0 between 18 and 65"), Ok(true));
+    assert_eq!(evaluate_str("2 between 1.5 and 2.5"), Ok(true));
+    // an inverted range is empty
+    assert_eq!(evaluate_str("40 between 65 and 18"), Ok(false));
+    assert_eq!(evaluate_str("65 between 65 and 65"), Ok(true));
+    context.insert("age".to_string(), ContextValue::String("40".to_string()));
+    assert!(matches!(expr.evaluate(&EvalEnv::new(&context, &options)), Err(EvalError::TypeMismatch(_))));
+    assert_display_round_trip("age between 18 and 65 && !(x between 1 and 2)", "age between 18 and 65 && !(x between 1 and 2)");
+  }
+
+  #[test]
+  fn test_quantifiers() {
+    assert_eq!(evaluate_str("50 > all (10,20,30)"), Ok(true));
+    assert_eq!(evaluate_str("15 > all (10,20,30)"), Ok(false));
+    assert_eq!(evaluate_str("15 > any (10, 20, 30)"), Ok(true));
+    assert_eq!(evaluate_str("5 > any (10, 20, 30)"), Ok(false));
+    assert_eq!(evaluate_str("'bob' =~ any ('^a', 'b$')"), Ok(true));
+    assert_eq!(evaluate_str("2 == all(2)"), Ok(true));
+    assert!(matches!(evaluate_str("1 > all (0, missing)"), Err(EvalError::UnknownIdentifier(_))));
+    assert!(parse_whole_boolean_expression("1 > all (0, 'a')").is_err());
+    let mut context = Context::new();
+    context.insert("all".to_string(), ContextValue::Integer(3));
+    let options = EvalOptions::default();
+    assert_eq!(parse_whole_boolean_expression("4 > all").unwrap().evaluate(&EvalEnv::new(&context, &options)), Ok(true));
+    let expression = parse_whole_boolean_expression("x >= all (1, y) && name =~ any ('a', 'b')").unwrap();
+    assert_eq!(expression.regex_literals(), vec!["a", "b"]);
+    assert!(expression.contains_identifier("y"));
+    assert!(!parse_whole_boolean_expression("x > all (1, 2)").unwrap().is_constant());
+    assert!(parse_whole_boolean_expression("3 > all (1, 2)").unwrap().is_constant());
+    assert_display_round_trip("x > all (1, 2) || x == any(3)", "x > all (1, 2) || x == any (3)");
+  }
+
+  #[test]
+  fn test_within() {
+    let now = 1_700_000_000;
+    let options = EvalOptions { now: Some(now), ..Default::default() };
+    let mut context = Context::new();
+    let last_week = parse_whole_boolean_expression("timestamp within_last 7d").unwrap();
+    let next_hour = parse_whole_boolean_expression("timestamp within_next 1h").unwrap();
+    let cases = [(now, true, true), (now - 3 * 86_400, true, false), (now - 7 * 86_400, true, false), (now - 7 * 86_400 - 1, false, false),
+      (now + 3_600, false, true), (now + 3_601, false, false)];
+    for (timestamp, in_last_week, in_next_hour) in cases {
+      context.insert("timestamp".to_string(), ContextValue::Integer(timestamp));
+      assert_eq!(last_week.evaluate(&EvalEnv::new(&context, &options)), Ok(in_last_week), "{}", timestamp);
+      assert_eq!(next_hour.evaluate(&EvalEnv::new(&context, &options)), Ok(in_next_hour), "{}", timestamp);
+    }
+    context.insert("timestamp".to_string(), ContextValue::String("yesterday".to_string()));
+    assert!(matches!(last_week.evaluate(&EvalEnv::new(&context, &options)), Err(EvalError::TypeMismatch(_))));
+    assert_display_round_trip("timestamp within_last 168h || !(timestamp within_next 90s)", "timestamp within_last 1w || !(timestamp within_next 90s)");
+    // durations longer than i64::MAX seconds don't wrap around to a negative window
+    let system_clock = EvalOptions::default();
+    context.insert("timestamp".to_string(), ContextValue::Integer(0));
+    let expression = parse_whole_boolean_expression("timestamp within_last 10000000000000000000s").unwrap();
+    assert_eq!(expression.evaluate(&EvalEnv::new(&context, &system_clock)), Ok(true));
+    context.insert("timestamp".to_string(), ContextValue::Integer(i64::MAX));
+    let expression = parse_whole_boolean_expression("timestamp within_next 10000000000000000000s").unwrap();
+    assert_eq!(expression.evaluate(&EvalEnv::new(&context, &system_clock)), Ok(true));
+  }
+
+  #[test]
+  fn test_sign_predicates() {
+    let mut context = Context::new();
+    let options = EvalOptions::default();
+    let values = [
+      (crate::ContextValue::Integer(3), [true, false, false]),
+      (crate::ContextValue::Integer(-3), [false, true, false]),
+      (crate::ContextValue::Integer(0), [false, false, true]),
+      (crate::ContextValue::Float(0.5), [true, false, false]),
+      (crate::ContextValue::Float(-0.5), [false, true, false]),
+      (crate::ContextValue::Float(0.0), [false, false, true]),
+    ];
+    for (value, expected) in values {
+      context.insert("delta".to_string(), value);
+      for (expr, expected) in ["delta is positive", "delta is negative", "delta is zero"].iter().zip(expected) {
+        let evaluated = parse_whole_boolean_expression(expr).unwrap().evaluate(&EvalEnv::new(&context, &options));
+        assert_eq!(evaluated, Ok(expected), "{} for {:?}", expr, context["delta"]);
+      }
+    }
+    context.insert("delta".to_string(), crate::ContextValue::String("1".to_string()));
+    let expr = parse_whole_boolean_expression("delta is positive").unwrap();
+    assert!(matches!(expr.evaluate(&EvalEnv::new(&context, &options)), Err(EvalError::TypeMismatch(_))));
+    assert_eq!(evaluate_str("2 is positive && 0.0 is zero"), Ok(true));
+    assert_display_round_trip("!(delta is zero) && delta is positive", "!(delta is zero) && delta is positive");
+  }
+
+  #[test]
+  fn test_comments() {
+    let expected = parse_whole_boolean_expression("a > 1 && b").unwrap();
+    assert_eq!(parse_whole_boolean_expression("a > 1 /* allow adults */ && b"), Ok(expected.clone()));
+    assert_eq!(parse_whole_boolean_expression("a > 1 # adults\n&& b # and b"), Ok(expected.clone()));
+    assert_eq!(parse_whole_boolean_expression("a > 1 // adults\n  && b // and b"), Ok(expected.clone()));
+    assert_eq!(parse_whole_boolean_expression("a > 1 && // require both\nb//"), Ok(expected.clone()));
+    assert_eq!(parse_whole_boolean_expression("/* multi\nline */ a > 1 &&/**/b"), Ok(expected));
+    let expr = parse_whole_boolean_expression("url == 'https://example.com/#top' // the start page").unwrap();
+    assert_eq!(expr.to_string(), "url == 'https://example.com/#top'");
+    let expr = parse_whole_boolean_expression("tag =~ '#[0-9]+ /* not a comment */'").unwrap();
+    assert_eq!(expr.to_string(), "tag =~ '#[0-9]+ /* not a comment */'");
+    assert_eq!(strip_comments("a /* é */ b").unwrap().len(), "a /* é */ b".len());
+    assert_eq!(parse_whole_boolean_expression("a /* b"), Err(EvalError::Parse("Unterminated block comment".to_string())));
+    assert_eq!(parse_whole_boolean_expression("a /* /* b */ */"), Err(EvalError::Parse("Nested block comments are not supported".to_string())));
+  }
+
+  #[test]
+  fn test_unary_precedence() {
+    let ident = |name: &str| Box::new(BooleanExpression::Identifier(Identifier::from(name)));
+    let not = |name: &str| Box::new(BooleanExpression::Unary(UnaryOperator::Not, ident(name)));
+    assert_eq!(parse_whole_boolean_expression("!a && b"), Ok(BooleanExpression::Chain(BinaryOperator::And, vec![*not("a"), *ident("b")])));
+    assert_eq!(parse_whole_boolean_expression("!a || !b"), Ok(BooleanExpression::Chain(BinaryOperator::Or, vec![*not("a"), *not("b")])));
+    assert_eq!(parse_whole_boolean_expression("!a == b"), Ok(BooleanExpression::Binary(not("a"), BinaryOperator::Equals, ident("b"))));
+    assert_eq!(parse_whole_boolean_expression("!!a"), Ok(BooleanExpression::Unary(UnaryOperator::Not, not("a"))));
+    assert!(parse_whole_boolean_expression("!a > 1").is_err());
+    let negated = parse_whole_boolean_expression("!(a == b)").unwrap();
+    assert!(matches!(negated, BooleanExpression::Unary(UnaryOperator::Not, value) if matches!(*value, BooleanExpression::NonBooleanExpression(_))));
+    assert_display_round_trip("!a == b", "!a == b");
+  }
+
+  #[test]
+  fn test_truth_table() {
+    let table = parse_whole_boolean_expression("a && (b || !a)").unwrap().truth_table().unwrap();
+    assert_eq!(table, vec![
+      (vec![false, false], false),
+      (vec![false, true], false),
+      (vec![true, false], false),
+      (vec![true, true], true),
+    ]);
+    let table = parse_whole_boolean_expression("b || 1 > 2").unwrap().truth_table().unwrap();
+    assert_eq!(table, vec![(vec![false], false), (vec![true], true)]);
+    assert_eq!(parse_whole_boolean_expression("true").unwrap().truth_table(), Ok(vec![(vec![], true)]));
+    let many = (b'a'..=b'q').map(|c| (c as char).to_string()).collect::<Vec<_>>().join(" && ");
+    assert!(matches!(parse_whole_boolean_expression(&many).unwrap().truth_table(), Err(EvalError::TooComplex(_))));
+    assert!(matches!(parse_whole_boolean_expression("a && x > 1").unwrap().truth_table(), Err(EvalError::UnknownIdentifier(_))));
+  }
+
+  #[test]
+  fn test_xor_truth_table() {
+    assert_eq!(evaluate_str("false ^^ false"), Ok(false));
+    assert_eq!(evaluate_str("false ^^ true"), Ok(true));
+    assert_eq!(evaluate_str("true ^^ false"), Ok(true));
+    assert_eq!(evaluate_str("true ^^ true"), Ok(false));
+  }
+
+  #[test]
+  fn test_implies_truth_table() {
+    assert_eq!(evaluate_str("false -> false"), Ok(true));
+    assert_eq!(evaluate_str("false -> true"), Ok(true));
+    assert_eq!(evaluate_str("true -> false"), Ok(false));
+    assert_eq!(evaluate_str("true -> true"), Ok(true));
+  }
+
+  #[test]
+  fn test_xor_implies_precedence() {
+    assert_eq!(
+      parse_whole_boolean_expression("a -> b -> c").unwrap(),
+      BooleanExpression::Chain(BinaryOperator::Implies, ["a", "b", "c"].map(|name| BooleanExpression::Identifier(Identifier::from(name))).to_vec())
+    );
+    // joined to the right, `false -> false -> false` is `false -> (false -> false)`
+    assert_eq!(evaluate_str("false -> false -> false"), Ok(true));
+    assert_eq!(evaluate_str("true -> true -> false"), Ok(false));
+    assert_eq!(evaluate_str("true -> false -> false"), Ok(true));
+    assert_eq!(evaluate_str("true ^^ true ^^ true"), Ok(true));
+    assert!(parse_whole_boolean_expression("a ^^ b && c").is_err());
+    assert!(parse_whole_boolean_expression("a -> b || c").is_err());
+    assert_eq!(evaluate_str("(true ^^ true) -> false"), Ok(true));
+    assert_display_round_trip("(a ^^ b) -> (c && d)", "(a ^^ b) -> (c && d)");
+  }
+
+  #[test]
+  fn test_deep_nesting_errors() {
+    let value = "(".repeat(10_000);
+    assert_eq!(
+      parse_whole_boolean_expression(&value),
+      Err(EvalError::Parse(format!("Expression is nested deeper than {} levels", MAX_DEPTH)))
+    );
+    let value = format!("{}true", "!".repeat(10_000));
+    assert!(parse_whole_boolean_expression(&value).is_err());
+    let value = format!("{}true{}", "(".repeat(10_000), ")".repeat(10_000));
+    assert!(parse_whole_boolean_expression(&value).is_err());
+    // a flat chain is not nested however long it is
+    let value = vec!["true"; 100_000].join(" && ");
+    let expression = parse_whole_boolean_expression(&value).unwrap();
+    assert_eq!(expression.depth(), 2);
+    let (context, options) = (Context::new(), EvalOptions::default());
+    assert_eq!(expression.evaluate(&EvalEnv::new(&context, &options)), Ok(true));
+    assert_eq!(expression.to_string(), value);
+    assert_eq!(expression.clone().simplify(), BooleanExpression::Boolean(true));
+    let value = vec!["a"; 129].join(" || ");
+    assert!(parse_whole_boolean_expression(&value).is_ok());
+    let value = format!("{}1 == 1", "a + ".repeat(100_000));
+    assert_eq!(
+      parse_whole_boolean_expression(&value),
+      Err(EvalError::Parse(format!("Expression is nested deeper than {} levels", MAX_DEPTH)))
+    );
+    let value = format!("{}1 == 1", "a ** ".repeat(20_000));
+    assert_eq!(
+      parse_whole_boolean_expression(&value),
+      Err(EvalError::Parse(format!("Expression is nested deeper than {} levels", MAX_DEPTH)))
+    );
+    let value = format!("{}1 == 1", "a ?: ".repeat(5000));
+    assert_eq!(
+      parse_whole_boolean_expression(&value),
+      Err(EvalError::Parse(format!("Expression is nested deeper than {} levels", MAX_DEPTH)))
+    );
+  }
+
+  #[test]
+  fn test_nesting_up_to_max_depth() {
+    let value = format!("{}true{}", "(".repeat(MAX_DEPTH - 1), ")".repeat(MAX_DEPTH - 1));
+    assert_eq!(parse_whole_boolean_expression(&value), Ok(BooleanExpression::Boolean(true)));
+    let value = format!("{}a && b{}", "(".repeat(MAX_DEPTH - 2), ")".repeat(MAX_DEPTH - 2));
+    assert!(parse_whole_boolean_expression(&value).is_ok());
+    let value = vec!["a"; MAX_DEPTH].join(" && ");
+    assert!(parse_whole_boolean_expression(&value).is_ok());
+    let value = format!("{}1 == 1", "a ?: ".repeat(MAX_DEPTH - 2));
+    assert!(parse_whole_boolean_expression(&value).is_ok());
+    let value = format!("{}1 == 1", "a * ".repeat(MAX_DEPTH - 2));
+    assert!(parse_whole_boolean_expression(&value).is_ok());
+    let value = format!("{}1 == 1", "a ** ".repeat(MAX_DEPTH - 2));
+    assert!(parse_whole_boolean_expression(&value).is_ok());
   }
 }
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, fmt, panic::{self, AssertUnwindSafe}};
+
+use crate::{error::message, value::{Identifier, Value}, EvalEnv, EvalError, ParseOptions};
+
+type Function = Box<dyn Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync>;
+type Operator = Box<dyn Fn(&Value, &Value) -> Result<bool, EvalError> + Send + Sync>;
+
+/// Functions provided by the host which expressions can call in addition to the builtin ones.
+/// A registered function replaces a builtin function with the same name.
+/// Custom binary operators are registered here as well, expressions using them are parsed with parse_options().
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{CompiledExpression, Context, EvalOptions, FunctionRegistry, Value};
+///
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("answer", |_| Ok(Value::IntegerLiteral(42)));
+/// let expr = CompiledExpression::compile("answer() == 42").unwrap();
+/// assert_eq!(expr.evaluate_with_functions(&Context::new(), &functions, &EvalOptions::default()), Ok(true));
+/// ```
+#[derive(Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Function>,
+    operators: HashMap<String, Operator>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the function under the name, a function registered before with the same name is replaced.
+    pub fn register<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync + 'static,
+    {
+        self.functions.insert(name.to_string(), Box::new(function));
+    }
+
+    /// Registers a binary operator written as the keyword name between its operands, e.g. `ip within '10.0.0.0/8'`.
+    /// The operands are resolved from the context before the operator is called with them.
+    /// An operator registered before with the same name is replaced.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::{CompiledExpression, Context, ContextValue, EvalOptions, FunctionRegistry, Value};
+    ///
+    /// let mut functions = FunctionRegistry::new();
+    /// functions.register_operator("starts", |lhs, rhs| match (lhs, rhs) {
+    ///     (Value::StringLiteral(lhs), Value::StringLiteral(rhs)) => Ok(lhs.starts_with(rhs.as_str())),
+    ///     _ => Ok(false),
+    /// });
+    /// let expr = CompiledExpression::compile_with_parse_options("name starts 'a'", &functions.parse_options()).unwrap();
+    /// let mut context = Context::new();
+    /// context.insert("name".to_string(), ContextValue::String("ada".to_string()));
+    /// assert_eq!(expr.evaluate_with_functions(&context, &functions, &EvalOptions::default()), Ok(true));
+    /// ```
+    pub fn register_operator<F>(&mut self, name: &str, operator: F)
+    where
+        F: Fn(&Value, &Value) -> Result<bool, EvalError> + Send + Sync + 'static,
+    {
+        self.operators.insert(name.to_string(), Box::new(operator));
+    }
+
+    /// The parse options which declare the keywords of the registered operators.
+    pub fn parse_options(&self) -> ParseOptions {
+        let mut custom_operators: Vec<String> = self.operators.keys().cloned().collect();
+        custom_operators.sort();
+        ParseOptions { custom_operators, ..Default::default() }
+    }
+
+    /// Calls the function with the name, returns None if it isn't registered.
+    /// With catch_panics a panic of the function is returned as EvalError::FunctionPanic.
+    pub(crate) fn call(&self, name: &Identifier, args: &[Value], catch_panics: bool) -> Option<Result<Value, EvalError>> {
+        let function = self.functions.get(name.name())?;
+        if !catch_panics {
+            return Some(function(args));
+        }
+        Some(panic::catch_unwind(AssertUnwindSafe(|| function(args)))
+            .unwrap_or_else(|_| Err(EvalError::FunctionPanic { name: name.name().to_string() })))
+    }
+
+    /// Applies the operator with the name, returns None if it isn't registered. Panics are handled like in call().
+    pub(crate) fn call_operator(&self, name: &str, lhs: &Value, rhs: &Value, catch_panics: bool) -> Option<Result<bool, EvalError>> {
+        let operator = self.operators.get(name)?;
+        if !catch_panics {
+            return Some(operator(lhs, rhs));
+        }
+        Some(panic::catch_unwind(AssertUnwindSafe(|| operator(lhs, rhs)))
+            .unwrap_or_else(|_| Err(EvalError::FunctionPanic { name: name.to_string() })))
+    }
+}
+
+impl fmt::Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.functions.keys().collect();
+        names.sort();
+        let mut operators: Vec<_> = self.operators.keys().collect();
+        operators.sort();
+        f.debug_struct("FunctionRegistry").field("functions", &names).field("operators", &operators).finish()
+    }
+}
+
+/// Calls a builtin function with arguments which are already resolved from the context.
+pub(crate) fn call(name: &Identifier, args: Vec<Value>, env: &EvalEnv) -> Result<Value, EvalError> {
+    match name.name() {
+        "md5" | "sha1" | "sha256" => hash(name.name(), string_argument(name, args)?).map(Value::StringLiteral),
+        "replace" => replace(name, args, env).map(Value::StringLiteral),
+        "now" if args.is_empty() => Ok(Value::DateTime(env.now())),
+        "now" => Err(EvalError::TypeMismatch(message(|| format!("Function now expects no arguments, got {}", args.len())))),
+        _ => Err(EvalError::UnknownIdentifier(message(|| format!("Function not found: {}", name)))),
+    }
+}
+
+fn string_argument(name: &Identifier, args: Vec<Value>) -> Result<String, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([Value::StringLiteral(s)]) => Ok(s),
+        Ok([value]) => Err(EvalError::TypeMismatch(message(|| format!("Function {} expects a string: {}", name, value)))),
+        Err(args) => Err(EvalError::TypeMismatch(message(|| format!("Function {} expects 1 argument, got {}", name, args.len())))),
+    }
+}
+
+/// Replaces all matches of the pattern in the string, `$1` in the replacement refers to the first capture group.
+/// A literal pattern was compiled with the expression, other patterns are compiled on every call.
+fn replace(name: &Identifier, args: Vec<Value>, env: &EvalEnv) -> Result<String, EvalError> {
+    match <[Value; 3]>::try_from(args) {
+        Ok([Value::StringLiteral(input), Value::StringLiteral(pattern), Value::StringLiteral(replacement)]) =>
+            Ok(env.regex(&pattern)?.replace_all(&input, replacement.as_str()).into_owned()),
+        Ok(args) => {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Err(EvalError::TypeMismatch(message(|| format!("Function {} expects strings: {}({})", name, name, args.join(", ")))))
+        }
+        Err(args) => Err(EvalError::TypeMismatch(message(|| format!("Function {} expects 3 arguments, got {}", name, args.len())))),
+    }
+}
+
+/// Returns the lowercase hex digest of the string.
+#[cfg(feature = "hash")]
+fn hash(algorithm: &str, input: String) -> Result<String, EvalError> {
+    use sha2::Digest;
+
+    let digest = match algorithm {
+        "md5" => md5::Md5::digest(input).to_vec(),
+        "sha1" => sha1::Sha1::digest(input).to_vec(),
+        _ => sha2::Sha256::digest(input).to_vec(),
+    };
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(not(feature = "hash"))]
+fn hash(algorithm: &str, _input: String) -> Result<String, EvalError> {
+    Err(EvalError::InvalidOption(message(|| format!("Function {} requires the hash feature", algorithm))))
+}
+
+#[cfg(feature = "hash")]
+#[test]
+fn test_hash() {
+    let (context, options) = (crate::Context::new(), crate::EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
+    let call_with = |name: &str, arg: Value| call(&Identifier::from(name), vec![arg], &env);
+    let empty = || Value::StringLiteral(String::new());
+    assert_eq!(call_with("sha256", empty()), Ok(Value::StringLiteral("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string())));
+    assert_eq!(call_with("sha1", empty()), Ok(Value::StringLiteral("da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string())));
+    assert_eq!(call_with("md5", empty()), Ok(Value::StringLiteral("d41d8cd98f00b204e9800998ecf8427e".to_string())));
+    assert!(matches!(call_with("sha256", Value::IntegerLiteral(1)), Err(EvalError::TypeMismatch(_))));
+    assert!(matches!(call(&Identifier::from("sha256"), vec![], &env), Err(EvalError::TypeMismatch(_))));
+}
+
+#[test]
+fn test_catch_panics() {
+    use crate::{CompiledExpression, Context, EvalOptions};
+
+    let mut functions = FunctionRegistry::new();
+    functions.register("boom", |_| panic!("boom"));
+    let expr = CompiledExpression::compile("boom() == 1").unwrap();
+    let options = EvalOptions { catch_panics: true, ..Default::default() };
+    assert_eq!(
+        expr.evaluate_with_functions(&Context::new(), &functions, &options),
+        Err(EvalError::FunctionPanic { name: "boom".to_string() })
+    );
+    let result = panic::catch_unwind(AssertUnwindSafe(|| expr.evaluate_with_functions(&Context::new(), &functions, &EvalOptions::default())));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_replace() {
+    use crate::{CompiledExpression, Context, ContextValue};
+
+    let mut context = Context::new();
+    context.insert("phone".to_string(), ContextValue::String("(555) 12-34".to_string()));
+    context.insert("count".to_string(), ContextValue::Integer(3));
+    let evaluate = |expr: &str| CompiledExpression::compile(expr)?.evaluate(&context);
+    assert_eq!(evaluate("replace(phone, '[^0-9]', '') == '5551234'"), Ok(true));
+    assert_eq!(evaluate("replace(phone, '[a-z]', '') == phone"), Ok(true));
+    assert_eq!(evaluate("replace('a-b', '(\\w)-(\\w)', '$2-$1') == 'b-a'"), Ok(true));
+    assert!(matches!(evaluate("replace(count, '[^0-9]', '') == '3'"), Err(EvalError::TypeMismatch(_))));
+    assert!(matches!(evaluate("replace(phone, '[0-9', '') == ''"), Err(EvalError::InvalidRegex(_))));
+    assert!(matches!(evaluate("replace(phone, '[0-9]') == ''"), Err(EvalError::TypeMismatch(_))));
+}
+
+#[test]
+fn test_unknown_function() {
+    let (context, options) = (crate::Context::new(), crate::EvalOptions::default());
+    assert_eq!(
+        call(&Identifier::from("nope"), vec![], &EvalEnv::new(&context, &options)),
+        Err(EvalError::UnknownIdentifier("Function not found: nope".to_string()))
+    );
+}
+
+#[test]
+fn test_custom_operator() {
+    use std::net::Ipv4Addr;
+    use crate::{CompiledExpression, Context, ContextValue, EvalOptions};
+
+    let mut functions = FunctionRegistry::new();
+    functions.register_operator("within", |ip, cidr| {
+        let (Value::StringLiteral(ip), Value::StringLiteral(cidr)) = (ip, cidr) else {
+            return Err(EvalError::TypeMismatch(format!("Operator within expects strings: {} within {}", ip, cidr)));
+        };
+        let invalid = || EvalError::TypeMismatch(format!("Invalid address: {} within {}", ip, cidr));
+        let (network, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+        let (ip, network) = (ip.parse::<Ipv4Addr>().map_err(|_| invalid())?, network.parse::<Ipv4Addr>().map_err(|_| invalid())?);
+        let mask = u32::MAX.checked_shl(32 - prefix.parse::<u32>().map_err(|_| invalid())?).unwrap_or(0);
+        Ok(u32::from(ip) & mask == u32::from(network) & mask)
+    });
+    let options = functions.parse_options();
+    let expr = CompiledExpression::compile_with_parse_options("ip within '10.0.0.0/8' && port == 22", &options).unwrap();
+    let mut context = Context::new();
+    context.insert("ip".to_string(), ContextValue::String("10.1.2.3".to_string()));
+    context.insert("port".to_string(), ContextValue::Integer(22));
+    assert_eq!(expr.evaluate_with_functions(&context, &functions, &EvalOptions::default()), Ok(true));
+    context.insert("ip".to_string(), ContextValue::String("192.168.0.1".to_string()));
+    assert_eq!(expr.evaluate_with_functions(&context, &functions, &EvalOptions::default()), Ok(false));
+    context.insert("ip".to_string(), ContextValue::Integer(1));
+    assert!(matches!(expr.evaluate_with_functions(&context, &functions, &EvalOptions::default()), Err(EvalError::TypeMismatch(_))));
+    assert_eq!(
+        expr.evaluate(&context),
+        Err(EvalError::InvalidOperator("Operator not found: within".to_string()))
+    );
+
+    let parsed = crate::value::with_parse_options(&options, || crate::expression::parse_whole_boolean_expression("ip within 'a'")).unwrap();
+    assert_eq!(parsed.to_string(), "ip within 'a'");
+    assert!(!parsed.is_constant());
+    assert!(CompiledExpression::compile("ip within '10.0.0.0/8'").is_err());
+    assert!(CompiledExpression::compile_with_parse_options("ip within_last 7d", &options).is_ok());
+}
+
+#[test]
+fn test_keywords_are_no_custom_operators() {
+    use crate::CompiledExpression;
+
+    let mut functions = FunctionRegistry::new();
+    functions.register_operator("and", |_, _| Ok(true));
+    assert_eq!(
+        CompiledExpression::compile_with_parse_options("a and b", &functions.parse_options()).unwrap_err(),
+        EvalError::InvalidOption("Custom operator can not be a builtin keyword: and".to_string())
+    );
+    let options = ParseOptions { custom_operators: vec!["in".to_string()], ..Default::default() };
+    assert!(matches!(CompiledExpression::compile_with_parse_options("x in (1, 2)", &options), Err(EvalError::InvalidOption(_))));
+    let options = ParseOptions { custom_operators: vec!["Yes".to_string()], ..ParseOptions::lenient() };
+    assert_eq!(
+        CompiledExpression::compile_with_parse_options("a Yes b", &options).unwrap_err(),
+        EvalError::InvalidOption("Custom operator can not be a boolean keyword: Yes".to_string())
+    );
+    let options = ParseOptions { custom_operators: vec!["true".to_string()], ..Default::default() };
+    assert!(matches!(CompiledExpression::compile_with_parse_options("a true b", &options), Err(EvalError::InvalidOption(_))));
+}
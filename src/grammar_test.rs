@@ -0,0 +1,288 @@
+//! A property test of the grammar: random syntax trees are displayed, parsed again and have to give the same tree.
+//! The generators only build trees the parser can produce, e.g. without groups and with chains as BooleanExpression::Chain,
+//! and the shrinkers keep that property so a failing tree is reduced to a small one which still fails.
+//! Literals cover the whole range of their type, like negative integers, floats of any magnitude and strings with quotes.
+
+use std::time::Duration;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{datetime::parse_datetime, expression::parse_whole_boolean_expression, non_boolean_expression::parse_whole_value, value::with_parse_options, BinaryOperator, BooleanExpression, Identifier, NonBooleanExpression, ParseOptions, Predicate, TimeWindow, UnaryOperator, Value};
+
+const NAMES: [&str; 6] = ["a", "count", "user.name", "_id", "x2", "order total"];
+const FUNCTIONS: [&str; 2] = ["lower", "len"];
+const WORDS: [&str; 8] = ["", "abc", "a b", "*.rs", "O'Brien", "'", "''", "// not a comment"];
+/// The custom operators the expressions are parsed with.
+const CUSTOM_OPERATORS: [&str; 2] = ["near", "overlaps"];
+const COMPARISONS: [BinaryOperator; 6] = [
+    BinaryOperator::Equals,
+    BinaryOperator::NotEquals,
+    BinaryOperator::LessThan,
+    BinaryOperator::GreaterThan,
+    BinaryOperator::LessEqual,
+    BinaryOperator::GreaterEqual,
+];
+const PATTERNS: [BinaryOperator; 3] = [BinaryOperator::RegexMatch, BinaryOperator::FullMatch, BinaryOperator::Like];
+const ARITHMETIC: [BinaryOperator; 5] = [BinaryOperator::Add, BinaryOperator::Subtract, BinaryOperator::Multiply, BinaryOperator::Modulo, BinaryOperator::Power];
+const CHAINS: [BinaryOperator; 4] = [BinaryOperator::And, BinaryOperator::Or, BinaryOperator::Xor, BinaryOperator::Implies];
+/// The first and last second of the years with four digits, the range of RFC 3339 timestamps.
+const DATETIMES: std::ops::RangeInclusive<i64> = -62_135_596_800..=253_402_300_799;
+
+fn pick<T: Clone>(rng: &mut StdRng, items: &[T]) -> T {
+    items.choose(rng).unwrap().clone()
+}
+
+fn identifier(rng: &mut StdRng) -> Identifier {
+    Identifier::from(pick(rng, &NAMES))
+}
+
+/// A literal of any type which can be the argument of a call.
+fn literal(rng: &mut StdRng) -> Value {
+    match rng.gen_range(0..3) {
+        0 => integer(rng),
+        1 => Value::FloatLiteral(float(rng)),
+        _ => string(rng),
+    }
+}
+
+/// A small integer or any one, including the extremes.
+fn integer(rng: &mut StdRng) -> Value {
+    Value::IntegerLiteral(match rng.gen_range(0..4) {
+        0 => rng.gen(),
+        1 => pick(rng, &[i64::MIN, i64::MAX, -1]),
+        _ => rng.gen_range(-1000..1000),
+    })
+}
+
+/// A finite float of any magnitude, from subnormals to f64::MAX, with either sign.
+fn float(rng: &mut StdRng) -> f64 {
+    match rng.gen_range(0..4) {
+        0 => std::iter::repeat_with(|| f64::from_bits(rng.gen())).find(|f| f.is_finite()).unwrap(),
+        1 => rng.gen_range(-1.0..1.0) * 10f64.powi(rng.gen_range(-320..=308)),
+        2 => pick(rng, &[f64::MAX, f64::MIN, f64::MIN_POSITIVE, f64::EPSILON, 5e-324, -0.0, 0.1 + 0.2]),
+        _ => rng.gen_range(-40..40) as f64 / 4.0,
+    }
+}
+
+/// A literal of any type which can be an operand of a comparison or an element of a list.
+fn constant(rng: &mut StdRng) -> Value {
+    #[cfg(feature = "decimal")]
+    if rng.gen_range(0..4) == 0 {
+        return Value::DecimalLiteral(rust_decimal::Decimal::new(rng.gen(), rng.gen_range(0..=28)));
+    }
+    literal(rng)
+}
+
+fn string(rng: &mut StdRng) -> Value {
+    Value::StringLiteral(pick(rng, &WORDS).to_string())
+}
+
+/// A value which isn't a literal: an identifier, an element of a list or a function call.
+fn term(rng: &mut StdRng) -> Value {
+    match rng.gen_range(0..4) {
+        0 => Value::Index(Identifier::from(pick(rng, &NAMES[..5])), rng.gen_range(-2..3)),
+        1 => {
+            let args = (0..rng.gen_range(0..3)).map(|_| if rng.gen() { Value::Identifier(identifier(rng)) } else { literal(rng) }).collect();
+            Value::Call(Identifier::from(pick(rng, &FUNCTIONS)), args)
+        }
+        _ => Value::Identifier(identifier(rng)),
+    }
+}
+
+/// A term or a calculation with a term, the operand of predicates, `between` and time windows.
+fn arithmetic(rng: &mut StdRng) -> Value {
+    match rng.gen_range(0..3) {
+        0 => Value::Arithmetic(Box::new(term(rng)), pick(rng, &ARITHMETIC), Box::new(integer(rng))),
+        _ => term(rng),
+    }
+}
+
+/// An arithmetic value or a fallback to a literal, the left operand of a comparison.
+fn operand(rng: &mut StdRng) -> Value {
+    match rng.gen_range(0..4) {
+        0 => Value::Fallback(Box::new(term(rng)), Box::new(constant(rng))),
+        _ => arithmetic(rng),
+    }
+}
+
+fn list(rng: &mut StdRng, element: fn(&mut StdRng) -> Value) -> Value {
+    Value::List((0..rng.gen_range(1..4)).map(|_| element(rng)).collect())
+}
+
+/// A comparison whose left side is no literal, so the parser never rejects the types of its literals.
+fn comparison(rng: &mut StdRng) -> NonBooleanExpression {
+    let lhs = operand(rng);
+    match rng.gen_range(0..8) {
+        0 => NonBooleanExpression(lhs, pick(rng, &PATTERNS), if rng.gen() { string(rng) } else { term(rng) }),
+        1 => NonBooleanExpression(lhs, BinaryOperator::In, list(rng, constant)),
+        2 => NonBooleanExpression(lhs, BinaryOperator::Contains, if rng.gen() { constant(rng) } else { term(rng) }),
+        3 => {
+            let (op, items) = match rng.gen() {
+                true => (pick(rng, &PATTERNS), list(rng, string)),
+                false => (pick(rng, &COMPARISONS), list(rng, constant)),
+            };
+            let quantified = if rng.gen() { BinaryOperator::All(Box::new(op)) } else { BinaryOperator::Any(Box::new(op)) };
+            NonBooleanExpression(lhs, quantified, items)
+        }
+        4 => NonBooleanExpression(lhs, BinaryOperator::Dynamic(identifier(rng)), if rng.gen() { constant(rng) } else { operand(rng) }),
+        5 => NonBooleanExpression(lhs, BinaryOperator::Custom(pick(rng, &CUSTOM_OPERATORS).to_string()), if rng.gen() { constant(rng) } else { operand(rng) }),
+        _ => NonBooleanExpression(lhs, pick(rng, &COMPARISONS), if rng.gen() { constant(rng) } else { operand(rng) }),
+    }
+}
+
+/// A boolean expression with at most depth levels of unary and binary operators.
+pub(crate) fn boolean(rng: &mut StdRng, depth: usize) -> BooleanExpression {
+    match rng.gen_range(0..if depth == 0 { 6 } else { 10 }) {
+        0 => BooleanExpression::Identifier(identifier(rng)),
+        1 => BooleanExpression::Boolean(rng.gen()),
+        2 => {
+            let predicate = pick(rng, &[Predicate::Positive, Predicate::Negative, Predicate::Zero]);
+            BooleanExpression::Predicate(arithmetic(rng), predicate)
+        }
+        3 => BooleanExpression::Between(arithmetic(rng), arithmetic(rng), arithmetic(rng)),
+        4 => {
+            let duration = Duration::from_secs(rng.gen_range(0..1_000_000));
+            BooleanExpression::Within(term(rng), if rng.gen() { TimeWindow::Last(duration) } else { TimeWindow::Next(duration) })
+        }
+        5 => BooleanExpression::NonBooleanExpression(comparison(rng)),
+        6 => BooleanExpression::Unary(UnaryOperator::Not, Box::new(boolean(rng, depth - 1))),
+        7 => {
+            // a comparison of two identifiers is parsed as a NonBooleanExpression
+            let (lhs, rhs) = (boolean(rng, depth - 1), boolean(rng, depth - 1));
+            let rhs = match (&lhs, rhs) {
+                (BooleanExpression::Identifier(_), BooleanExpression::Identifier(_)) => BooleanExpression::Boolean(rng.gen()),
+                (_, rhs) => rhs,
+            };
+            BooleanExpression::Binary(Box::new(lhs), pick(rng, &COMPARISONS), Box::new(rhs))
+        }
+        _ => BooleanExpression::Chain(pick(rng, &CHAINS), (0..rng.gen_range(2..5)).map(|_| boolean(rng, depth - 1)).collect()),
+    }
+}
+
+/// Simpler values which can take the place of the value, a literal becomes a literal of the same type.
+fn shrink_value(value: &Value) -> Vec<Value> {
+    let a = Value::Identifier(Identifier::from("a"));
+    match value {
+        Value::Identifier(ident) if ident.name() == "a" => vec![],
+        Value::IntegerLiteral(0) | Value::FloatLiteral(0.0) => vec![],
+        Value::StringLiteral(s) if s.is_empty() => vec![],
+        Value::IntegerLiteral(_) => vec![Value::IntegerLiteral(0)],
+        Value::FloatLiteral(_) => vec![Value::FloatLiteral(0.0)],
+        Value::StringLiteral(_) => vec![Value::StringLiteral(String::new())],
+        Value::List(items) if items.len() > 1 => vec![Value::List(items[..1].to_vec()), Value::List(items[1..].to_vec())],
+        Value::List(items) => shrink_value(&items[0]).into_iter().map(|item| Value::List(vec![item])).collect(),
+        Value::Fallback(lhs, _) | Value::Arithmetic(lhs, _, _) => vec![a, (**lhs).clone()],
+        Value::Call(name, args) if !args.is_empty() => vec![a, Value::Call(name.clone(), args[1..].to_vec())],
+        _ => vec![a],
+    }
+}
+
+/// Smaller expressions which can take the place of the expression: its operands, or the expression with a shrunk operand.
+fn shrink_boolean(expression: &BooleanExpression) -> Vec<BooleanExpression> {
+    let a = BooleanExpression::Identifier(Identifier::from("a"));
+    let mut candidates = vec![];
+    match expression {
+        BooleanExpression::Identifier(ident) if ident.name() == "a" => {}
+        BooleanExpression::Identifier(_) | BooleanExpression::Boolean(_) => candidates.push(a),
+        BooleanExpression::Binary(lhs, op, rhs) => {
+            candidates.extend([(**lhs).clone(), (**rhs).clone()]);
+            candidates.extend(shrink_boolean(lhs).into_iter().map(|lhs| BooleanExpression::Binary(Box::new(lhs), op.clone(), rhs.clone())));
+            candidates.extend(shrink_boolean(rhs).into_iter().map(|rhs| BooleanExpression::Binary(lhs.clone(), op.clone(), Box::new(rhs))));
+            candidates.retain(|candidate| !matches!(candidate,
+                BooleanExpression::Binary(lhs, _, rhs) if matches!((&**lhs, &**rhs), (BooleanExpression::Identifier(_), BooleanExpression::Identifier(_)))));
+        }
+        BooleanExpression::Chain(op, operands) => {
+            candidates.extend(operands.iter().cloned());
+            if operands.len() > 2 {
+                candidates.extend((0..operands.len()).map(|index| {
+                    let mut operands = operands.clone();
+                    operands.remove(index);
+                    BooleanExpression::Chain(op.clone(), operands)
+                }));
+            }
+            for (index, operand) in operands.iter().enumerate() {
+                candidates.extend(shrink_boolean(operand).into_iter().map(|operand| {
+                    let mut operands = operands.clone();
+                    operands[index] = operand;
+                    BooleanExpression::Chain(op.clone(), operands)
+                }));
+            }
+        }
+        BooleanExpression::Unary(op, value) => {
+            candidates.push((**value).clone());
+            candidates.extend(shrink_boolean(value).into_iter().map(|value| BooleanExpression::Unary(op.clone(), Box::new(value))));
+        }
+        BooleanExpression::NonBooleanExpression(NonBooleanExpression(lhs, op, rhs)) => {
+            candidates.push(a);
+            // literals on the right keep their type, so a shrunk comparison is still accepted by the parser
+            candidates.extend(shrink_value(lhs).into_iter().map(|lhs| BooleanExpression::NonBooleanExpression(NonBooleanExpression(lhs, op.clone(), rhs.clone()))));
+            candidates.extend(shrink_value(rhs).into_iter().map(|rhs| BooleanExpression::NonBooleanExpression(NonBooleanExpression(lhs.clone(), op.clone(), rhs))));
+        }
+        BooleanExpression::Predicate(value, predicate) => {
+            candidates.push(a);
+            candidates.extend(shrink_value(value).into_iter().map(|value| BooleanExpression::Predicate(value, predicate.clone())));
+        }
+        BooleanExpression::Between(value, lower, upper) => {
+            candidates.push(a);
+            candidates.extend(shrink_value(value).into_iter().map(|value| BooleanExpression::Between(value, lower.clone(), upper.clone())));
+            candidates.extend(shrink_value(lower).into_iter().map(|lower| BooleanExpression::Between(value.clone(), lower, upper.clone())));
+            candidates.extend(shrink_value(upper).into_iter().map(|upper| BooleanExpression::Between(value.clone(), lower.clone(), upper)));
+        }
+        BooleanExpression::Within(value, window) => {
+            candidates.push(a);
+            candidates.extend(shrink_value(value).into_iter().map(|value| BooleanExpression::Within(value, window.clone())));
+        }
+        BooleanExpression::Group(value) => candidates.push((**value).clone()),
+    }
+    candidates
+}
+
+/// Replaces the failing expression with smaller failing ones until none of the candidates fails anymore.
+fn shrink(mut expression: BooleanExpression, fails: impl Fn(&BooleanExpression) -> bool) -> BooleanExpression {
+    while let Some(smaller) = shrink_boolean(&expression).into_iter().find(&fails) {
+        expression = smaller;
+    }
+    expression
+}
+
+fn parse(input: &str) -> Result<BooleanExpression, crate::EvalError> {
+    let options = ParseOptions { custom_operators: CUSTOM_OPERATORS.map(String::from).to_vec(), ..Default::default() };
+    with_parse_options(&options, || parse_whole_boolean_expression(input))
+}
+
+fn round_trips(expression: &BooleanExpression) -> bool {
+    parse(&expression.to_string()).as_ref() == Ok(expression)
+}
+
+#[test]
+fn display_parse_round_trip() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for case in 0..2000 {
+        let expression = boolean(&mut rng, case % 6);
+        if !round_trips(&expression) {
+            let minimal = shrink(expression, |expression| !round_trips(expression));
+            panic!("{} does not parse as {:?}: {:?}", minimal, minimal, parse(&minimal.to_string()));
+        }
+    }
+}
+
+/// The parser has no datetime literals, a datetime is displayed as a string which is parsed as the same point in time.
+#[test]
+fn datetime_display_round_trip() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for timestamp in [*DATETIMES.start(), 0, *DATETIMES.end()].into_iter().chain((0..1000).map(|_| rng.gen_range(DATETIMES))) {
+        let displayed = Value::DateTime(timestamp).to_string();
+        let Ok(Some(Value::StringLiteral(s))) = parse_whole_value(&displayed) else { panic!("{} is not a string", displayed) };
+        assert_eq!(parse_datetime(&s), Some(timestamp), "{}", displayed);
+    }
+}
+
+#[test]
+fn test_shrink() {
+    let between = |expression: &BooleanExpression| expression.to_string().contains("between");
+    let mut rng = StdRng::seed_from_u64(1);
+    let expression = std::iter::repeat_with(|| boolean(&mut rng, 4)).find(|expression| between(expression) && expression.node_count() > 3).unwrap();
+    let minimal = shrink(expression, between);
+    assert_eq!(minimal.to_string(), "a between a and a");
+    assert!(round_trips(&minimal));
+}
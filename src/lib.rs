@@ -1,11 +1,83 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, ops::Deref};
 
 mod operator;
 mod expression;
 mod value;
 mod non_boolean_expression;
+mod error;
+mod options;
+mod collation;
+mod normalization;
+mod env;
+mod compiled;
+mod context;
+mod decision_table;
+mod function;
+mod schema;
+mod completion;
+mod datetime;
+#[cfg(test)]
+mod grammar_test;
+/// The commonly used functions and types, import them all with `use logical_expr::prelude::*;`.
+///
+/// ```rust
+/// use logical_expr::prelude::*;
+///
+/// let mut context = Context::new();
+/// context.insert("count".to_string(), ContextValue::Integer(3));
+/// assert_eq!(evaluate("count > 1", &context), Ok(true));
+///
+/// let expr = CompiledExpression::compile("count > 1 && !done").unwrap();
+/// assert!(matches!(expr.evaluate(&context), Err(EvalError::UnknownIdentifier(_))));
+/// let options = EvalOptions { loose_equality: true, ..Default::default() };
+/// assert_eq!(evaluate_with_options("count == 'a'", &context, &options), Ok(false));
+///
+/// let tree: BooleanExpression = parse("count > 1").unwrap();
+/// assert_eq!(tree, BooleanExpression::NonBooleanExpression(NonBooleanExpression(
+///     Value::Identifier(Identifier::from("count")),
+///     BinaryOperator::GreaterThan,
+///     Value::IntegerLiteral(1),
+/// )));
+/// ```
+pub mod prelude;
 
-use expression::BooleanExpression;
+pub use expression::BooleanExpression;
+pub use non_boolean_expression::NonBooleanExpression;
+pub use operator::{BinaryOperator, Predicate, TimeWindow, UnaryOperator};
+pub use value::{Identifier, Value};
+pub use error::{EvalError, EvalErrorCode};
+pub use options::{EvalOptions, ParseOptions, RegexProvider};
+pub use compiled::{CompiledExpression, CompilerContext};
+pub use context::{ContextProvider, ResolvedContext};
+pub use decision_table::DecisionTable;
+pub use function::FunctionRegistry;
+pub use schema::ValueType;
+pub use completion::{completions_at, Completion};
+/// Derives `to_context(&self) -> Context` for a struct, requires the `derive` feature.
+///
+/// # Examples
+/// ```rust
+/// # #[cfg(feature = "derive")] {
+/// use logical_expr::{evaluate, IntoContext};
+///
+/// #[derive(IntoContext)]
+/// struct User {
+///     name: String,
+///     age: i64,
+/// }
+///
+/// let user = User { name: "bob".to_string(), age: 20 };
+/// assert_eq!(evaluate("age > 18 && name == 'bob'", &user.to_context()), Ok(true));
+/// # }
+/// ```
+#[cfg(feature = "derive")]
+pub use logical_expr_derive::IntoContext;
+
+// the code generated by IntoContext refers to this crate by its name, also in the tests of this crate
+#[cfg(all(test, feature = "derive"))]
+extern crate self as logical_expr;
+use context::{FnContext, RecordingContext};
+use env::EvalEnv;
 
 // todo
 // allow for a && b && c instead of (a && b) && c
@@ -14,6 +86,7 @@ use expression::BooleanExpression;
 
 /// # Introduction
 /// This functions sits at the core of the library. It takes an expression as a string and returns a boolean.
+/// The context is usually a Context, any other ContextProvider can be used instead.
 /// 
 /// # Examples
 /// ```rust
@@ -29,54 +102,461 @@ use expression::BooleanExpression;
 /// # Accepted Grammar of &str is:  
 /// ```markdown
 ///  boolean_expression  
-///     boolean_value || boolean_vale || .. || boolean_value  
-///     boolean_value && boolean_vale && .. && boolean_value  
+///     boolean_equality || boolean_equality || .. || boolean_equality  
+///     boolean_equality && boolean_equality && .. && boolean_equality  
+///     boolean_equality ^^ boolean_equality ^^ .. ^^ boolean_equality  
+///     boolean_equality -> boolean_equality -> .. -> boolean_equality  // a -> b -> c is a -> (b -> c)  
+///     boolean_equality  
+///     // the operators above can not be mixed without parentheses  
+///     // and, or and not can be written instead of &&, || and !: a and not b  
+///     // the syntax tree may be at most 128 levels deep, each parenthesis, unary operator and further operand of a calculation or ?: adds a level,
+///     // the operands of a chain don't however many there are  
+///     // /* block comments */ and # or // comments until the end of the line count as whitespace  
+///
+///  boolean_equality  
+///     boolean_value == boolean_value  
+///     boolean_value != boolean_value  
+///     boolean_value < boolean_value // also >, <= and >=, false is less than true  
 ///     boolean_value  
 ///
 ///  boolean_value  
 ///     value operator value  
+///     value predicate  
+///     value between value and value // integer, float, inclusive, false if the lower bound is greater than the upper bound  
+///     value in (value, ..) // equals any element of the list (elements compare like ==), a list from the context works as well  
+///     value contains value // string (substring), list (element)  
+///     value not in (value, ..), value not contains value // the negation, !(value in (value, ..))  
+///     value operator all (value, ..), value operator any (value, ..) // the comparison holds for every or for some element: x > all (10, 20)  
+///     value within_last duration // datetime or integer timestamp in seconds since the unix epoch, between now - duration and now  
+///     value within_next duration // between now and now + duration, now is EvalOptions::now or the system clock  
+///     // a duration is a number followed by s, m, h, d or w (seconds, minutes, hours, days, weeks): 7d  
+///     unary_value  
+///
+///  unary_value  
 ///     boolean   // true, false  
-///     unary_operator boolean_value  
 ///     identifier  
 ///     (boolean_expression)  
+///     unary_operator unary_value // ! binds tightest, !a == b is (!a) == b, use !(a > 1) to negate a comparison  
 /// 
 ///  value  
 ///    identifier // mode (accesses context)  
-///    string     // 'normal'  
-///    integer    // 5  
-///    float      // 5.0  
+///    identifier.key // user.address.city (entry of a map in the context, unless the whole name is a key itself)  
+///    `key` // `order total`, `order-id` (a context key which is not a valid identifier, it can't contain a backtick)  
+///    identifier[integer] // scores[0], scores[-1] (element of a list in the context, negative counts from the end)  
+///    string     // 'normal', 'O''Brien' (a quote is written twice)  
+///               // a string compared to a datetime from the context is parsed as an RFC 3339 timestamp: '2024-01-01', '2024-01-01T12:00:00Z'  
+///    integer    // 5, -5, 5i (the suffix i states the type explicitly)  
+///    float      // 5.0, -5.0, 5f (the suffix f makes a whole number a float)  
+///    decimal    // dec'1.10' (exact, requires the decimal feature)  
+///    value % value // integer (remainder), a % b % c is (a % b) % c  
+///    value * value // integer, float, decimal (product), binds like %  
+///    value ** value // integer with a non-negative integer exponent, float with a float or integer exponent, binds tighter than %: 2 ** 3 ** 2 is 2 ** (3 ** 2)  
+///    value + value // integer, float, decimal (sum), % binds tighter than +  
+///    value - value // integer, float, decimal (difference), binds like +  
+///    // integer and decimal calculations fail with EvalError::Overflow instead of wrapping around  
+///                  // decimals and integers can be combined, integers are converted to decimals  
+///                  // a datetime plus or minus a duration string is a datetime: now() - '7d', the difference of two datetimes is an integer of seconds  
+///    identifier(value, ..) // function call, see functions  
+///    (value, value, ..) // tuple, == and != compare the elements like ==, tuples of different length can't be compared  
+///    value ?: value // the left value if its identifiers are in the context, otherwise the right value  
+///                   // ?: binds looser than % and + but tighter than the comparison: a ?: b == 'x' is (a ?: b) == 'x'  
+///    value ?? value // the same as ?:, a missing key takes the place of null  
 ///
 ///  operator   
-///    ==         // string, integer, float  
-///    !=         // string, integer, float  
-///    <          // integer, float  
-///    >          // integer, float  
-///    <=         // integer, float  
-///    >=         // integer, float  
+///    ${name}    // any comparison operator below, looked up in the context  
+///    ==         // string, integer, float, boolean  
+///    !=         // string, integer, float, boolean  
+///    <          // integer, float, string, boolean  
+///    >          // integer, float, string, boolean  
+///    <=         // integer, float, string, boolean  
+///    >=         // integer, float, string, boolean  
+///               // a comparison with a float NaN is false, only != is true  
+///               // strings are ordered by their UTF-8 bytes ('Z' < 'a') unless EvalOptions::collation is set  
 ///    &&         // boolean  
 ///    ||         // boolean  
-///    =~         // string (regex)  
+///    ^^         // boolean (exclusive or)  
+///    ->         // boolean (implication)  
+///    =~         // string (regex), may be followed by flags: 'pattern' /m  
+///    ===~       // string (regex matching the whole string), may be followed by flags  
+///    like       // string (glob matching the whole string), * matches any characters and ? one: name like '*.txt'  
+///    keyword    // any values, registered with FunctionRegistry::register_operator(): ip within '10.0.0.0/8'  
+///   
+///  regex flags  
+///    i          // case insensitive  
+///    m          // multiline, ^ and $ match at the start and end of every line  
+///    s          // . matches a new line  
+///    x          // ignore whitespace and # comments in the pattern  
+///    U          // swap the greediness of x* and x*?  
 ///   
 ///  unary_operator   
 ///    !          // boolean  
+///    not        // boolean, like !  
+///
+///  functions  
+///    md5(string), sha1(string), sha256(string) // lowercase hex digest, requires the hash feature  
+///    replace(string, pattern, replacement) // replaces all regex matches, $1 refers to a capture group  
+///    now() // the current datetime, EvalOptions::now or the system clock  
+///    name(value, ..) // registered with a FunctionRegistry, see evaluate_with_functions()  
+///
+///  predicate  
+///    is positive // integer, float, greater than zero  
+///    is negative // integer, float, less than zero  
+///    is zero     // integer, float, zero is neither positive nor negative  
 /// ```
-///  
+pub fn evaluate(expression: &str, context: &impl ContextProvider) -> Result<bool, EvalError> {
+    evaluate_with_options(expression, context, &EvalOptions::default())
+}
 
-pub fn evaluate(expression: &str, context: &Context) -> Result<bool, String> {
-    let expr = BooleanExpression::try_from(expression)?;
-    let expr = expr.use_context(context)?;
-    expr.evaluate()
+/// Works like evaluate() but allows to change the evaluation with EvalOptions.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, EvalOptions, evaluate_with_options};
+///
+/// let mut context = Context::new();
+/// context.insert("name".to_string(), ContextValue::String("apple".to_string()));
+/// context.insert("other".to_string(), ContextValue::String("Banana".to_string()));
+///
+/// let options = EvalOptions { collation: Some("en".to_string()), ..Default::default() };
+/// let result = evaluate_with_options("name < other", &context, &options);
+/// # #[cfg(feature = "collation")]
+/// assert_eq!(result, Ok(true));
+/// ```
+pub fn evaluate_with_options(expression: &str, context: &impl ContextProvider, options: &EvalOptions) -> Result<bool, EvalError> {
+    CompiledExpression::compile(expression)?.evaluate_with_options(context, options)
+}
+
+/// Evaluates an expression which may be a single value instead of a boolean expression and returns what it reduces to,
+/// e.g. a number for `count + 1` or a boolean for `a && b`. This helps to inspect the parts of a complex rule.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, evaluate_value};
+///
+/// let mut context = Context::new();
+/// context.insert("count".to_string(), ContextValue::Integer(2));
+///
+/// assert_eq!(evaluate_value("count * 3", &context), Ok(ContextValue::Integer(6)));
+/// assert_eq!(evaluate_value("count * 3 > 5", &context), Ok(ContextValue::Boolean(true)));
+/// ```
+pub fn evaluate_value(expression: &str, context: &impl ContextProvider) -> Result<ContextValue, EvalError> {
+    match non_boolean_expression::parse_whole_value(expression)? {
+        Some(value) => value.resolve(&EvalEnv::new(context, &EvalOptions::default()))?.into_context_value(),
+        None => evaluate(expression, context).map(ContextValue::Boolean),
+    }
+}
+
+/// Evaluates an expression with fuzzy logic and returns a truth value between 0 and 1.
+/// Identifiers used as boolean values may hold a float between 0 and 1 besides a boolean.
+/// `&&` takes the minimum, `||` the maximum and `!` the complement of its operands.
+/// `^^`, `->` and orderings of conditions like `(a) < (b)` are composed from these, `==` yields one minus the distance of both sides and `!=` the distance.
+/// Comparisons like `count > 5` stay crisp and yield either 0 or 1.
+/// Like [`evaluate`], `&&` stops at a 0, `||` at a 1 and `->` at a false premise without evaluating the remaining operands.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, evaluate_fuzzy};
+///
+/// let mut context = Context::new();
+/// context.insert("warm".to_string(), ContextValue::Float(0.7));
+/// context.insert("sunny".to_string(), ContextValue::Float(0.4));
+///
+/// assert_eq!(evaluate_fuzzy("warm && sunny", &context), Ok(0.4));
+/// assert_eq!(evaluate_fuzzy("warm || sunny", &context), Ok(0.7));
+/// ```
+pub fn evaluate_fuzzy(expression: &str, context: &impl ContextProvider) -> Result<f64, EvalError> {
+    let expr = CompiledExpression::compile(expression)?;
+    expr.expression().evaluate_fuzzy(&expr.env(context, &EvalOptions::default()))
+}
+
+/// Parses an expression into its syntax tree without evaluating it.
+/// The tree can be inspected, stored and evaluated later on.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{parse, BooleanExpression, BinaryOperator};
+///
+/// let expr = parse("true && false").unwrap();
+/// assert_eq!(expr, BooleanExpression::Chain(
+///     BinaryOperator::And,
+///     vec![BooleanExpression::Boolean(true), BooleanExpression::Boolean(false)],
+/// ));
+/// ```
+pub fn parse(expression: &str) -> Result<BooleanExpression, EvalError> {
+    BooleanExpression::try_from(expression)
+}
+
+/// Works like parse() but keeps the parentheses written in the expression as BooleanExpression::Group,
+/// so displaying the tree reproduces redundant parentheses like in `(a) && b`.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{parse, parse_with_groups};
+///
+/// assert_eq!(parse("(a) && b").unwrap().to_string(), "a && b");
+/// assert_eq!(parse_with_groups("(a) && b").unwrap().to_string(), "(a) && b");
+/// ```
+pub fn parse_with_groups(expression: &str) -> Result<BooleanExpression, EvalError> {
+    expression::parse_with_groups(expression)
+}
+
+/// Works like parse() but rejects identifiers which are used as boolean values, like `enableed` in `enableed && x > 1`,
+/// unless they are declared, so typos are found while authoring a rule instead of when it is evaluated.
+/// Identifiers in comparisons are not checked. An undeclared identifier is reported as EvalError::UnknownIdentifier.
+///
+/// # Examples
+/// ```rust
+/// use std::collections::HashSet;
+/// use logical_expr::parse_strict;
+///
+/// let declared = HashSet::from(["enabled".to_string()]);
+/// assert!(parse_strict("enabled && count > 1", &declared).is_ok());
+/// assert!(parse_strict("enableed && count > 1", &declared).is_err());
+/// ```
+pub fn parse_strict(expression: &str, declared: &HashSet<String>) -> Result<BooleanExpression, EvalError> {
+    let tree = parse(expression)?;
+    if let Some(ident) = tree.boolean_identifiers().into_iter().find(|ident| !declared.contains(ident.name())) {
+        return Err(EvalError::UnknownIdentifier(format!("Identifier is not declared: {}", ident)));
+    }
+    Ok(tree)
+}
+
+/// Works like evaluate() but returns an EvalOutcome which additionally carries diagnostics.
+/// Currently a diagnostic is reported for every context value the expression does not use,
+/// which usually hints at a typo in either the expression or the context.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, evaluate_outcome};
+///
+/// let mut context = Context::new();
+/// context.insert("foo".to_string(), ContextValue::Boolean(true));
+/// context.insert("bar".to_string(), ContextValue::Boolean(false));
+///
+/// let outcome = evaluate_outcome("foo", &context).unwrap();
+/// assert!(outcome == true);
+/// assert_eq!(outcome.diagnostics, vec!["Context value is not used by the expression: bar".to_string()]);
+/// ```
+pub fn evaluate_outcome(expression: &str, context: &Context) -> Result<EvalOutcome, EvalError> {
+    let expr = CompiledExpression::compile(expression)?;
+    let used: Vec<String> = expr.expression().identifiers().iter().map(|ident| ident.name().to_string()).collect();
+    let mut diagnostics: Vec<String> = context.keys()
+        .filter(|key| !used.iter().any(|name| name == *key || name.strip_prefix(key.as_str()).is_some_and(|rest| rest.starts_with('.'))))
+        .map(|key| format!("Context value is not used by the expression: {}", key))
+        .collect();
+    diagnostics.sort();
+    let value = expr.evaluate(context)?;
+    Ok(EvalOutcome { value, diagnostics })
+}
+
+/// Works like evaluate() but resolves identifiers by calling f at the moment the evaluation needs them.
+/// Identifiers in operands skipped by short-circuiting are never resolved, so f may compute expensive values.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{ContextValue, evaluate_with_fn};
+///
+/// let result = evaluate_with_fn("size > 10", |key| match key {
+///     "size" => Some(ContextValue::Integer(42)),
+///     _ => None,
+/// });
+/// assert_eq!(result, Ok(true));
+/// ```
+pub fn evaluate_with_fn<F>(expression: &str, f: F) -> Result<bool, EvalError>
+where
+    F: Fn(&str) -> Option<ContextValue>,
+{
+    evaluate(expression, &FnContext(f))
+}
+
+/// Works like evaluate() but calls the functions of the registry, functions which are
+/// not registered fall back to the builtin ones. The arguments are resolved from the context before the call.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, EvalError, FunctionRegistry, Value, evaluate_with_functions};
+///
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("len", |args| match args {
+///     [Value::StringLiteral(s)] => Ok(Value::IntegerLiteral(s.chars().count() as i64)),
+///     _ => Err(EvalError::TypeMismatch("len expects a string".to_string())),
+/// });
+///
+/// let mut context = Context::new();
+/// context.insert("name".to_string(), ContextValue::String("Ada".to_string()));
+///
+/// assert_eq!(evaluate_with_functions("len(name) > 3", &context, &functions), Ok(false));
+/// ```
+pub fn evaluate_with_functions(expression: &str, context: &impl ContextProvider, functions: &FunctionRegistry) -> Result<bool, EvalError> {
+    CompiledExpression::compile(expression)?.evaluate_with_functions(context, functions, &EvalOptions::default())
+}
+
+/// Evaluates a compiled expression and only returns the kind of an error, e.g. for hot loops
+/// which handle all errors alike. The messages of errors are not built, use CompiledExpression::evaluate() to get them.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{evaluate_fast, CompiledExpression, Context, EvalErrorCode};
+///
+/// let expr = CompiledExpression::compile("count > 1").unwrap();
+/// assert_eq!(evaluate_fast(&expr, &Context::new()), Err(EvalErrorCode::UnknownIdentifier));
+/// ```
+pub fn evaluate_fast(expression: &CompiledExpression, context: &Context) -> Result<bool, EvalErrorCode> {
+    error::without_messages(|| expression.evaluate(context)).map_err(|err| err.code())
+}
+
+/// Evaluates a compiled expression against every context, the results are in the order of the contexts.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{evaluate_batch, CompiledExpression, Context, ContextValue};
+///
+/// let expr = CompiledExpression::compile("count > 1").unwrap();
+/// let contexts: Vec<Context> = (0..3).map(|count| Context::from([("count".to_string(), ContextValue::Integer(count))])).collect();
+/// assert_eq!(evaluate_batch(&expr, &contexts), vec![Ok(false), Ok(false), Ok(true)]);
+/// ```
+pub fn evaluate_batch(expression: &CompiledExpression, contexts: &[Context]) -> Vec<Result<bool, EvalError>> {
+    contexts.iter().map(|context| expression.evaluate(context)).collect()
+}
+
+/// Works like evaluate_batch() but evaluates the contexts in parallel on the rayon thread pool, requires the `rayon` feature.
+/// The results are in the order of the contexts.
+#[cfg(feature = "rayon")]
+pub fn evaluate_batch_parallel(expression: &CompiledExpression, contexts: &[Context]) -> Vec<Result<bool, EvalError>> {
+    use rayon::prelude::*;
+
+    contexts.par_iter().map(|context| expression.evaluate(context)).collect()
+}
+
+/// Works like evaluate() but additionally returns the identifiers and values the evaluation looked up.
+/// Operands skipped by short-circuiting are not looked up and thus not returned,
+/// so the inputs are exactly what the result depends on, e.g. to build a cache key.
+///
+/// # Examples
+/// ```rust
+/// use logical_expr::{Context, ContextValue, evaluate_with_inputs};
+///
+/// let mut context = Context::new();
+/// context.insert("a".to_string(), ContextValue::Integer(0));
+/// context.insert("b".to_string(), ContextValue::Integer(1));
+///
+/// let (result, inputs) = evaluate_with_inputs("a > 1 && b < 2", &context).unwrap();
+/// assert!(!result);
+/// assert_eq!(inputs, vec![("a".to_string(), ContextValue::Integer(0))]);
+/// ```
+pub fn evaluate_with_inputs(expression: &str, context: &impl ContextProvider) -> Result<(bool, Vec<(String, ContextValue)>), EvalError> {
+    let expr = CompiledExpression::compile(expression)?;
+    let recording = RecordingContext::new(context);
+    let value = expr.evaluate(&recording)?;
+    Ok((value, recording.inputs.into_inner()))
+}
+
+/// The result of an evaluation together with diagnostics collected along the way.
+/// It dereferences and compares to bool, so it can be used like the plain result.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EvalOutcome {
+    pub value: bool,
+    pub diagnostics: Vec<String>,
+}
+
+impl Deref for EvalOutcome {
+    type Target = bool;
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl PartialEq<bool> for EvalOutcome {
+    fn eq(&self, other: &bool) -> bool {
+        self.value == *other
+    }
+}
+
+impl PartialEq<EvalOutcome> for bool {
+    fn eq(&self, other: &EvalOutcome) -> bool {
+        *self == other.value
+    }
+}
+
+impl From<EvalOutcome> for bool {
+    fn from(outcome: EvalOutcome) -> Self {
+        outcome.value
+    }
 }
 
 /// This is a type alias for a hashmap of strings and context values
 pub type Context = HashMap<String, ContextValue>;
 
 /// This is an enum containing valid context value types.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ContextValue {
     String(String),
     Integer(i64),
     Float(f64),
     Boolean(bool),
+    List(Vec<ContextValue>),
+    /// Nested values accessed with dotted identifiers like `user.address.city`.
+    /// The map itself resolves to a list of `(key, value)` tuples sorted by key.
+    Map(HashMap<String, ContextValue>),
+    /// A point in time as seconds since the unix epoch, it compares to RFC 3339 string literals like `'2024-01-01'`.
+    DateTime(i64),
+    /// An exact decimal number, requires the `decimal` feature.
+    #[cfg(feature = "decimal")]
+    Decimal(rust_decimal::Decimal),
+}
+
+impl ContextValue {
+    /// The type of the value, a map has the type of the list of `(key, value)` tuples it resolves to.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use logical_expr::{ContextValue, ValueType};
+    ///
+    /// assert_eq!(ContextValue::Integer(1).value_type(), ValueType::Integer);
+    /// ```
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            ContextValue::String(_) => ValueType::String,
+            ContextValue::Integer(_) => ValueType::Integer,
+            ContextValue::Float(_) => ValueType::Float,
+            ContextValue::Boolean(_) => ValueType::Boolean,
+            ContextValue::List(_) | ContextValue::Map(_) => ValueType::List,
+            ContextValue::DateTime(_) => ValueType::DateTime,
+            #[cfg(feature = "decimal")]
+            ContextValue::Decimal(_) => ValueType::Decimal,
+        }
+    }
+}
+
+impl From<String> for ContextValue {
+    fn from(value: String) -> Self {
+        ContextValue::String(value)
+    }
+}
+
+impl From<&str> for ContextValue {
+    fn from(value: &str) -> Self {
+        ContextValue::String(value.to_string())
+    }
+}
+
+impl From<i64> for ContextValue {
+    fn from(value: i64) -> Self {
+        ContextValue::Integer(value)
+    }
+}
+
+impl From<f64> for ContextValue {
+    fn from(value: f64) -> Self {
+        ContextValue::Float(value)
+    }
+}
+
+impl From<bool> for ContextValue {
+    fn from(value: bool) -> Self {
+        ContextValue::Boolean(value)
+    }
 }
 
 
@@ -103,11 +583,741 @@ mod tests {
         assert!(result);
     }
     #[test]
+    fn regex_from_context() {
+        let mut context = HashMap::new();
+        context.insert("text".to_string(), ContextValue::String("baaar".to_string()));
+        context.insert("pattern".to_string(), ContextValue::String("a+".to_string()));
+        assert_eq!(evaluate("text =~ pattern", &context), Ok(true));
+        assert_eq!(evaluate("text ===~ pattern", &context), Ok(false));
+        assert_eq!(evaluate("'aaa' ===~ pattern && pattern =~ '\\+'", &context), Ok(true));
+        context.insert("pattern".to_string(), ContextValue::String("(".to_string()));
+        assert!(matches!(evaluate("text =~ pattern", &context), Err(EvalError::InvalidRegex(_))));
+        context.insert("pattern".to_string(), ContextValue::Integer(1));
+        assert!(matches!(evaluate("text =~ pattern", &context), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn context_value_types() {
+        let values = [
+            (ContextValue::String("a".to_string()), ValueType::String),
+            (ContextValue::Integer(1), ValueType::Integer),
+            (ContextValue::Float(1.5), ValueType::Float),
+            (ContextValue::Boolean(true), ValueType::Boolean),
+            (ContextValue::List(vec![ContextValue::Integer(1)]), ValueType::List),
+            (ContextValue::Map(HashMap::new()), ValueType::List),
+            (ContextValue::DateTime(0), ValueType::DateTime),
+        ];
+        for (value, value_type) in values {
+            assert_eq!(value.value_type(), value_type, "{:?}", value);
+        }
+    }
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn decimal_value_type() {
+        assert_eq!(ContextValue::Decimal(rust_decimal::Decimal::new(11, 1)).value_type(), ValueType::Decimal);
+    }
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive_into_context() {
+        #[derive(IntoContext)]
+        struct Order {
+            pub customer: String,
+            quantity: i64,
+            /// The price of one item.
+            price: f64,
+            r#paid: bool,
+        }
+        let order = Order { customer: "ada".to_string(), quantity: 3, price: 2.5, paid: true };
+        let context = order.to_context();
+        assert_eq!(context.len(), 4);
+        assert_eq!(context.get("customer"), Some(&ContextValue::String("ada".to_string())));
+        assert_eq!(context.get("quantity"), Some(&ContextValue::Integer(3)));
+        assert_eq!(context.get("price"), Some(&ContextValue::Float(2.5)));
+        assert_eq!(context.get("paid"), Some(&ContextValue::Boolean(true)));
+        assert_eq!(evaluate("paid && quantity > 2 && price < 3.0 && customer == 'ada'", &context), Ok(true));
+    }
+    #[test]
+    fn values_of_expressions() {
+        let mut context = HashMap::new();
+        context.insert("a".to_string(), ContextValue::Boolean(true));
+        context.insert("b".to_string(), ContextValue::Boolean(false));
+        context.insert("name".to_string(), ContextValue::String("ada".to_string()));
+        assert_eq!(evaluate_value("1 + 2", &context), Ok(ContextValue::Integer(3)));
+        assert_eq!(evaluate_value("a && b", &context), Ok(ContextValue::Boolean(false)));
+        assert_eq!(evaluate_value(" name # the name\n", &context), Ok(ContextValue::String("ada".to_string())));
+        assert_eq!(evaluate_value("a", &context), Ok(ContextValue::Boolean(true)));
+        assert_eq!(evaluate_value("(1, name)", &context), Ok(ContextValue::List(vec![ContextValue::Integer(1), ContextValue::String("ada".to_string())])));
+        assert!(matches!(evaluate_value("missing + 1", &context), Err(EvalError::UnknownIdentifier(_))));
+        assert!(matches!(evaluate_value("1 +", &context), Err(EvalError::Parse(_))));
+    }
+    #[test]
     fn not_boolean() {
         let mut context = HashMap::new();
         context.insert("foo".to_string(), ContextValue::Boolean(false));
         let result = evaluate("!foo", &context).unwrap();
         assert!(result);
     }
+    #[test]
+    fn boolean_equality_with_context() {
+        let mut context = HashMap::new();
+        context.insert("enabled".to_string(), ContextValue::Boolean(true));
+        context.insert("other".to_string(), ContextValue::Boolean(true));
+        assert_eq!(evaluate("enabled != false", &context), Ok(true));
+        assert_eq!(evaluate("enabled == false", &context), Ok(false));
+        assert_eq!(evaluate("enabled == other", &context), Ok(true));
+        assert_eq!(evaluate("enabled != other && true", &context), Ok(false));
+    }
+    #[test]
+    fn boolean_literal_on_the_left() {
+        let mut context = HashMap::new();
+        context.insert("active".to_string(), ContextValue::Boolean(true));
+        context.insert("count".to_string(), ContextValue::Integer(1));
+        assert_eq!(evaluate("true == active", &context), Ok(true));
+        assert_eq!(evaluate("false != active", &context), Ok(true));
+        assert_eq!(evaluate("false == active || true != true", &context), Ok(false));
+        assert_eq!(evaluate("true < active", &context), Ok(false));
+        assert!(matches!(evaluate("true < count", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("true == count", &context), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn parse_error_position() {
+        let context = HashMap::new();
+        let err = evaluate("a &&", &context).unwrap_err();
+        assert!(err.to_string().starts_with("Unexpected input at line 1, column 3:"), "{}", err);
+    }
+    #[test]
+    fn dynamic_operator_from_context() {
+        let mut context = HashMap::new();
+        context.insert("op".to_string(), ContextValue::String(">".to_string()));
+        context.insert("x".to_string(), ContextValue::Integer(5));
+        assert_eq!(evaluate("x ${op} 3", &context), Ok(true));
+        assert_eq!(evaluate("x ${op} 7", &context), Ok(false));
+
+        context.insert("op".to_string(), ContextValue::String("=~".to_string()));
+        context.insert("name".to_string(), ContextValue::String("baaar".to_string()));
+        assert_eq!(evaluate("name ${op} 'ba+r'", &context), Ok(true));
+
+        context.insert("op".to_string(), ContextValue::String("<>".to_string()));
+        assert_eq!(evaluate("x ${op} 3", &context), Err(EvalError::InvalidOperator("Unknown operator: <>".to_string())));
+    }
+    #[test]
+    fn fuzzy_evaluation() {
+        let mut context = HashMap::new();
+        assert_eq!(evaluate_fuzzy("true && false", &context), Ok(0.0));
+        assert_eq!(evaluate_fuzzy("!false", &context), Ok(1.0));
+
+        context.insert("a".to_string(), ContextValue::Float(0.25));
+        context.insert("b".to_string(), ContextValue::Float(0.75));
+        context.insert("c".to_string(), ContextValue::Boolean(true));
+        context.insert("count".to_string(), ContextValue::Integer(3));
+        assert_eq!(evaluate_fuzzy("a || b", &context), Ok(0.75));
+        assert_eq!(evaluate_fuzzy("a && b", &context), Ok(0.25));
+        assert_eq!(evaluate_fuzzy("!a && (b || !c)", &context), Ok(0.75));
+        assert_eq!(evaluate_fuzzy("(a || count > 5) && c", &context), Ok(0.25));
+        assert_eq!(evaluate_fuzzy("count > 1 && b", &context), Ok(0.75));
+
+        context.insert("invalid".to_string(), ContextValue::Float(1.5));
+        assert!(matches!(evaluate_fuzzy("invalid", &context), Err(EvalError::TypeMismatch(_))));
+        // operands after a deciding value are skipped like by evaluate()
+        for expression in ["true || missing", "false && missing", "false -> missing", "c && false && missing", "c -> false -> missing", "false || c || missing"] {
+            assert_eq!(evaluate_fuzzy(expression, &context), evaluate(expression, &context).map(|b| if b { 1.0 } else { 0.0 }));
+        }
+        assert_eq!(evaluate_fuzzy("a -> b -> a", &context), Ok(0.75));
+        assert!(matches!(evaluate_fuzzy("a || missing", &context), Err(EvalError::UnknownIdentifier(_))));
+        // ordering booleans agrees with the crisp evaluation
+        for (lhs, rhs) in [(true, true), (true, false), (false, true), (false, false)] {
+            let context = HashMap::from([("l".to_string(), ContextValue::Boolean(lhs)), ("r".to_string(), ContextValue::Boolean(rhs))]);
+            for expression in ["(l) < (r)", "(l) > (r)", "(l) <= (r)", "(l) >= (r)", "(l && r) < r", "(l || r) >= !r"] {
+                assert_eq!(evaluate_fuzzy(expression, &context), evaluate(expression, &context).map(|b| if b { 1.0 } else { 0.0 }), "{}", expression);
+            }
+        }
+        assert_eq!(evaluate_fuzzy("(a) < (b)", &context), Ok(0.75));
+        assert_eq!(evaluate_fuzzy("(a) >= (b)", &context), Ok(0.25));
+    }
+    #[test]
+    fn boolean_identifiers_with_comparisons() {
+        let mut context = HashMap::new();
+        context.insert("enabled".to_string(), ContextValue::Boolean(true));
+        context.insert("disabled".to_string(), ContextValue::Boolean(false));
+        context.insert("count".to_string(), ContextValue::Integer(7));
+        assert_eq!(evaluate("enabled && count > 5", &context), Ok(true));
+        assert_eq!(evaluate("count > 5 || disabled", &context), Ok(true));
+        assert_eq!(evaluate("disabled || count > 9 || !enabled", &context), Ok(false));
+        assert_eq!(evaluate("enabled && count > 5 && !disabled && count < 9", &context), Ok(true));
+        assert_eq!(parse("enabled && count > 5").unwrap().to_string(), "enabled && count > 5");
+    }
+    #[test]
+    fn quoted_identifiers() {
+        let mut context = HashMap::new();
+        context.insert("order total".to_string(), ContextValue::Integer(120));
+        context.insert("order-id".to_string(), ContextValue::String("a-1".to_string()));
+        context.insert("is paid".to_string(), ContextValue::Boolean(true));
+        context.insert("and".to_string(), ContextValue::Boolean(true));
+        assert_eq!(evaluate("`order total` > 100 && `order-id` == 'a-1' # `not a comment", &context), Ok(true));
+        assert_eq!(evaluate("`is paid` && !`and`", &context), Ok(false));
+        assert_eq!(
+            evaluate("`order_total` > 100", &context),
+            Err(EvalError::UnknownIdentifier("Identifier not found in context: order_total".to_string()))
+        );
+        assert_eq!(parse("`order total`>100 && `plain` && `and`").unwrap().to_string(), "`order total` > 100 && plain && `and`");
+        assert!(parse("`` && a").is_err());
+    }
+    #[test]
+    fn strict_parsing() {
+        let declared = HashSet::from(["enabled".to_string(), "admin".to_string()]);
+        assert_eq!(parse_strict("enabled && (admin || !enabled)", &declared), parse("enabled && (admin || !enabled)"));
+        assert_eq!(
+            parse_strict("enabled && !enableed", &declared),
+            Err(EvalError::UnknownIdentifier("Identifier is not declared: enableed".to_string()))
+        );
+        // identifiers in comparisons are not bare booleans
+        assert!(parse_strict("count > 1 && name == admin", &declared).is_ok());
+        assert!(matches!(parse_strict("enabled &&", &declared), Err(EvalError::UnexpectedInput { .. })));
+    }
+    #[test]
+    fn syntax_trees_in_hash_set() {
+        let mut rules = std::collections::HashSet::new();
+        rules.insert(parse("price > 1.5 && name in ('a', 'b')").unwrap());
+        rules.insert(parse("price  >  1.5 && name in ('a','b') # the same rule").unwrap());
+        assert_eq!(rules.len(), 1);
+        rules.insert(parse("price > 1.5 && name in ('a', 'c')").unwrap());
+        assert_eq!(rules.len(), 2);
+        // a NaN from compile_with_constants() is equal to itself, so the rule is only kept once
+        let nan = || BooleanExpression::NonBooleanExpression(NonBooleanExpression(
+            Value::Identifier(Identifier::from("price")), BinaryOperator::GreaterThan, Value::FloatLiteral(f64::NAN)));
+        rules.insert(nan());
+        rules.insert(nan());
+        assert_eq!(rules.len(), 3);
+        assert!(rules.contains(&nan()));
+    }
+    #[test]
+    fn short_circuit_skips_missing_identifiers() {
+        let context = HashMap::new();
+        assert_eq!(evaluate("false && missing", &context), Ok(false));
+        assert_eq!(evaluate("false && missing > 1", &context), Ok(false));
+        assert_eq!(evaluate("false -> missing", &context), Ok(true));
+        assert!(matches!(evaluate("true && missing", &context), Err(EvalError::UnknownIdentifier(_))));
+    }
+    #[test]
+    fn short_circuit_or_skips_missing_identifiers() {
+        let mut context = HashMap::new();
+        assert_eq!(evaluate("true || missing", &context), Ok(true));
+        context.insert("verified".to_string(), ContextValue::Boolean(true));
+        assert_eq!(evaluate("verified || adminOverride", &context), Ok(true));
+        assert_eq!(evaluate("verified || adminOverride == 'yes'", &context), Ok(true));
+        context.insert("verified".to_string(), ContextValue::Boolean(false));
+        assert!(matches!(evaluate("verified || adminOverride", &context), Err(EvalError::UnknownIdentifier(_))));
+    }
+    #[test]
+    fn multiline_regex() {
+        let mut context = Context::new();
+        context.insert("block".to_string(), ContextValue::String("INFO start\nERROR failed\nINFO done".to_string()));
+        assert_eq!(evaluate("block =~ '^ERROR' /m", &context), Ok(true));
+        assert_eq!(evaluate("block =~ '^ERROR'", &context), Ok(false));
+        assert_eq!(evaluate("block =~ 'failed$' /m", &context), Ok(true));
+        assert_eq!(evaluate("block =~ '^INFO'", &context), Ok(true));
+    }
+    #[test]
+    fn power() {
+        let mut context = Context::new();
+        context.insert("base".to_string(), ContextValue::Integer(3));
+        context.insert("limit".to_string(), ContextValue::Integer(8));
+        context.insert("negative".to_string(), ContextValue::Integer(-1));
+        assert_eq!(evaluate("2 ** 10 == 1024", &context), Ok(true));
+        assert_eq!(evaluate("base ** 2 > limit", &context), Ok(true));
+        assert_eq!(evaluate_value("2.0 ** 0.5", &context), Ok(ContextValue::Float(2f64.sqrt())));
+        assert_eq!(evaluate_value("2.0 ** negative", &context), Ok(ContextValue::Float(0.5)));
+        assert_eq!(evaluate_value("2 ** 3 ** 2", &context), Ok(ContextValue::Integer(512)));
+        assert_eq!(evaluate_value("2 * 3 ** 2", &context), Ok(ContextValue::Integer(18)));
+        assert_eq!(evaluate("1 ** 5000000000 == 1", &context), Ok(true));
+        assert_eq!(evaluate_value("0 ** 5000000000", &context), Ok(ContextValue::Integer(0)));
+        assert_eq!(evaluate_value("negative ** 5000000001", &context), Ok(ContextValue::Integer(-1)));
+        assert_eq!(evaluate_value("negative ** 5000000000", &context), Ok(ContextValue::Integer(1)));
+        assert_eq!(evaluate_value("base**0", &context), Ok(ContextValue::Integer(1)));
+        assert_eq!(
+            evaluate_value("2 ** 63", &context),
+            Err(EvalError::Overflow("Integer overflow: 2 ** 63".to_string()))
+        );
+        assert!(matches!(evaluate_value("2 ** negative", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate_value("2 ** 0.5", &context), Err(EvalError::TypeMismatch(_))));
+        assert_eq!(parse("base ** 2 > limit").unwrap().to_string(), "base ** 2 > limit");
+    }
+    #[test]
+    fn modulo() {
+        let mut context = Context::new();
+        context.insert("index".to_string(), ContextValue::Integer(10));
+        assert_eq!(evaluate("10 % 3 == 1", &context), Ok(true));
+        assert_eq!(evaluate("index % 5 == 0 && index % 4 == 2", &context), Ok(true));
+        assert_eq!(evaluate("index % 4 % 3 is zero", &context), Ok(false));
+        assert_eq!(
+            evaluate("index % 0 == 0", &context),
+            Err(EvalError::DivisionByZero("Modulo by zero: 10 % 0".to_string()))
+        );
+        context.insert("divisor".to_string(), ContextValue::Integer(0));
+        assert_eq!(
+            evaluate("index % divisor == 0", &context),
+            Err(EvalError::DivisionByZero("Modulo by zero: 10 % 0, divisor is 0".to_string()))
+        );
+        context.insert("sizes".to_string(), ContextValue::List(vec![ContextValue::Integer(0)]));
+        assert_eq!(
+            evaluate("index % sizes[0] == 0", &context).map_err(|err| err.to_string()),
+            Err("Modulo by zero: 10 % 0, sizes[0] is 0".to_string())
+        );
+        assert!(matches!(evaluate("1.5 % 1.0 == 0.5", &context), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn custom_context_provider() {
+        use std::borrow::Cow;
+
+        struct Order {
+            id: i64,
+            express: bool,
+            tags: Vec<&'static str>,
+        }
+        impl ContextProvider for Order {
+            fn get(&self, key: &str) -> Option<Cow<'_, ContextValue>> {
+                let value = match key {
+                    "id" => ContextValue::Integer(self.id),
+                    "express" => ContextValue::Boolean(self.express),
+                    "tags" => ContextValue::List(self.tags.iter().map(|tag| ContextValue::String(tag.to_string())).collect()),
+                    _ => return None,
+                };
+                Some(Cow::Owned(value))
+            }
+        }
+        let order = Order { id: 42, express: true, tags: vec!["fragile", "gift"] };
+        assert_eq!(evaluate("express && id % 2 == 0 && tags[-1] == 'gift'", &order), Ok(true));
+        assert_eq!(evaluate_fuzzy("express || id > 100", &order), Ok(1.0));
+        assert!(matches!(evaluate("missing", &order), Err(EvalError::UnknownIdentifier(_))));
+    }
+    #[cfg(feature = "hash")]
+    #[test]
+    fn hash_functions() {
+        let mut context = HashMap::new();
+        context.insert("payload".to_string(), ContextValue::String("hello".to_string()));
+        context.insert("stored_hash".to_string(), ContextValue::String("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string()));
+        assert_eq!(evaluate("stored_hash == sha256(payload)", &context), Ok(true));
+        assert_eq!(evaluate("sha256('') == 'e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855'", &context), Ok(true));
+        assert_eq!(evaluate("stored_hash != md5(payload) && sha1(payload) != sha256(payload)", &context), Ok(true));
+        assert!(matches!(evaluate("sha256(1) == stored_hash", &context), Err(EvalError::TypeMismatch(_))));
+    }
+    #[cfg(not(feature = "hash"))]
+    #[test]
+    fn hash_functions_require_feature() {
+        let context = HashMap::new();
+        assert!(matches!(evaluate("sha256('') == 'x'", &context), Err(EvalError::InvalidOption(_))));
+    }
+    #[test]
+    fn lazy_resolution_with_fn() {
+        let calls = std::cell::RefCell::new(Vec::new());
+        let resolve = |key: &str| {
+            calls.borrow_mut().push(key.to_string());
+            Some(ContextValue::Boolean(key == "cheap"))
+        };
+        assert_eq!(evaluate_with_fn("true || expensive", resolve), Ok(true));
+        assert!(calls.borrow().is_empty());
+        assert_eq!(evaluate_with_fn("cheap && !expensive", resolve), Ok(true));
+        assert_eq!(*calls.borrow(), vec!["cheap".to_string(), "expensive".to_string()]);
+        assert!(matches!(evaluate_with_fn("missing", |_| None), Err(EvalError::UnknownIdentifier(_))));
+    }
+    #[test]
+    fn registered_functions() {
+        let mut functions = FunctionRegistry::new();
+        functions.register("len", |args| match args {
+            [Value::StringLiteral(s)] => Ok(Value::IntegerLiteral(s.chars().count() as i64)),
+            [Value::List(items)] => Ok(Value::IntegerLiteral(items.len() as i64)),
+            _ => Err(EvalError::TypeMismatch("len expects a string or a list".to_string())),
+        });
+        functions.register("now", |_| Ok(Value::IntegerLiteral(1_700_000_000)));
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("Grace".to_string()));
+        context.insert("tags".to_string(), ContextValue::List(vec![ContextValue::Integer(1), ContextValue::Integer(2)]));
+        context.insert("timestamp".to_string(), ContextValue::Integer(1_600_000_000));
+        assert_eq!(evaluate_with_functions("len(name) > 3", &context, &functions), Ok(true));
+        assert_eq!(evaluate_with_functions("len('abc') == 3 && len(tags) == 2", &context, &functions), Ok(true));
+        assert_eq!(evaluate_with_functions("now() > timestamp", &context, &functions), Ok(true));
+        assert!(matches!(evaluate_with_functions("len(timestamp) > 3", &context, &functions), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate_with_functions("size(name) > 3", &context, &functions), Err(EvalError::UnknownIdentifier(_))));
+        assert!(matches!(evaluate("len(name) > 3", &context), Err(EvalError::UnknownIdentifier(_))));
+    }
+    #[test]
+    fn membership() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("grace".to_string()));
+        context.insert("tags".to_string(), ContextValue::List(vec![ContextValue::String("admin".to_string())]));
+        for x in 0..5 {
+            context.insert("x".to_string(), ContextValue::Integer(x));
+            let is_in = evaluate("x in (1,2,3)", &context).unwrap();
+            assert_eq!(is_in, (1..=3).contains(&x));
+            assert_eq!(evaluate("x not in (1,2,3)", &context), Ok(!is_in));
+        }
+        assert_eq!(evaluate("name in ('ada', 'grace') && name contains 'ac' && name not contains 'z'", &context), Ok(true));
+        assert_eq!(evaluate("tags contains 'admin' && 'admin' in tags && 'root' not in tags", &context), Ok(true));
+        assert_eq!(evaluate("x in(4)", &context), Ok(true));
+        assert!(matches!(evaluate("x in name", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("x contains 1", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("x in ('4')", &context), Err(EvalError::TypeMismatch(_))));
+        assert_eq!(parse("x not in (1, 2) || y").unwrap().to_string(), "!(x in (1, 2)) || y");
+    }
+    #[test]
+    fn identifiers_of_different_types() {
+        let mut context = HashMap::new();
+        context.insert("a".to_string(), ContextValue::String("1".to_string()));
+        context.insert("b".to_string(), ContextValue::Integer(1));
+        context.insert("c".to_string(), ContextValue::Integer(2));
+        assert_eq!(evaluate("b < c", &context), Ok(true));
+        assert_eq!(evaluate("a == b", &context), Err(EvalError::TypeMismatch("Can not compare string with integer: '1' == 1".to_string())));
+        assert_eq!(evaluate("b != a", &context), Err(EvalError::TypeMismatch("Can not compare integer with string: 1 != '1'".to_string())));
+        let options = EvalOptions { loose_equality: true, ..Default::default() };
+        assert_eq!(evaluate_with_options("a == b", &context, &options), Ok(false));
+    }
+    #[test]
+    fn tuples() {
+        let mut context = HashMap::new();
+        context.insert("a".to_string(), ContextValue::String("x".to_string()));
+        context.insert("b".to_string(), ContextValue::Integer(1));
+        assert_eq!(evaluate("(a, b) == ('x', 1)", &context), Ok(true));
+        assert_eq!(evaluate("(a, b) != ('x', 1)", &context), Ok(false));
+        assert_eq!(evaluate("(a, b) == ('x', 2)", &context), Ok(false));
+        context.insert("flag".to_string(), ContextValue::Boolean(true));
+        // a single parenthesized value is not a tuple
+        assert_eq!(evaluate("(b, b + 1) != (1, 3) && (flag) == (flag)", &context), Ok(true));
+        assert!(matches!(evaluate("(a, b) == ('x', 1, 2)", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("(a, b) == (1, 'x')", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("(a, b) < ('x', 1)", &context), Err(EvalError::InvalidOperator(_))));
+        assert_eq!(evaluate("(a, b) in (('y', 0), ('x', 1))", &context), Ok(true));
+        assert_eq!(parse("(a,b) == ('x',1)").unwrap().to_string(), "(a, b) == ('x', 1)");
+    }
+    #[test]
+    fn fast_evaluation() {
+        let expr = CompiledExpression::compile("count % 3 == 0 || name =~ '^a'").unwrap();
+        let mut context = HashMap::new();
+        assert_eq!(evaluate_fast(&expr, &context), Err(EvalErrorCode::UnknownIdentifier));
+        context.insert("name".to_string(), ContextValue::String("ada".to_string()));
+        let mut matches = 0;
+        for count in 0..10_000 {
+            context.insert("count".to_string(), ContextValue::Integer(count));
+            if evaluate_fast(&expr, &context).unwrap() {
+                matches += 1;
+            }
+        }
+        assert_eq!(matches, 10_000);
+        context.insert("count".to_string(), ContextValue::String("1".to_string()));
+        assert_eq!(evaluate_fast(&expr, &context), Err(EvalErrorCode::TypeMismatch));
+        // the fast path leaves the message empty, the slow one still builds it
+        assert_eq!(error::without_messages(|| expr.evaluate(&context)), Err(EvalError::TypeMismatch(String::new())));
+        assert_eq!(expr.evaluate(&context), Err(EvalError::TypeMismatch("Operator % requires integers: '1' % 3".to_string())));
+    }
+    #[test]
+    fn batch_evaluation() {
+        let expr = CompiledExpression::compile("count % 3 == 0 && name =~ 'a'").unwrap();
+        let contexts: Vec<Context> = (0..1000).map(|count| {
+            let mut context = HashMap::new();
+            context.insert("count".to_string(), ContextValue::Integer(count));
+            if count % 7 != 0 {
+                context.insert("name".to_string(), ContextValue::String("ada".to_string()));
+            }
+            context
+        }).collect();
+        let expected: Vec<_> = contexts.iter().map(|context| evaluate("count % 3 == 0 && name =~ 'a'", context)).collect();
+        assert_eq!(evaluate_batch(&expr, &contexts), expected);
+        assert!(expected.iter().any(|result| result.is_err()));
+        #[cfg(feature = "rayon")]
+        {
+            assert_eq!(evaluate_batch_parallel(&expr, &contexts), expected);
+            assert!(evaluate_batch_parallel(&expr, &[]).is_empty());
+        }
+    }
+    #[test]
+    fn inputs_of_evaluation() {
+        let mut context = HashMap::new();
+        context.insert("a".to_string(), ContextValue::Integer(2));
+        context.insert("b".to_string(), ContextValue::Integer(1));
+        context.insert("unused".to_string(), ContextValue::Integer(3));
+        let (value, inputs) = evaluate_with_inputs("a > 1 && b < 2", &context).unwrap();
+        assert!(value);
+        assert_eq!(inputs, vec![("a".to_string(), ContextValue::Integer(2)), ("b".to_string(), ContextValue::Integer(1))]);
+        let (value, inputs) = evaluate_with_inputs("(a > 5 && b < 2) || a == b", &context).unwrap();
+        assert!(!value);
+        assert_eq!(inputs, vec![("a".to_string(), ContextValue::Integer(2)), ("b".to_string(), ContextValue::Integer(1))]);
+        let (_, inputs) = evaluate_with_inputs("a > 1 || b < 2", &context).unwrap();
+        assert_eq!(inputs, vec![("a".to_string(), ContextValue::Integer(2))]);
+    }
+    #[test]
+    fn addition() {
+        let mut context = HashMap::new();
+        context.insert("count".to_string(), ContextValue::Integer(4));
+        assert_eq!(evaluate("count + 1 == 5", &context), Ok(true));
+        assert_eq!(evaluate("count + 6 % 4 == 6", &context), Ok(true));
+        assert_eq!(evaluate("0.1 + 0.2 == 0.3", &context), Ok(false));
+        assert!(matches!(evaluate("count + 0.5 > 1.0", &context), Err(EvalError::TypeMismatch(_))));
+        assert_eq!(evaluate("count - 1 * 2 == 2 && count*3-1 == 11 && 10 - count - 1 == 5", &context), Ok(true));
+        assert_eq!(evaluate("count * 2 > 7 -> count - 4 is zero", &context), Ok(true));
+        assert_eq!(evaluate("1.5 * 2.0 - 0.5 == 2.5", &context), Ok(true));
+    }
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn decimal_is_exact() {
+        let mut context = HashMap::new();
+        context.insert("price".to_string(), ContextValue::Decimal(rust_decimal::Decimal::new(110, 2)));
+        assert_eq!(evaluate("dec'0.1' + dec'0.2' == dec'0.3'", &context), Ok(true));
+        assert_eq!(evaluate("price == dec'1.10' && price == dec'1.1'", &context), Ok(true));
+        assert_eq!(evaluate("price + 1 > 2 && price < 2", &context), Ok(true));
+        assert!(matches!(evaluate("price == 1.1", &context), Err(EvalError::TypeMismatch(_))));
+    }
+    #[cfg(not(feature = "decimal"))]
+    #[test]
+    fn decimal_requires_feature() {
+        assert!(matches!(evaluate("dec'0.1' == dec'0.1'", &Context::new()), Err(EvalError::UnexpectedInput { .. })));
+    }
+    #[test]
+    fn loose_equality() {
+        let mut context = HashMap::new();
+        context.insert("x".to_string(), ContextValue::String("5".to_string()));
+        assert!(matches!(evaluate("x == 5", &context), Err(EvalError::TypeMismatch(_))));
+        let options = EvalOptions { loose_equality: true, ..Default::default() };
+        assert_eq!(evaluate_with_options("x == 5", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("x != 5", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("x == '5'", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("5 == x || 5.0 != x", &context, &options), Ok(true));
+        assert!(matches!(evaluate_with_options("x < 5", &context, &options), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn regex_size_limit() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("abc".to_string()));
+        let options = EvalOptions { regex_size_limit: Some(10_000), ..Default::default() };
+        assert_eq!(evaluate_with_options("name =~ '^a.c$'", &context, &options), Ok(true));
+        let huge = format!("name =~ '(a|b|c){{1000}}' || name ===~ '{}'", "x".repeat(100_000));
+        assert_eq!(evaluate(&huge, &context), Ok(false));
+        assert_eq!(
+            evaluate_with_options(&huge, &context, &options),
+            Err(EvalError::InvalidRegex("Regex exceeds the size limit of 10000 bytes: (a|b|c){1000}".to_string()))
+        );
+        let expr = CompiledExpression::compile(&huge).unwrap();
+        assert!(matches!(expr.evaluate_with_options(&context, &options), Err(EvalError::InvalidRegex(_))));
+    }
+    #[test]
+    fn regex_provider() {
+        use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("abc".to_string()));
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        // the provider answers `^a` with a different regex, so a true result shows it was used instead of compiling the pattern
+        let provider = RegexProvider::new(move |pattern| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            (pattern == "^x").then(|| regex::Regex::new("^a").unwrap())
+        });
+        let options = EvalOptions { regex_provider: Some(provider.clone()), ..Default::default() };
+        assert_eq!(evaluate_with_options("name =~ '^x'", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("name =~ 'c$'", &context, &options), Ok(true));
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+        let expr = CompiledExpression::compile("name =~ '^x'").unwrap();
+        assert_eq!(expr.evaluate(&context), Ok(false));
+        assert_eq!(expr.evaluate_with_options(&context, &options), Ok(true));
+        let limited = EvalOptions { regex_size_limit: Some(10_000), ..options.clone() };
+        assert_eq!(evaluate_with_options("name =~ '^x'", &context, &limited), Ok(true));
+        assert_eq!(options, options.clone());
+        assert_ne!(options, EvalOptions { regex_provider: Some(RegexProvider::new(|_| None)), ..Default::default() });
+
+        let regexes: HashMap<String, regex::Regex> = ["^a", "^(?:a.c)$"].into_iter().map(|pattern| (pattern.to_string(), regex::Regex::new(pattern).unwrap())).collect();
+        let options = EvalOptions { regex_provider: Some(RegexProvider::new(move |pattern| regexes.get(pattern).cloned())), ..Default::default() };
+        assert_eq!(evaluate_with_options("name =~ '^a' && name ===~ 'a.c' && name =~ 'b'", &context, &options), Ok(true));
+    }
+    #[test]
+    fn float_tolerance() {
+        let mut context = HashMap::new();
+        context.insert("total".to_string(), ContextValue::Float(0.1 + 0.2));
+        assert_eq!(evaluate("0.1 + 0.2 == 0.3", &context), Ok(false));
+        assert_eq!(evaluate("total > 0.3", &context), Ok(true));
+        let options = EvalOptions { float_tolerance: Some(1e-9), ..Default::default() };
+        assert_eq!(evaluate_with_options("0.1 + 0.2 == 0.3", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("total != 0.3", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("total > 0.3 || total < 0.3", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("total <= 0.3 && total >= 0.3", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("total == 0.31", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("total < 0.31", &context, &options), Ok(true));
+        let negative = EvalOptions { float_tolerance: Some(-1.0), ..Default::default() };
+        assert!(matches!(evaluate_with_options("total == 0.3", &context, &negative), Err(EvalError::InvalidOption(_))));
+    }
+    #[test]
+    fn coerce_strings() {
+        let mut context = HashMap::new();
+        context.insert("port".to_string(), ContextValue::String("8081".to_string()));
+        context.insert("ratio".to_string(), ContextValue::String(" 0.5 ".to_string()));
+        context.insert("host".to_string(), ContextValue::String("localhost".to_string()));
+        assert!(matches!(evaluate("port > 8080", &context), Err(EvalError::TypeMismatch(_))));
+        let options = EvalOptions { coerce_strings: true, ..Default::default() };
+        assert_eq!(evaluate_with_options("port > 8080", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("8081 == port && port in (80, 8081)", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("ratio between 0.0 and 1.0", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("port == '8081' && port =~ '^80'", &context, &options), Ok(true));
+        assert_eq!(
+            evaluate_with_options("host > 8080", &context, &options),
+            Err(EvalError::TypeMismatch("Value should be an integer: 'localhost'".to_string()))
+        );
+        assert!(matches!(evaluate_with_options("port > 8080.0 && ratio > 0", &context, &options), Err(EvalError::TypeMismatch(_))));
+        let options = EvalOptions { loose_equality: true, ..options };
+        assert!(matches!(evaluate_with_options("host == 1", &context, &options), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn fallback_operator() {
+        let mut context = HashMap::new();
+        context.insert("username".to_string(), ContextValue::String("x".to_string()));
+        assert_eq!(evaluate("nickname ?: username == 'x'", &context), Ok(true));
+        context.insert("nickname".to_string(), ContextValue::String("y".to_string()));
+        assert_eq!(evaluate("nickname ?: username == 'x'", &context), Ok(false));
+        assert_eq!(evaluate("'x' == missing ?: username", &context), Ok(true));
+        assert_eq!(evaluate("count ?: 0 + 1 == 1", &context), Ok(true));
+    }
+    #[test]
+    fn null_coalescing() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("guest".to_string()));
+        assert_eq!(evaluate("nickname ?? name == 'guest'", &context), Ok(true));
+        context.insert("nickname".to_string(), ContextValue::String("bob".to_string()));
+        assert_eq!(evaluate("nickname ?? name == 'guest'", &context), Ok(false));
+        assert_eq!(parse("nickname??name == 'guest'").unwrap(), parse("nickname ?: name == 'guest'").unwrap());
+        // a provider which knows the key but has no value for it, like a null in JSON
+        let nulls = |key: &str| match key {
+            "nickname" => None,
+            _ => context.get(key).cloned(),
+        };
+        assert_eq!(evaluate_with_fn("nickname ?? name == 'guest'", nulls), Ok(true));
+        let context = HashMap::new();
+        assert!(matches!(evaluate("nickname ?? name == 'guest'", &context), Err(EvalError::UnknownIdentifier(_))));
+        assert_eq!(evaluate("nickname ?? name ?? 'guest' == 'guest'", &context), Ok(true));
+    }
+    #[test]
+    fn list_index() {
+        let mut context = HashMap::new();
+        context.insert("scores".to_string(), ContextValue::List(vec![ContextValue::Integer(95), ContextValue::Integer(42)]));
+        assert_eq!(evaluate("scores[0] > 90", &context), Ok(true));
+        assert_eq!(evaluate("scores[-1] > 90", &context), Ok(false));
+        assert!(matches!(evaluate("scores[2] > 90", &context), Err(EvalError::IndexOutOfBounds(_))));
+        context.insert("tags".to_string(), ContextValue::List(vec![ContextValue::String("urgent".to_string())]));
+        assert_eq!(evaluate("tags contains 'urgent' && tags[0] == 'urgent' && scores[0] > 50", &context), Ok(true));
+        assert!(matches!(evaluate("tags[1] == 'urgent' || tags contains 'urgent'", &context), Err(EvalError::IndexOutOfBounds(_))));
+    }
+    #[test]
+    fn nested_maps() {
+        let address = HashMap::from([("city".to_string(), ContextValue::String("Berlin".to_string()))]);
+        let user = HashMap::from([
+            ("name".to_string(), ContextValue::String("ada".to_string())),
+            ("address".to_string(), ContextValue::Map(address)),
+        ]);
+        let mut context = HashMap::new();
+        context.insert("user".to_string(), ContextValue::Map(user));
+        assert_eq!(evaluate("user.name == 'ada'", &context), Ok(true));
+        assert_eq!(evaluate("user.address.city == 'Berlin'", &context), Ok(true));
+        assert_eq!(evaluate("('name', 'ada') in user", &context), Ok(true));
+        assert_eq!(
+            evaluate("user.office.city == 'Berlin'", &context),
+            Err(EvalError::UnknownIdentifier("Identifier not found in context: user.office".to_string()))
+        );
+        assert!(matches!(evaluate("user.name.first == 'a'", &context), Err(EvalError::TypeMismatch(_))));
+        assert_eq!(evaluate("user.office.city ?: 'none' == 'none'", &context), Ok(true));
+        // a flat key with a dot takes precedence
+        context.insert("user.name".to_string(), ContextValue::String("grace".to_string()));
+        assert_eq!(evaluate("user.name == 'grace'", &context), Ok(true));
+        assert_eq!(
+            evaluate_outcome("user.address.city == 'Berlin'", &context).unwrap().diagnostics,
+            vec!["Context value is not used by the expression: user.name".to_string()]
+        );
+    }
+    #[test]
+    fn datetimes() {
+        let mut context = HashMap::new();
+        context.insert("created_at".to_string(), ContextValue::DateTime(1_700_000_000));
+        context.insert("updated_at".to_string(), ContextValue::DateTime(1_710_000_000));
+        assert_eq!(evaluate("created_at < updated_at && updated_at != created_at", &context), Ok(true));
+        assert_eq!(evaluate("created_at < '2024-01-01' && created_at >= '2023-11-14T22:13:20Z'", &context), Ok(true));
+        assert_eq!(evaluate("'2023-11-15T00:13:20+02:00' == created_at", &context), Ok(true));
+        assert_eq!(evaluate("created_at in ('2023-11-14', '2023-11-14T22:13:20Z')", &context), Ok(true));
+        assert!(matches!(evaluate("created_at < 'last week'", &context), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate("created_at < 1700000001", &context), Err(EvalError::TypeMismatch(_))));
+        let options = EvalOptions { now: Some(1_700_000_100), ..Default::default() };
+        assert_eq!(evaluate_with_options("created_at within_last 1h", &context, &options), Ok(true));
+    }
+    #[test]
+    fn relative_datetimes() {
+        let day = 86_400;
+        let now = 1_700_000_000;
+        let options = EvalOptions { now: Some(now), ..Default::default() };
+        let mut context = HashMap::new();
+        context.insert("created_at".to_string(), ContextValue::DateTime(now - 3 * day));
+        assert_eq!(evaluate_with_options("created_at > now() - '7d'", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("created_at + '2h' > now() - '2d'", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("now() - created_at == 259200", &context, &options), Ok(true));
+        context.insert("created_at".to_string(), ContextValue::DateTime(now - 8 * day));
+        assert_eq!(evaluate_with_options("created_at > now() - '7d'", &context, &options), Ok(false));
+        assert!(matches!(evaluate_with_options("created_at > now() - 'a week'", &context, &options), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(evaluate_with_options("created_at > now(1)", &context, &options), Err(EvalError::TypeMismatch(_))));
+    }
+    #[test]
+    fn outcome_compares_to_bool() {
+        let mut context = HashMap::new();
+        context.insert("foo".to_string(), ContextValue::Integer(3));
+        let outcome = evaluate_outcome("foo > 2", &context).unwrap();
+        assert!(outcome == true);
+        assert!(true == outcome);
+        assert!(*outcome);
+        assert!(outcome.diagnostics.is_empty());
+
+        let outcome = evaluate_outcome("foo < 2", &context).unwrap();
+        assert!(outcome == false);
+        assert!(!bool::from(outcome));
+    }
+    #[test]
+    fn outcome_reports_unused_context() {
+        let mut context = HashMap::new();
+        context.insert("foo".to_string(), ContextValue::Boolean(true));
+        context.insert("unused".to_string(), ContextValue::Integer(1));
+        let outcome = evaluate_outcome("foo", &context).unwrap();
+        assert_eq!(outcome.diagnostics, vec!["Context value is not used by the expression: unused".to_string()]);
+    }
+    #[cfg(feature = "collation")]
+    #[test]
+    fn collation_orders_strings_by_locale() {
+        let mut context = HashMap::new();
+        context.insert("lhs".to_string(), ContextValue::String("apple".to_string()));
+        context.insert("rhs".to_string(), ContextValue::String("Banana".to_string()));
+        let options = EvalOptions { collation: Some("en".to_string()), ..Default::default() };
+        assert_eq!(evaluate_with_options("lhs < rhs", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("lhs >= rhs", &context, &options), Ok(false));
+    }
+    #[cfg(feature = "normalization")]
+    #[test]
+    fn normalize_strings() {
+        let mut context = HashMap::new();
+        context.insert("composed".to_string(), ContextValue::String("caf\u{e9}".to_string()));
+        context.insert("decomposed".to_string(), ContextValue::String("cafe\u{301}".to_string()));
+        assert_eq!(evaluate("composed == decomposed", &context), Ok(false));
+        let options = EvalOptions { normalize_strings: true, ..Default::default() };
+        assert_eq!(evaluate_with_options("composed == decomposed", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("composed != decomposed", &context, &options), Ok(false));
+        assert_eq!(evaluate_with_options("decomposed >= composed && decomposed <= composed", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("decomposed in ('caf\u{e9}') && decomposed =~ '\u{e9}$'", &context, &options), Ok(true));
+        assert_eq!(evaluate_with_options("decomposed like 'caf?'", &context, &options), Ok(true));
+    }
+    #[cfg(not(feature = "normalization"))]
+    #[test]
+    fn normalization_requires_feature() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), ContextValue::String("caf\u{e9}".to_string()));
+        let options = EvalOptions { normalize_strings: true, ..Default::default() };
+        assert!(matches!(evaluate_with_options("name == 'cafe'", &context, &options), Err(EvalError::InvalidOption(_))));
+        assert_eq!(evaluate("name == 'cafe'", &context), Ok(false));
+    }
+    #[cfg(not(feature = "collation"))]
+    #[test]
+    fn collation_requires_feature() {
+        let mut context = HashMap::new();
+        context.insert("lhs".to_string(), ContextValue::String("apple".to_string()));
+        context.insert("rhs".to_string(), ContextValue::String("Banana".to_string()));
+        let options = EvalOptions { collation: Some("en".to_string()), ..Default::default() };
+        assert!(matches!(evaluate_with_options("lhs < rhs", &context, &options), Err(EvalError::InvalidOption(_))));
+    }
 }
 
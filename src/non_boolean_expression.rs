@@ -1,34 +1,153 @@
-use nom::{branch::alt, character::complete::multispace0, combinator::map, sequence::{delimited, tuple}, IResult};
-use regex::Regex;
+use std::{borrow::Cow, fmt};
 
-use crate::{operator::{binary_operator_number, binary_operator_string, BinaryOperator}, value::*};
+use nom::{branch::alt, bytes::complete::{tag, take_while1}, character::complete::{char, multispace0, multispace1}, combinator::{all_consuming, map, map_res, opt, peek}, error::ErrorKind, multi::separated_list1, sequence::{delimited, preceded, tuple}, IResult};
+use crate::{expression::{nested, strip_comments}, operator::{binary_operator_custom, binary_operator_dynamic, binary_operator_string, BinaryOperator}, value::*, collation::collate, error::message, normalization::nfc, EvalError, EvalEnv};
 
-#[derive(Debug, PartialEq, PartialOrd)]
-pub(crate) struct NonBooleanExpression(pub(crate) Value, pub (crate) BinaryOperator, pub (crate) Value);
+/// A comparison between two non boolean values, e.g. `count > 5` or `name =~ 'a+'`.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonBooleanExpression(pub Value, pub BinaryOperator, pub Value);
 impl NonBooleanExpression {
-  pub(crate) fn evaluate(&self) -> Result<bool, String> {
+  /// Resolves the identifiers of the comparison from the context and evaluates it.
+  pub(crate) fn evaluate(&self, env: &EvalEnv) -> Result<bool, EvalError> {
+    let resolved = self.clone().resolve(env)?;
+    if let NonBooleanExpression(lhs, BinaryOperator::Custom(name), rhs) = &resolved {
+      return env.call_operator(name, lhs, rhs);
+    }
+    #[cfg(feature = "decimal")]
+    let resolved = {
+      let NonBooleanExpression(lhs, op, rhs) = resolved;
+      let (lhs, rhs) = coerce_decimals(lhs, rhs);
+      NonBooleanExpression(lhs, op, rhs)
+    };
+    let NonBooleanExpression(lhs, op, rhs) = resolved;
+    let (lhs, rhs) = coerce_datetimes(lhs, rhs)?;
+    let comparison = matches!(op, BinaryOperator::Equals | BinaryOperator::NotEquals | BinaryOperator::LessThan | BinaryOperator::GreaterThan | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual);
+    let (lhs, rhs) = if env.options.coerce_strings && comparison { coerce_numbers(lhs, rhs)? } else { (lhs, rhs) };
+    NonBooleanExpression(lhs, op, rhs).evaluate_values(env)
+  }
+  fn evaluate_values(&self, env: &EvalEnv) -> Result<bool, EvalError> {
+    match (&self.0, &self.1, &self.2) {
+      (value, BinaryOperator::In, Value::List(items)) | (Value::List(items), BinaryOperator::Contains, value) => return Self::eval_in(value, items, env),
+      (Value::StringLiteral(lhs), BinaryOperator::Contains, Value::StringLiteral(rhs)) => return Ok(lhs.contains(rhs.as_str())),
+      (value, BinaryOperator::All(op), Value::List(items)) => return Self::eval_quantified(value, op, items, true, env),
+      (value, BinaryOperator::Any(op), Value::List(items)) => return Self::eval_quantified(value, op, items, false, env),
+      (_, BinaryOperator::In | BinaryOperator::All(_) | BinaryOperator::Any(_), rhs) => return Err(EvalError::TypeMismatch(message(|| format!("Value should be a list: {}", rhs)))),
+      (lhs, BinaryOperator::Contains, _) => return Err(EvalError::TypeMismatch(message(|| format!("Value should be a string or a list: {}", lhs)))),
+      (Value::List(lhs), op, Value::List(rhs)) => return self.eval_tuple(lhs, op, rhs, env),
+      _ => {}
+    }
+    if env.options.loose_equality && std::mem::discriminant(&self.0) != std::mem::discriminant(&self.2) {
+      match self.1 {
+        BinaryOperator::Equals => return Ok(false),
+        BinaryOperator::NotEquals => return Ok(true),
+        _ => {}
+      }
+    }
+    // both operands are only known after resolving identifiers, so the types are checked here and not by the parser
+    if std::mem::discriminant(&self.0) != std::mem::discriminant(&self.2) {
+      return Err(EvalError::TypeMismatch(message(|| format!("Can not compare {} with {}: {}", self.0.type_name(), self.2.type_name(), self))));
+    }
+    #[cfg(feature = "decimal")]
+    if let Value::DecimalLiteral(_) = self.0 {
+      return self.eval_decimal();
+    }
+    if let Value::DateTime(_) = self.0 {
+      return self.eval_datetime();
+    }
     if let Value::StringLiteral(_) = self.0 {
-      self.eval_string()
+      self.eval_string(env)
     } else if let Value::IntegerLiteral(_) = self.0 {
       self.eval_integer()
+    } else if let Value::Boolean(_) = self.0 {
+      self.eval_boolean()
     } else {
-      self.eval_float()
+      self.eval_float(env.options.float_tolerance)
+    }
+  }
+  /// Whether the value equals an element of the list, the elements are compared like with `==`.
+  fn eval_in(value: &Value, items: &[Value], env: &EvalEnv) -> Result<bool, EvalError> {
+    for item in items {
+      if NonBooleanExpression(value.clone(), BinaryOperator::Equals, item.clone()).evaluate(env)? {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+  /// Compares the value with the elements in their order like separate comparisons, all is true for `all` and false for `any`.
+  /// The elements are resolved with the rest of the comparison, so an unknown identifier fails it even after the result is decided.
+  fn eval_quantified(value: &Value, op: &BinaryOperator, items: &[Value], all: bool, env: &EvalEnv) -> Result<bool, EvalError> {
+    for item in items {
+      if NonBooleanExpression(value.clone(), op.clone(), item.clone()).evaluate(env)? != all {
+        return Ok(!all);
+      }
+    }
+    Ok(all)
+  }
+  /// Tuples are equal if all their elements are equal, the elements are compared like with `==`.
+  /// Comparing tuples of different lengths is an error, like comparing values of different types.
+  fn eval_tuple(&self, lhs: &[Value], op: &BinaryOperator, rhs: &[Value], env: &EvalEnv) -> Result<bool, EvalError> {
+    if !matches!(op, BinaryOperator::Equals | BinaryOperator::NotEquals) {
+      return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for tuple: {:?}", op))));
+    }
+    if lhs.len() != rhs.len() {
+      return Err(EvalError::TypeMismatch(message(|| format!("Can not compare tuples of length {} and {}: {}", lhs.len(), rhs.len(), self))));
+    }
+    let mut equal = true;
+    for (lhs, rhs) in lhs.iter().zip(rhs) {
+      if !NonBooleanExpression(lhs.clone(), BinaryOperator::Equals, rhs.clone()).evaluate(env)? {
+        equal = false;
+        break;
+      }
     }
+    Ok(equal == (*op == BinaryOperator::Equals))
   }
-  fn eval_string(&self) -> Result<bool, String> {
+  /// Strings are ordered by their bytes, which is the lexicographic order of their UTF-8 encoding,
+  /// unless a collation is set in the options. With EvalOptions::normalize_strings both strings are normalized first.
+  fn eval_string(&self, env: &EvalEnv) -> Result<bool, EvalError> {
     if let NonBooleanExpression(Value::StringLiteral(lhs), op, Value::StringLiteral(rhs))  = &self{
+      let (lhs, rhs) = match env.options.normalize_strings {
+        true => (nfc(lhs)?, nfc(rhs)?),
+        false => (Cow::Borrowed(lhs.as_str()), Cow::Borrowed(rhs.as_str())),
+      };
+      Ok(match (op, &env.options.collation) {
+        (BinaryOperator::Equals, _) => lhs == rhs,
+        (BinaryOperator::NotEquals, _) => lhs != rhs,
+        (BinaryOperator::RegexMatch, _) => 
+          env.regex(&rhs)?.is_match(&lhs),
+        (BinaryOperator::FullMatch, _) => env.regex(&full_match(&rhs))?.is_match(&lhs),
+        (BinaryOperator::Like, _) => env.regex(&glob_to_regex(&rhs))?.is_match(&lhs),
+        (BinaryOperator::LessThan, Some(locale)) => collate(locale, &lhs, &rhs)?.is_lt(),
+        (BinaryOperator::GreaterThan, Some(locale)) => collate(locale, &lhs, &rhs)?.is_gt(),
+        (BinaryOperator::LessEqual, Some(locale)) => collate(locale, &lhs, &rhs)?.is_le(),
+        (BinaryOperator::GreaterEqual, Some(locale)) => collate(locale, &lhs, &rhs)?.is_ge(),
+        (BinaryOperator::LessThan, None) => lhs < rhs,
+        (BinaryOperator::GreaterThan, None) => lhs > rhs,
+        (BinaryOperator::LessEqual, None) => lhs <= rhs,
+        (BinaryOperator::GreaterEqual, None) => lhs >= rhs,
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for string: {:?}", op))))
+      })
+    } else {
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary String expression: {:?}", self))))
+    }
+  }
+  fn eval_boolean(&self) -> Result<bool, EvalError> {
+    if let NonBooleanExpression(Value::Boolean(lhs), op, Value::Boolean(rhs)) = &self{
       Ok(match op {
         BinaryOperator::Equals => lhs == rhs,
         BinaryOperator::NotEquals => lhs != rhs,
-        BinaryOperator::RegexMatch => 
-          Regex::new(&rhs).map_err(|_| format!("Invalid regex: {}", rhs))?.is_match(&lhs),
-        _ => return Err(format!("Invalid binary operator for string: {:?}", op))
+        // false is less than true
+        BinaryOperator::LessThan => lhs < rhs,
+        BinaryOperator::GreaterThan => lhs > rhs,
+        BinaryOperator::LessEqual => lhs <= rhs,
+        BinaryOperator::GreaterEqual => lhs >= rhs,
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for boolean: {:?}", op))))
       })
     } else {
-      Err(format!("Not a Binary String expression: {:?}", self))
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary Boolean expression: {:?}", self))))
     }
   }
-  fn eval_integer(&self) -> Result<bool, String> {
+  fn eval_integer(&self) -> Result<bool, EvalError> {
     if let NonBooleanExpression(Value::IntegerLiteral(lhs), op, Value::IntegerLiteral(rhs)) = &self{
 
       Ok(match op {
@@ -38,15 +157,14 @@ impl NonBooleanExpression {
         BinaryOperator::GreaterThan => lhs > rhs,
         BinaryOperator::LessEqual => lhs <= rhs,
         BinaryOperator::GreaterEqual => lhs >= rhs,
-        _ => return Err(format!("Invalid binary operator for number: {:?}", op))
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for number: {:?}", op))))
       })
     } else {
-      Err(format!("Not a Binary Integer expression: {:?}", self))
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary Integer expression: {:?}", self))))
     }
   }
-  fn eval_float(&self) -> Result<bool, String> {
-    if let NonBooleanExpression(Value::FloatLiteral(lhs), op, Value::FloatLiteral(rhs)) = &self{
-      
+  fn eval_datetime(&self) -> Result<bool, EvalError> {
+    if let NonBooleanExpression(Value::DateTime(lhs), op, Value::DateTime(rhs)) = &self {
       Ok(match op {
         BinaryOperator::Equals => lhs == rhs,
         BinaryOperator::NotEquals => lhs != rhs,
@@ -54,30 +172,301 @@ impl NonBooleanExpression {
         BinaryOperator::GreaterThan => lhs > rhs,
         BinaryOperator::LessEqual => lhs <= rhs,
         BinaryOperator::GreaterEqual => lhs >= rhs,
-        _ => return Err(format!("Invalid binary operator for number: {:?}", op))
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for datetime: {:?}", op))))
+      })
+    } else {
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary DateTime expression: {:?}", self))))
+    }
+  }
+  /// A comparison with NaN is false, only `!=` is true. NaN is not equal to itself and not ordered
+  /// against any number, so `x != x` is the way to check for NaN.
+  /// With a tolerance floats which differ by at most the tolerance are equal, e.g. `0.1 + 0.2 == 0.3`,
+  /// and they are neither less nor greater than each other.
+  fn eval_float(&self, tolerance: Option<f64>) -> Result<bool, EvalError> {
+    if let NonBooleanExpression(Value::FloatLiteral(lhs), op, Value::FloatLiteral(rhs)) = &self{
+      if lhs.is_nan() || rhs.is_nan() {
+        return match op {
+          BinaryOperator::NotEquals => Ok(true),
+          BinaryOperator::Equals | BinaryOperator::LessThan | BinaryOperator::GreaterThan
+          | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual => Ok(false),
+          _ => Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for number: {:?}", op)))),
+        };
+      }
+      let equal = match tolerance {
+        Some(tolerance) if tolerance.is_nan() || tolerance < 0.0 =>
+          return Err(EvalError::InvalidOption(message(|| format!("Float tolerance should be a non-negative number: {}", tolerance)))),
+        Some(tolerance) => lhs == rhs || (lhs - rhs).abs() <= tolerance,
+        None => lhs == rhs,
+      };
+      Ok(match op {
+        BinaryOperator::Equals => equal,
+        BinaryOperator::NotEquals => !equal,
+        BinaryOperator::LessThan => lhs < rhs && !equal,
+        BinaryOperator::GreaterThan => lhs > rhs && !equal,
+        BinaryOperator::LessEqual => lhs < rhs || equal,
+        BinaryOperator::GreaterEqual => lhs > rhs || equal,
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for number: {:?}", op))))
       })
     } else {
-      Err(format!("Not a Binary Integer expression: {:?}", self))
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary Integer expression: {:?}", self))))
     }
   }
   
-  pub(crate) fn use_context(self, context: &std::collections::HashMap<String, crate::ContextValue>) -> Result<Self, String> {
-    Ok(NonBooleanExpression(self.0.use_context(context)?, self.1, self.2.use_context(context)?))
+  /// Decimals compare exactly, integers on either side were converted to decimals before.
+  #[cfg(feature = "decimal")]
+  fn eval_decimal(&self) -> Result<bool, EvalError> {
+    if let NonBooleanExpression(Value::DecimalLiteral(lhs), op, Value::DecimalLiteral(rhs)) = &self {
+      Ok(match op {
+        BinaryOperator::Equals => lhs == rhs,
+        BinaryOperator::NotEquals => lhs != rhs,
+        BinaryOperator::LessThan => lhs < rhs,
+        BinaryOperator::GreaterThan => lhs > rhs,
+        BinaryOperator::LessEqual => lhs <= rhs,
+        BinaryOperator::GreaterEqual => lhs >= rhs,
+        _ => return Err(EvalError::InvalidOperator(message(|| format!("Invalid binary operator for number: {:?}", op))))
+      })
+    } else {
+      Err(EvalError::TypeMismatch(message(|| format!("Not a Binary Decimal expression: {:?}", self))))
     }
+  }
+
+  pub(crate) fn substitute(self, context: &crate::Context, known: &dyn Fn(&Identifier) -> bool) -> Result<Self, EvalError> {
+    let op = match self.1 {
+      BinaryOperator::Dynamic(ident) if known(&ident) => BinaryOperator::Dynamic(ident).use_context(context)?,
+      op => op,
+    };
+    Ok(NonBooleanExpression(self.0.substitute(context, known)?, op, self.2.substitute(context, known)?))
+  }
+
+  pub(crate) fn rename_identifiers(self, f: &dyn Fn(&str) -> String) -> Self {
+    let op = match self.1 {
+      BinaryOperator::Dynamic(ident) => BinaryOperator::Dynamic(ident.rename(f)),
+      op => op,
+    };
+    NonBooleanExpression(self.0.rename_identifiers(f), op, self.2.rename_identifiers(f))
+  }
+
+  pub(crate) fn resolve(self, env: &EvalEnv) -> Result<Self, EvalError> {
+    Ok(NonBooleanExpression(self.0.resolve(env)?, self.1.use_context(env.context)?, self.2.resolve(env)?))
+    }
+  /// Whether the comparison only uses literals and its result doesn't depend on the options,
+  /// strings are ordered by the collation of the options. Custom operators depend on the registered functions.
+  pub(crate) fn is_constant(&self) -> bool {
+    if let Some(comparisons) = self.quantified_comparisons() {
+      return comparisons.iter().all(NonBooleanExpression::is_constant);
+    }
+    let ordering = matches!(self.1, BinaryOperator::LessThan | BinaryOperator::GreaterThan | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual);
+    let strings = matches!(self.0, Value::StringLiteral(_)) || matches!(self.2, Value::StringLiteral(_));
+    self.0.is_constant() && self.2.is_constant() && !matches!(self.1, BinaryOperator::Dynamic(_) | BinaryOperator::Custom(_)) && !(ordering && strings)
+  }
+  /// The patterns of a regex match against a string literal and of the regex functions the values call.
+  pub(crate) fn regex_patterns(&self) -> Vec<String> {
+    if let Some(comparisons) = self.quantified_comparisons() {
+      return comparisons.iter().flat_map(NonBooleanExpression::regex_patterns).collect();
+    }
+    let pattern = match self {
+      NonBooleanExpression(_, BinaryOperator::RegexMatch, Value::StringLiteral(pattern)) => Some(pattern.clone()),
+      NonBooleanExpression(_, BinaryOperator::FullMatch, Value::StringLiteral(pattern)) => Some(full_match(pattern)),
+      NonBooleanExpression(_, BinaryOperator::Like, Value::StringLiteral(pattern)) => Some(glob_to_regex(pattern)),
+      _ => None,
+    };
+    self.0.regex_patterns().into_iter().chain(pattern).chain(self.2.regex_patterns()).collect()
+  }
+  /// The byte range in the parsed expression from the first to the last identifier of the comparison, see Identifier::span().
+  pub fn span(&self) -> Option<std::ops::Range<usize>> {
+    covering_span(self.identifiers())
+  }
+  pub(crate) fn identifiers(&self) -> Vec<&Identifier> {
+    let op = match &self.1 {
+      BinaryOperator::Dynamic(ident) => Some(ident),
+      _ => None,
+    };
+    self.0.identifiers().into_iter().chain(op).chain(self.2.identifiers()).collect()
+  }
+  /// The comparisons of the value with each element of the list if the comparison is quantified with `all` or `any`.
+  pub(crate) fn quantified_comparisons(&self) -> Option<Vec<NonBooleanExpression>> {
+    match self {
+      NonBooleanExpression(value, BinaryOperator::All(op) | BinaryOperator::Any(op), Value::List(items)) =>
+        Some(items.iter().map(|item| NonBooleanExpression(value.clone(), (**op).clone(), item.clone())).collect()),
+      _ => None,
+    }
+  }
+  /// The string literals of the values, or with regexes set only the pattern of a regex match.
+  pub(crate) fn literals(&self, regexes: bool) -> Vec<&str> {
+    let regex = |op: &BinaryOperator| matches!(op, BinaryOperator::RegexMatch | BinaryOperator::FullMatch);
+    if let NonBooleanExpression(value, BinaryOperator::All(op) | BinaryOperator::Any(op), Value::List(items)) = self {
+      let patterns = items.iter().filter_map(|item| match item {
+        Value::StringLiteral(pattern) => Some(pattern.as_str()),
+        _ => None,
+      });
+      return match (regexes, regex(op)) {
+        (true, true) => patterns.collect(),
+        (true, false) => vec![],
+        (false, true) => value.string_literals(),
+        (false, false) => value.string_literals().into_iter().chain(items.iter().flat_map(Value::string_literals)).collect(),
+      };
+    }
+    let pattern = match self {
+      NonBooleanExpression(_, op, Value::StringLiteral(pattern)) if regex(op) => Some(pattern.as_str()),
+      _ => None,
+    };
+    match (regexes, pattern) {
+      (true, pattern) => pattern.into_iter().collect(),
+      (false, Some(_)) => self.0.string_literals(),
+      (false, None) => self.0.string_literals().into_iter().chain(self.2.string_literals()).collect(),
+    }
+  }
+  pub(crate) fn contains_identifier(&self, name: &str) -> bool {
+    self.0.contains_identifier(name) || matches!(&self.1, BinaryOperator::Dynamic(ident) if ident.name() == name) || self.2.contains_identifier(name)
+  }
+}
+
+impl fmt::Display for NonBooleanExpression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} {} {}", self.0, self.1, self.2)
+  }
 }
 
+/// Parses a comparison of two values. Any value may be compared with any operator,
+/// literals are checked afterwards so e.g. `2.0 == 1` or `5 =~ 'a'` is rejected while parsing.
 pub(crate) fn binary_non_bool(input: &str) -> IResult<&str, NonBooleanExpression> {
-  alt((
-    map(tuple((integer, delimited(multispace0, binary_operator_number, multispace0), integer)), |(first, op, second)| NonBooleanExpression(first, op, second)),
-    map(tuple((float, delimited(multispace0, binary_operator_number, multispace0), float)), |(first, op, second)| NonBooleanExpression(first, op, second)),
-    map(tuple((string, delimited(multispace0, binary_operator_string, multispace0), string)), |(first, op, second)| NonBooleanExpression(first, op, second)),
-  ))(input)
+  map_res(tuple((operand, delimited(multispace0, comparison_operator, multispace0), operand, opt(regex_flags))), |(first, op, second, flags)| {
+    check_literals(&first, &op, &second)?;
+    match (flags, op, second) {
+      (None, op, second) => Ok(NonBooleanExpression(first, op, second)),
+      (Some(flags), op @ (BinaryOperator::RegexMatch | BinaryOperator::FullMatch), Value::StringLiteral(pattern)) =>
+        Ok(NonBooleanExpression(first, op, Value::StringLiteral(format!("(?{}){}", flags, pattern)))),
+      (Some(flags), op, second) => Err(format!("Regex flags /{} require a regex literal: {} {}", flags, op, second)),
+    }
+  })(input)
+}
+
+/// A comparison with every element of a list like `x > all (10, 20)` or `name =~ any ('^a', '^b')`.
+/// The operator is one of the comparison operators, `${name}` and custom operators can't be quantified.
+pub(crate) fn quantified(input: &str) -> IResult<&str, NonBooleanExpression> {
+  let quantifier = alt((tag("all"), tag("any")));
+  let separator = alt((multispace1, peek(tag("("))));
+  map_res(tuple((operand, delimited(multispace0, binary_operator_string, multispace0), quantifier, separator, list)), |(value, op, quantifier, _, items)| {
+    if let Value::List(items) = &items {
+      for item in items {
+        check_literals(&value, &op, item)?;
+      }
+    }
+    let op = match quantifier {
+      "all" => BinaryOperator::All(Box::new(op)),
+      _ => BinaryOperator::Any(Box::new(op)),
+    };
+    Ok::<_, String>(NonBooleanExpression(value, op, items))
+  })(input)
+}
+
+/// A membership test like `x in (1, 2, 3)` or `name contains 'a'`, true if it is negated with `not`.
+pub(crate) fn membership(input: &str) -> IResult<&str, (NonBooleanExpression, bool)> {
+  let op = map_res(alt((tag("in"), tag("contains"))), BinaryOperator::try_from);
+  let separator = alt((multispace1, peek(tag("("))));
+  map(tuple((operand, multispace1, opt(tuple((tag("not"), multispace1))), op, separator, alt((list, operand)))),
+    |(lhs, _, not, op, _, rhs)| (NonBooleanExpression(lhs, op, rhs), not.is_some())
+  )(input)
+}
+
+/// A list literal like `(1, 2, 3)`.
+fn list(input: &str) -> IResult<&str, Value> {
+  let separator = tuple((multispace0, char(','), multispace0));
+  map(delimited(tuple((char('('), multispace0)), separated_list1(separator, operand), tuple((multispace0, char(')')))), Value::List)(input)
+}
+
+/// A tuple like `(a, b)` which has at least two elements, `(a)` is a parenthesized boolean expression.
+/// Tuples can be nested, so they count towards the maximum depth like parentheses.
+/// Exceeding it only fails the tuple, the parentheses may still start a boolean expression.
+fn tuple_literal(input: &str) -> IResult<&str, Value> {
+  match nested(input, tuple_elements) {
+    Err(nom::Err::Failure(err)) if err.code == ErrorKind::TooLarge => Err(nom::Err::Error(err)),
+    result => result,
+  }
+}
+fn tuple_elements(input: &str) -> IResult<&str, Value> {
+  let separator = |input| tuple((multispace0, char(','), multispace0))(input);
+  map(
+    delimited(tuple((char('('), multispace0)), tuple((operand, separator, separated_list1(separator, operand))), tuple((multispace0, char(')')))),
+    |(first, _, mut rest)| {
+      rest.insert(0, first);
+      Value::List(rest)
+    }
+  )(input)
 }
 
+fn operand(input: &str) -> IResult<&str, Value> {
+  alt((tuple_literal, fallback))(input)
+}
+
+/// Parses the whole input as a single value like `1 + 2` or `name`, None if it is not a value but e.g. a boolean expression.
+pub(crate) fn parse_whole_value(input: &str) -> Result<Option<Value>, EvalError> {
+  let input = strip_comments(input)?;
+  let parsed = with_source(&input, || all_consuming(delimited(multispace0, operand, multispace0))(&input).ok().map(|(_, value)| value));
+  Ok(parsed)
+}
+
+fn comparison_operator(input: &str) -> IResult<&str, BinaryOperator> {
+  alt((binary_operator_string, binary_operator_dynamic, binary_operator_custom))(input)
+}
+
+/// Rejects comparisons of literals which can never be evaluated, identifiers are only checked during evaluation.
+fn check_literals(lhs: &Value, op: &BinaryOperator, rhs: &Value) -> Result<(), String> {
+  let literal_type = |value: &Value| match value {
+    Value::StringLiteral(_) => Some("string"),
+    Value::IntegerLiteral(_) => Some("integer"),
+    Value::FloatLiteral(_) => Some("float"),
+    _ => None,
+  };
+  // the registered operator checks the types of its operands
+  if let BinaryOperator::Custom(_) = op {
+    return Ok(());
+  }
+  let types = [literal_type(lhs), literal_type(rhs)];
+  if let [Some(lhs_type), Some(rhs_type)] = types {
+    if lhs_type != rhs_type {
+      return Err(format!("Can not compare {} with {}: {} {} {}", lhs_type, rhs_type, lhs, op, rhs));
+    }
+  }
+  let supported = match op {
+    BinaryOperator::RegexMatch | BinaryOperator::FullMatch | BinaryOperator::Like => !types.contains(&Some("integer")) && !types.contains(&Some("float")),
+    _ => true,
+  };
+  if supported {
+    Ok(())
+  } else {
+    Err(format!("Operator {} does not support the literal: {} {} {}", op, lhs, op, rhs))
+  }
+}
+
+/// Anchors a pattern so it has to match the whole string.
+fn full_match(pattern: &str) -> String {
+  format!("^(?:{})$", pattern)
+}
+
+/// Translates a glob pattern to a regex matching the whole string, `*` matches any characters and `?` a single one.
+fn glob_to_regex(pattern: &str) -> String {
+  let mut regex = String::from("^(?s:");
+  for c in pattern.chars() {
+    match c {
+      '*' => regex.push_str(".*"),
+      '?' => regex.push('.'),
+      c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+    }
+  }
+  regex.push_str(")$");
+  regex
+}
+
+/// Flags after a regex literal, e.g. `'^error' /mi`, turned into an inline group of the pattern.
+fn regex_flags(input: &str) -> IResult<&str, &str> {
+  preceded(tuple((multispace0, char('/'))), take_while1(|c: char| "imsxU".contains(c)))(input)
+}
 
 #[cfg(test)]
 mod test_non_bool_expression {
   use super::*;
+  use crate::EvalOptions;
   
   #[test]
   fn parse_test() {
@@ -105,27 +494,153 @@ mod test_non_bool_expression {
     assert_eq!(e, NonBooleanExpression(Value::FloatLiteral(2.0), BinaryOperator::Equals, Value::Identifier(Identifier::from("mode"))));
   }
   #[test]
+  fn parse_test_dynamic_operator() {
+    let e = binary_non_bool("2.5 ${op} mode").unwrap().1;
+    assert_eq!(e, NonBooleanExpression(Value::FloatLiteral(2.5), BinaryOperator::Dynamic(Identifier::from("op")), Value::Identifier(Identifier::from("mode"))));
+    let e = binary_non_bool("name ${op} 'a+'").unwrap().1;
+    assert_eq!(e, NonBooleanExpression(Value::Identifier(Identifier::from("name")), BinaryOperator::Dynamic(Identifier::from("op")), Value::StringLiteral("a+".to_string())));
+  }
+  #[test]
+  fn parse_test_regex_flags() {
+    let e = binary_non_bool("block =~ '^ERROR' /mi").unwrap().1;
+    assert_eq!(e, NonBooleanExpression(Value::Identifier(Identifier::from("block")), BinaryOperator::RegexMatch, Value::StringLiteral("(?mi)^ERROR".to_string())));
+    assert!(binary_non_bool("block == '^ERROR' /m").is_err());
+    assert!(binary_non_bool("block =~ pattern /m").is_err());
+  }
+  #[test]
+  fn parse_test_identifiers() {
+    let ident = |name: &str| Value::Identifier(Identifier::from(name));
+    let operators = [
+      ("==", BinaryOperator::Equals),
+      ("!=", BinaryOperator::NotEquals),
+      ("<", BinaryOperator::LessThan),
+      (">", BinaryOperator::GreaterThan),
+      ("<=", BinaryOperator::LessEqual),
+      (">=", BinaryOperator::GreaterEqual),
+      ("=~", BinaryOperator::RegexMatch),
+      ("===~", BinaryOperator::FullMatch),
+      ("like", BinaryOperator::Like),
+      ("${op}", BinaryOperator::Dynamic(Identifier::from("op"))),
+    ];
+    for (symbol, op) in operators {
+      let input = format!("a {} b", symbol);
+      assert_eq!(binary_non_bool(&input).unwrap(), ("", NonBooleanExpression(ident("a"), op, ident("b"))));
+    }
+  }
+  #[test]
+  fn parse_test_literal_after_identifier() {
+    let e = binary_non_bool("count > 1.5").unwrap();
+    assert_eq!(e, ("", NonBooleanExpression(Value::Identifier(Identifier::from("count")), BinaryOperator::GreaterThan, Value::FloatLiteral(1.5))));
+    let e = binary_non_bool("name =~ 'a+'").unwrap();
+    assert_eq!(e, ("", NonBooleanExpression(Value::Identifier(Identifier::from("name")), BinaryOperator::RegexMatch, Value::StringLiteral("a+".to_string()))));
+    let e = binary_non_bool("1.5 <= count").unwrap();
+    assert_eq!(e, ("", NonBooleanExpression(Value::FloatLiteral(1.5), BinaryOperator::LessEqual, Value::Identifier(Identifier::from("count")))));
+    assert!(binary_non_bool("5 =~ name").is_err());
+    assert!(binary_non_bool("'a' == 1").is_err());
+  }
+  #[test]
   fn parse_test_error() {
     let e = binary_non_bool("2.0 == 1");
     assert!(e.is_err())
   }
 
+  #[test]
+  fn test_eval_float_nan() {
+    let mut context = crate::Context::new();
+    context.insert("nan".to_string(), crate::ContextValue::Float(f64::NAN));
+    context.insert("one".to_string(), crate::ContextValue::Float(1.0));
+    let options = EvalOptions::default();
+    let env = EvalEnv::new(&context, &options);
+    let ops = ["==", "!=", "<", ">", "<=", ">="];
+    for (lhs, rhs) in [("nan", "nan"), ("nan", "one"), ("one", "nan")] {
+      for op in ops {
+        let e = binary_non_bool(&format!("{} {} {}", lhs, op, rhs)).unwrap().1;
+        assert_eq!(e.evaluate(&env), Ok(op == "!="), "{} {} {}", lhs, op, rhs);
+      }
+    }
+    let e = NonBooleanExpression(Value::FloatLiteral(f64::NAN), BinaryOperator::RegexMatch, Value::FloatLiteral(1.0));
+    assert!(matches!(e.eval_float(None), Err(EvalError::InvalidOperator(_))));
+  }
+
   #[test]
   fn test_eval_string() {
+    let (context, options) = (crate::Context::new(), EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::Equals, Value::StringLiteral("test".to_string()));
-    assert_eq!(e.eval_string(), Ok(true));
+    assert_eq!(e.eval_string(&env), Ok(true));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::NotEquals, Value::StringLiteral("test".to_string()));
-    assert_eq!(e.eval_string(), Ok(false));
+    assert_eq!(e.eval_string(&env), Ok(false));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::RegexMatch, Value::StringLiteral("t.*t".to_string()));
-    assert_eq!(e.eval_string(), Ok(true));
+    assert_eq!(e.eval_string(&env), Ok(true));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::RegexMatch, Value::StringLiteral("t.t".to_string()));
-    assert_eq!(e.eval_string(), Ok(false));
+    assert_eq!(e.eval_string(&env), Ok(false));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::Equals, Value::StringLiteral("nope".to_string()));
-    assert_eq!(e.eval_string(), Ok(false));
+    assert_eq!(e.eval_string(&env), Ok(false));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::RegexMatch, Value::StringLiteral("t..t".to_string()));
-    assert_eq!(e.eval_string(), Ok(true));
+    assert_eq!(e.eval_string(&env), Ok(true));
     let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::LessEqual, Value::StringLiteral("t..t".to_string()));
-    assert_eq!(e.eval_string(), Err("Invalid binary operator for string: LessEqual".to_string()));
+    assert_eq!(e.eval_string(&env), Ok(false));
+    let e = NonBooleanExpression(Value::StringLiteral("test".to_string()), BinaryOperator::Modulo, Value::StringLiteral("t..t".to_string()));
+    assert_eq!(e.eval_string(&env), Err(EvalError::InvalidOperator("Invalid binary operator for string: Modulo".to_string())));
+  }
+
+  #[test]
+  fn test_eval_like() {
+    let (context, options) = (crate::Context::new(), EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
+    let tests = [
+      ("'report.txt' like '*.txt'", true),
+      ("'report.csv' like '*.txt'", false),
+      ("'report.txt.bak' like '*.txt'", false),
+      ("'a.txt' like '?.txt'", true),
+      ("'ab.txt' like '?.txt'", false),
+      ("'abtxt' like '*.txt'", false),
+      ("'(a)+' like '(?)+'", true),
+      ("'line\nbreak' like 'line*'", true),
+    ];
+    for (input, expected) in tests {
+      assert_eq!(binary_non_bool(input).unwrap().1.evaluate(&env), Ok(expected), "{}", input);
+    }
+    assert!(binary_non_bool("1 like '1'").is_err());
+    assert!(binary_non_bool("name likewise").is_err());
+    assert_eq!(binary_non_bool("name like pattern").unwrap().1.to_string(), "name like pattern");
+  }
+
+  #[test]
+  fn test_eval_full_match() {
+    let (context, options) = (crate::Context::new(), EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
+    let tests = [
+      ("'abc' =~ 'b'", true),
+      ("'abc' ===~ 'b'", false),
+      ("'abc' ===~ 'a.c'", true),
+      ("'ab' =~ 'a|ab'", true),
+      ("'ab' ===~ 'a|ab'", true),
+      ("'xab' ===~ 'a|ab'", false),
+      ("'ABC' ===~ 'abc' /i", true),
+    ];
+    for (input, expected) in tests {
+      assert_eq!(binary_non_bool(input).unwrap().1.evaluate(&env), Ok(expected), "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_eval_string_ordering() {
+    let (context, options) = (crate::Context::new(), EvalOptions::default());
+    let env = EvalEnv::new(&context, &options);
+    let tests = [
+      ("'apple' < 'banana'", true),
+      ("'banana' > 'apple'", true),
+      ("'apple' <= 'apple'", true),
+      ("'apple' >= 'apple'", true),
+      ("'apple' < 'apple'", false),
+      ("'app' < 'apple'", true),
+      ("'Zebra' < 'apple'", true),
+      ("'été' > 'zebra'", true),
+    ];
+    for (input, expected) in tests {
+      assert_eq!(binary_non_bool(input).unwrap().1.evaluate(&env), Ok(expected), "{}", input);
+    }
   }
 
   #[test]
@@ -145,12 +660,12 @@ mod test_non_bool_expression {
     let e = NonBooleanExpression(Value::IntegerLiteral(1), BinaryOperator::Equals, Value::IntegerLiteral(2));
     assert_eq!(e.eval_integer(), Ok(false));
     let e = NonBooleanExpression(Value::FloatLiteral(1.0), BinaryOperator::LessThan, Value::FloatLiteral(2.0));
-    assert_eq!(e.eval_float(), Ok(true));
+    assert_eq!(e.eval_float(None), Ok(true));
     let e = NonBooleanExpression(Value::FloatLiteral(1.0), BinaryOperator::GreaterThan, Value::FloatLiteral(2.0));
-    assert_eq!(e.eval_float(), Ok(false));
+    assert_eq!(e.eval_float(None), Ok(false));
     let e = NonBooleanExpression(Value::FloatLiteral(1.0), BinaryOperator::LessEqual, Value::FloatLiteral(2.0));
-    assert_eq!(e.eval_float(), Ok(true));
+    assert_eq!(e.eval_float(None), Ok(true));
     let e = NonBooleanExpression(Value::FloatLiteral(1.0), BinaryOperator::GreaterEqual, Value::FloatLiteral(2.0));
-    assert_eq!(e.eval_float(), Ok(false));
+    assert_eq!(e.eval_float(None), Ok(false));
   }
 }
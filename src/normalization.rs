@@ -0,0 +1,28 @@
+use std::borrow::Cow;
+
+use crate::EvalError;
+
+/// Returns the string in Unicode normalization form NFC, e.g. `e` followed by a combining acute accent becomes `é`.
+/// Strings which are already normalized, like all ASCII strings, are borrowed.
+#[cfg(feature = "normalization")]
+pub(crate) fn nfc(value: &str) -> Result<Cow<'_, str>, EvalError> {
+    use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+    Ok(match is_nfc_quick(value.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(value),
+        _ => Cow::Owned(value.nfc().collect()),
+    })
+}
+
+#[cfg(not(feature = "normalization"))]
+pub(crate) fn nfc(_value: &str) -> Result<Cow<'_, str>, EvalError> {
+    Err(EvalError::InvalidOption("Unicode normalization requires the normalization feature".to_string()))
+}
+
+#[cfg(feature = "normalization")]
+#[test]
+fn test_nfc() {
+    assert!(matches!(nfc("abc"), Ok(Cow::Borrowed("abc"))));
+    assert_eq!(nfc("e\u{301}").unwrap(), "\u{e9}");
+    assert_eq!(nfc("\u{e9}").unwrap(), "\u{e9}");
+}
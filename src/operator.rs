@@ -1,12 +1,18 @@
 
+use std::{fmt, time::Duration};
+
 use nom::{
-    branch::alt, bytes::complete::tag, combinator::{map, map_res}, IResult
+    branch::alt, bytes::complete::{tag, take_while, take_while1}, character::complete::{multispace1, one_of, satisfy}, combinator::{all_consuming, map, map_opt, map_res, not, recognize}, sequence::{delimited, preceded, terminated, tuple}, IResult
 };
 
+use crate::{context::ContextProvider, error::message, value::{identifier, is_custom_operator, Identifier, Value}, EvalError};
+
 
 
-#[derive(Debug, PartialEq, PartialOrd)]
-pub(crate) enum BinaryOperator {
+/// Operators which combine two operands.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOperator {
     Equals,
     NotEquals,
     LessThan,
@@ -15,7 +21,52 @@ pub(crate) enum BinaryOperator {
     GreaterEqual,
     And,
     Or,
+    Xor,
+    Implies,
     RegexMatch,
+    /// Matches a regex against the whole string, as if the pattern was written as `^(?:pattern)$`.
+    FullMatch,
+    /// Matches a glob pattern against the whole string, `*` matches any characters and `?` a single one.
+    Like,
+    /// Calculates the remainder of an integer division.
+    Modulo,
+    /// Adds two numbers of the same type.
+    Add,
+    /// Subtracts two numbers of the same type.
+    Subtract,
+    /// Multiplies two numbers of the same type.
+    Multiply,
+    /// Raises a number to a power, an integer to a non-negative integer and a float to a float or an integer.
+    Power,
+    /// Whether the value equals an element of the list, written as `x in (1, 2, 3)`.
+    In,
+    /// Whether the string contains the other string or the list contains the value.
+    Contains,
+    /// A comparison operator which is looked up in the context, written as `${name}`.
+    Dynamic(Identifier),
+    /// A comparison registered with FunctionRegistry::register_operator(), written as its keyword like `ip within '10.0.0.0/8'`.
+    Custom(String),
+    /// Compares the value with every element of a list and holds if all comparisons hold, written as `x > all (10, 20)`.
+    All(Box<BinaryOperator>),
+    /// Compares the value with every element of a list and holds if any comparison holds, written as `x == any (1, 2)`.
+    Any(Box<BinaryOperator>),
+}
+
+impl BinaryOperator {
+    /// Resolves a dynamic operator from the context, only comparison operators are accepted.
+    pub(crate) fn use_context(self, context: &dyn ContextProvider) -> Result<Self, EvalError> {
+        let BinaryOperator::Dynamic(ident) = self else {
+            return Ok(self);
+        };
+        let Value::StringLiteral(symbol) = ident.use_context(context)? else {
+            return Err(EvalError::TypeMismatch(message(|| format!("Operator should be a string: {}", ident))));
+        };
+        match BinaryOperator::try_from(symbol.as_str()) {
+            Ok(BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor | BinaryOperator::Implies | BinaryOperator::Modulo | BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply | BinaryOperator::Power) => Err(EvalError::InvalidOperator(message(|| format!("Operator can not compare values: {}", symbol)))),
+            Ok(op) => Ok(op),
+            Err(err) => Err(EvalError::InvalidOperator(err)),
+        }
+    }
 }
 
 
@@ -30,29 +81,112 @@ impl TryFrom<&str> for BinaryOperator {
             ">=" => Ok(BinaryOperator::GreaterEqual),
             "&&" => Ok(BinaryOperator::And),
             "||" => Ok(BinaryOperator::Or),
+            "^^" => Ok(BinaryOperator::Xor),
+            "->" => Ok(BinaryOperator::Implies),
             "=~" => Ok(BinaryOperator::RegexMatch),
+            "===~" => Ok(BinaryOperator::FullMatch),
+            "like" => Ok(BinaryOperator::Like),
+            "%" => Ok(BinaryOperator::Modulo),
+            "+" => Ok(BinaryOperator::Add),
+            "-" => Ok(BinaryOperator::Subtract),
+            "*" => Ok(BinaryOperator::Multiply),
+            "**" => Ok(BinaryOperator::Power),
+            "in" => Ok(BinaryOperator::In),
+            "contains" => Ok(BinaryOperator::Contains),
             _ => Err(format!("Unknown operator: {}", value)),
         }
     }
     type Error = String;
 }
 
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Equals => "==",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Or => "||",
+            BinaryOperator::Xor => "^^",
+            BinaryOperator::Implies => "->",
+            BinaryOperator::RegexMatch => "=~",
+            BinaryOperator::FullMatch => "===~",
+            BinaryOperator::Like => "like",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Power => "**",
+            BinaryOperator::In => "in",
+            BinaryOperator::Contains => "contains",
+            BinaryOperator::Dynamic(ident) => return write!(f, "${{{}}}", ident),
+            BinaryOperator::Custom(name) => name,
+            BinaryOperator::All(op) => return write!(f, "{} all", op),
+            BinaryOperator::Any(op) => return write!(f, "{} any", op),
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
 pub(crate) fn binary_operator_number(input: &str) -> IResult<&str, BinaryOperator> {
-    map_res(alt((tag("=="), tag("!="), tag("<"), tag(">"), tag("<="), tag(">="))), BinaryOperator::try_from)(input)
+    map_res(alt((tag("=="), tag("!="), tag("<="), tag(">="), tag("<"), tag(">"))), BinaryOperator::try_from)(input)
 }
 
+/// The operators of numbers, the regex operators and `like`.
 pub(crate) fn binary_operator_string(input: &str) -> IResult<&str, BinaryOperator> {
-    map_res(alt((tag("=="), tag("!="), tag("=~"))), BinaryOperator::try_from)(input)
+    alt((map_res(alt((tag("===~"), tag("=~"), keyword("like"))), BinaryOperator::try_from), binary_operator_number))(input)
+}
+pub(crate) fn binary_operator_dynamic(input: &str) -> IResult<&str, BinaryOperator> {
+    map_res(delimited(tag("${"), identifier, tag("}")), |value| match value {
+        Value::Identifier(ident) => Ok(BinaryOperator::Dynamic(ident)),
+        _ => Err(format!("Operator placeholder should be an identifier: {:?}", value)),
+    })(input)
+}
+/// A keyword of a custom operator which the parse options declare.
+pub(crate) fn binary_operator_custom(input: &str) -> IResult<&str, BinaryOperator> {
+    let word = recognize(tuple((satisfy(|c: char| c.is_ascii_alphabetic()), take_while(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_'))));
+    map_opt(word, |word| is_custom_operator(word).then(|| BinaryOperator::Custom(word.to_string())))(input)
+}
+/// The operators comparing two booleans, false is less than true.
+pub(crate) fn binary_operator_equality(input: &str) -> IResult<&str, BinaryOperator> {
+    map_res(alt((tag("=="), tag("!="), tag("<="), tag(">="), tag("<"), tag(">"))), BinaryOperator::try_from)(input)
+}
+/// A word like `and` which is not the start of a longer identifier like `android`.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag(word), not(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_')))
+}
+
+/// The keywords `and`, `or` and `not` are aliases of `&&`, `||` and `!`, they can't be used as identifiers.
+pub(crate) fn is_operator_keyword(word: &str) -> bool {
+    matches!(word, "and" | "or" | "not")
+}
+
+/// Whether the word is a keyword of the grammar, a custom operator with its name would take its place.
+pub(crate) fn is_builtin_keyword(word: &str) -> bool {
+    is_operator_keyword(word)
+        || matches!(word, "in" | "contains" | "like" | "all" | "any" | "between" | "is" | "within_last" | "within_next")
 }
+
 pub(crate) fn binary_and_operator(input: &str) -> IResult<&str, BinaryOperator> {
-    map(tag("&&"), |_| BinaryOperator::And)(input)
+    map(alt((tag("&&"), keyword("and"))), |_| BinaryOperator::And)(input)
 }
 pub(crate) fn binary_or_operator(input: &str) -> IResult<&str, BinaryOperator> {
-    map(tag("||"), |_| BinaryOperator::Or)(input)
+    map(alt((tag("||"), keyword("or"))), |_| BinaryOperator::Or)(input)
+}
+pub(crate) fn binary_xor_operator(input: &str) -> IResult<&str, BinaryOperator> {
+    map(tag("^^"), |_| BinaryOperator::Xor)(input)
+}
+pub(crate) fn binary_implies_operator(input: &str) -> IResult<&str, BinaryOperator> {
+    map(tag("->"), |_| BinaryOperator::Implies)(input)
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
-pub(crate) enum UnaryOperator {
+/// Operators which apply to a single operand.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
     Not
 }
 
@@ -66,11 +200,102 @@ impl TryFrom<&str> for UnaryOperator {
     type Error = String;
 }
 
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOperator::Not => write!(f, "!"),
+        }
+    }
+}
 
 pub(crate) fn unary_operator_primary(input: &str) -> IResult<&str, UnaryOperator> {
-    map_res(tag("!"), UnaryOperator::try_from)(input)
+    alt((map_res(tag("!"), UnaryOperator::try_from), map(keyword("not"), |_| UnaryOperator::Not)))(input)
+}
+
+/// Tests on the sign of a number, e.g. `delta is positive`.
+/// Zero is neither positive nor negative, only `is zero` holds for it.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Predicate {
+    Positive,
+    Negative,
+    Zero,
 }
 
+impl TryFrom<&str> for Predicate {
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "positive" => Ok(Predicate::Positive),
+            "negative" => Ok(Predicate::Negative),
+            "zero" => Ok(Predicate::Zero),
+            _ => Err(format!("Unknown predicate: {}", value)),
+        }
+    }
+    type Error = String;
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::Positive => write!(f, "is positive"),
+            Predicate::Negative => write!(f, "is negative"),
+            Predicate::Zero => write!(f, "is zero"),
+        }
+    }
+}
+
+pub(crate) fn predicate(input: &str) -> IResult<&str, Predicate> {
+    map_res(preceded(tuple((tag("is"), multispace1)), alt((tag("positive"), tag("negative"), tag("zero")))), Predicate::try_from)(input)
+}
+
+/// A window of time around the current time, written as `within_last 7d` or `within_next 12h`.
+/// Both ends of the window are inclusive.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeWindow {
+    /// From the duration before now until now.
+    Last(Duration),
+    /// From now until the duration after now.
+    Next(Duration),
+}
+
+/// The units of durations with their length in seconds, the largest first.
+const DURATION_UNITS: [(char, u64); 5] = [('w', 604_800), ('d', 86_400), ('h', 3_600), ('m', 60), ('s', 1)];
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, duration) = match self {
+            TimeWindow::Last(duration) => ("within_last", duration),
+            TimeWindow::Next(duration) => ("within_next", duration),
+        };
+        let seconds = duration.as_secs();
+        let (unit, length) = DURATION_UNITS.iter().find(|(_, length)| seconds > 0 && seconds % length == 0).unwrap_or(&('s', 1));
+        write!(f, "{} {}{}", name, seconds / length, unit)
+    }
+}
+
+/// A duration like `30s`, `15m`, `12h`, `7d` or `2w`.
+fn duration(input: &str) -> IResult<&str, Duration> {
+    map_res(tuple((take_while1(|c: char| c.is_ascii_digit()), one_of("smhdw"))), |(amount, unit): (&str, char)| {
+        let length = DURATION_UNITS.iter().find(|(name, _)| *name == unit).map_or(1, |(_, length)| *length);
+        amount.parse::<u64>().ok()
+            .and_then(|amount| amount.checked_mul(length))
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("Duration is too long: {}{}", amount, unit))
+    })(input)
+}
+
+/// Parses a whole string like `'7d'` as a duration.
+pub(crate) fn parse_duration(input: &str) -> Option<Duration> {
+    all_consuming(duration)(input).ok().map(|(_, duration)| duration)
+}
+
+pub(crate) fn time_window(input: &str) -> IResult<&str, TimeWindow> {
+    alt((
+        map(preceded(tuple((tag("within_last"), multispace1)), duration), TimeWindow::Last),
+        map(preceded(tuple((tag("within_next"), multispace1)), duration), TimeWindow::Next),
+    ))(input)
+}
 
 #[test]
 fn test_operator() {
@@ -84,7 +309,18 @@ fn test_operator() {
         (">=", BinaryOperator::GreaterEqual),
         ("&&", BinaryOperator::And),
         ("||", BinaryOperator::Or),
+        ("^^", BinaryOperator::Xor),
+        ("->", BinaryOperator::Implies),
         ("=~", BinaryOperator::RegexMatch),
+        ("===~", BinaryOperator::FullMatch),
+        ("like", BinaryOperator::Like),
+        ("%", BinaryOperator::Modulo),
+        ("+", BinaryOperator::Add),
+        ("-", BinaryOperator::Subtract),
+        ("*", BinaryOperator::Multiply),
+        ("**", BinaryOperator::Power),
+        ("in", BinaryOperator::In),
+        ("contains", BinaryOperator::Contains),
     ];
 
     for (input, expected) in tests.iter() {
@@ -92,6 +328,43 @@ fn test_operator() {
     }
     
     assert_eq!(BinaryOperator::try_from("invalid").unwrap_err(), "Unknown operator: invalid");
+
+    for (input, expected) in tests.iter() {
+        assert_eq!(expected.to_string(), *input);
+    }
+}
+
+#[test]
+fn test_predicate() {
+    assert_eq!(predicate("is positive").unwrap().1, Predicate::Positive);
+    assert_eq!(predicate("is  negative").unwrap().1, Predicate::Negative);
+    assert_eq!(predicate("is zero").unwrap().1, Predicate::Zero);
+    assert!(predicate("is odd").is_err());
+    assert!(predicate("iszero").is_err());
+    assert_eq!(Predicate::Positive.to_string(), "is positive");
+}
+
+#[test]
+fn test_operator_dynamic() {
+    assert_eq!(
+        binary_operator_dynamic("${op}"),
+        Ok(("", BinaryOperator::Dynamic(Identifier::from("op"))))
+    );
+    assert_eq!(BinaryOperator::Dynamic(Identifier::from("op")).to_string(), "${op}");
+
+    let mut context = crate::Context::new();
+    context.insert("op".to_string(), crate::ContextValue::String(">".to_string()));
+    context.insert("and".to_string(), crate::ContextValue::String("&&".to_string()));
+    context.insert("unknown".to_string(), crate::ContextValue::String("<>".to_string()));
+    assert_eq!(BinaryOperator::Dynamic(Identifier::from("op")).use_context(&context), Ok(BinaryOperator::GreaterThan));
+    assert_eq!(
+        BinaryOperator::Dynamic(Identifier::from("and")).use_context(&context),
+        Err(EvalError::InvalidOperator("Operator can not compare values: &&".to_string()))
+    );
+    assert_eq!(
+        BinaryOperator::Dynamic(Identifier::from("unknown")).use_context(&context),
+        Err(EvalError::InvalidOperator("Unknown operator: <>".to_string()))
+    );
 }
 
 #[test]
@@ -103,6 +376,20 @@ fn test_operator_primary() {
   assert_eq!(
     binary_operator_number(">"),
     Ok(("", BinaryOperator::GreaterThan))
+  );
+  assert_eq!(
+    binary_operator_number("<="),
+    Ok(("", BinaryOperator::LessEqual))
   )
 }
 
+#[test]
+fn test_time_window() {
+    assert_eq!(time_window("within_last 7d").unwrap().1, TimeWindow::Last(Duration::from_secs(7 * 86_400)));
+    assert_eq!(time_window("within_next  90m").unwrap().1, TimeWindow::Next(Duration::from_secs(5_400)));
+    assert!(time_window("within_last 7").is_err());
+    assert!(time_window("within_last 99999999999999999999s").is_err());
+    assert_eq!(TimeWindow::Last(Duration::from_secs(14 * 86_400)).to_string(), "within_last 2w");
+    assert_eq!(TimeWindow::Next(Duration::from_secs(5_400)).to_string(), "within_next 90m");
+    assert_eq!(TimeWindow::Next(Duration::from_secs(0)).to_string(), "within_next 0s");
+}
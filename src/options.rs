@@ -0,0 +1,112 @@
+use std::{fmt, sync::Arc};
+
+use regex::Regex;
+
+type Provider = Arc<dyn Fn(&str) -> Option<Regex> + Send + Sync>;
+
+/// Options which change how an expression is parsed, see CompiledExpression::compile_with_parse_options().
+/// The default options parse like compile(), only `true` and `false` are boolean keywords.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseOptions {
+    /// When set the boolean keywords and their aliases are recognized in any case, e.g. `True` or `YES`.
+    pub case_insensitive_booleans: bool,
+    /// Further pairs of keywords for true and false, e.g. `("yes", "no")`. They can't be used as identifiers.
+    pub boolean_aliases: Vec<(String, String)>,
+    /// Keywords which are parsed as custom binary operators like `ip within '10.0.0.0/8'`, see FunctionRegistry::register_operator().
+    /// Builtin keywords like `and` or `in` and boolean keywords are rejected with EvalError::InvalidOption when compiling.
+    pub custom_operators: Vec<String>,
+}
+
+impl ParseOptions {
+    /// Recognizes `true`/`false`, `yes`/`no` and `on`/`off` in any case, e.g. for rules from configuration files.
+    pub fn lenient() -> Self {
+        ParseOptions {
+            case_insensitive_booleans: true,
+            boolean_aliases: vec![("yes".to_string(), "no".to_string()), ("on".to_string(), "off".to_string())],
+            ..Default::default()
+        }
+    }
+}
+
+/// Options which change how an expression is evaluated.
+/// The default options give the same results as evaluate().
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvalOptions {
+    /// Locale tag (e.g. "en" or "ar") used to order strings with the unicode collation algorithm.
+    /// Requires the `collation` feature. When not set strings are ordered by their bytes.
+    pub collation: Option<String>,
+    /// When set `==` and `!=` compare values of different types instead of failing,
+    /// e.g. `x == 5` is false and `x != 5` is true if x is a string. Integers and floats are different types.
+    pub loose_equality: bool,
+    /// When set panics of registered functions are caught with `catch_unwind` and returned as
+    /// EvalError::FunctionPanic. This does not work if the binary is built with `panic = "abort"`,
+    /// and state the function shares with the host may be left inconsistent by the panic.
+    pub catch_panics: bool,
+    /// The current time in seconds since the unix epoch used by `within_last` and `within_next`.
+    /// When not set the system clock is used.
+    pub now: Option<i64>,
+    /// When set a string is parsed as a number if it is compared with an integer or a float, e.g. for contexts
+    /// read from environment variables `port > 8080` is true if port is '8081'. A string which is not a number
+    /// of the other type fails with EvalError::TypeMismatch, even with loose_equality.
+    pub coerce_strings: bool,
+    /// The maximum size in bytes of a compiled regex, see regex::RegexBuilder::size_limit().
+    /// Larger patterns fail with EvalError::InvalidRegex, use it to bound the memory of patterns from untrusted input.
+    /// The regexes precompiled by CompiledExpression are only used if CompilerContext::with_regex_size_limit() compiled them
+    /// with at most this limit, otherwise the patterns are compiled during evaluation.
+    pub regex_size_limit: Option<usize>,
+    /// When set floats which differ by at most the tolerance are equal, e.g. `0.1 + 0.2 == 0.3` is true with `1e-9`.
+    /// The ordering operators are consistent with it, `<` is false for equal floats. When not set floats compare exactly.
+    /// A negative or NaN tolerance fails with EvalError::InvalidOption.
+    pub float_tolerance: Option<f64>,
+    /// Regexes compiled by the caller, e.g. once for the patterns shared by all expressions of a rule set.
+    /// It is consulted before a pattern is compiled and before the regex size limit is applied.
+    pub regex_provider: Option<RegexProvider>,
+    /// When set strings are normalized to Unicode NFC before they are compared or matched, e.g. `é` and `e` followed by
+    /// a combining accent are equal. Requires the `normalization` feature. When not set strings are compared by their bytes.
+    pub normalize_strings: bool,
+}
+
+/// Supplies compiled regexes for the patterns of `=~`, `===~` and `like`, see EvalOptions::regex_provider.
+/// The provider is asked for the pattern as it is compiled: `===~` anchors it as `^(?:pattern)$`
+/// and `like` translates the glob to a regex. A pattern it returns None for is compiled as usual.
+/// Clones of a Regex share its compiled program, so a provider which compiles the patterns of a rule set once,
+/// e.g. into a map, shares them between all evaluations without compiling them again.
+/// Clones share the provider, two options are only equal if they share the same one.
+#[derive(Clone)]
+pub struct RegexProvider(Provider);
+
+impl RegexProvider {
+    /// # Examples
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use logical_expr::{evaluate_with_options, Context, ContextValue, EvalOptions, RegexProvider};
+    /// use regex::Regex;
+    ///
+    /// // compiled once for the whole rule set
+    /// let regexes: HashMap<String, Regex> = ["^[0-9]+$", "^[a-z]+$"].into_iter().map(|pattern| (pattern.to_string(), Regex::new(pattern).unwrap())).collect();
+    /// let provider = RegexProvider::new(move |pattern| regexes.get(pattern).cloned());
+    /// let options = EvalOptions { regex_provider: Some(provider), ..Default::default() };
+    /// let mut context = Context::new();
+    /// context.insert("zip".to_string(), ContextValue::String("12345".to_string()));
+    /// assert_eq!(evaluate_with_options("zip =~ '^[0-9]+$'", &context, &options), Ok(true));
+    /// ```
+    pub fn new(provider: impl Fn(&str) -> Option<Regex> + Send + Sync + 'static) -> Self {
+        RegexProvider(Arc::new(provider))
+    }
+
+    pub(crate) fn get(&self, pattern: &str) -> Option<Regex> {
+        (self.0)(pattern)
+    }
+}
+
+impl fmt::Debug for RegexProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RegexProvider")
+    }
+}
+
+impl PartialEq for RegexProvider {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
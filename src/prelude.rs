@@ -0,0 +1,4 @@
+pub use crate::{
+    evaluate, evaluate_with_options, parse, BinaryOperator, BooleanExpression, CompiledExpression, Context, ContextProvider, ContextValue, EvalError,
+    EvalOptions, FunctionRegistry, ParseOptions, Identifier, NonBooleanExpression, Predicate, TimeWindow, UnaryOperator, Value, ValueType,
+};
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{datetime::parse_datetime, BinaryOperator, BooleanExpression, EvalError, NonBooleanExpression, Value};
+
+/// The type of a context value, used to declare the types of the identifiers for CompiledExpression::compile_typed().
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValueType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    List,
+    DateTime,
+    /// An exact decimal number, requires the `decimal` feature.
+    #[cfg(feature = "decimal")]
+    Decimal,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::String => write!(f, "string"),
+            ValueType::Integer => write!(f, "integer"),
+            ValueType::Float => write!(f, "float"),
+            ValueType::Boolean => write!(f, "boolean"),
+            ValueType::List => write!(f, "list"),
+            ValueType::DateTime => write!(f, "datetime"),
+            #[cfg(feature = "decimal")]
+            ValueType::Decimal => write!(f, "decimal"),
+        }
+    }
+}
+
+/// Checks that every identifier is declared in the schema and that every operator is applied to types it supports.
+/// Values whose type is only known at runtime, like list elements and function results, are not checked.
+pub(crate) fn check_types(expression: &BooleanExpression, schema: &HashMap<String, ValueType>) -> Result<(), EvalError> {
+    match expression {
+        BooleanExpression::Boolean(_) => Ok(()),
+        BooleanExpression::Identifier(ident) => match lookup(ident.name(), schema)? {
+            ValueType::Boolean => Ok(()),
+            value_type => Err(EvalError::TypeMismatch(format!("Identifier {} should be a boolean, but is a {}", ident, value_type))),
+        },
+        BooleanExpression::NonBooleanExpression(nbe) => check_comparison(nbe, schema),
+        BooleanExpression::Predicate(value, _) => check_numbers(&[value], schema),
+        BooleanExpression::Between(value, lower, upper) => check_numbers(&[value, lower, upper], schema),
+        BooleanExpression::Within(value, _) => match value_type(value, schema)? {
+            Some(ValueType::Integer | ValueType::DateTime) | None => Ok(()),
+            Some(value_type) => Err(EvalError::TypeMismatch(format!("Timestamp should be a datetime or an integer, but {} is a {}", value, value_type))),
+        },
+        BooleanExpression::Binary(lhs, _, rhs) => {
+            check_types(lhs, schema)?;
+            check_types(rhs, schema)
+        }
+        BooleanExpression::Chain(_, operands) => operands.iter().try_for_each(|operand| check_types(operand, schema)),
+        BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => check_types(value, schema),
+    }
+}
+
+/// Converts the literals of comparisons to the declared type of the other side, strings compared to a datetime
+/// are parsed as timestamps and integers compared to a decimal become decimals. Evaluation then compares both
+/// sides directly instead of converting the literal every time, invalid timestamps are rejected with EvalError::TypeMismatch.
+/// Expects an expression which check_types() accepted.
+pub(crate) fn resolve_literals(expression: BooleanExpression, schema: &HashMap<String, ValueType>) -> Result<BooleanExpression, EvalError> {
+    Ok(match expression {
+        BooleanExpression::NonBooleanExpression(NonBooleanExpression(lhs, op, rhs)) if is_comparison(&op) => {
+            let (lhs_type, rhs_type) = (value_type(&lhs, schema)?, value_type(&rhs, schema)?);
+            BooleanExpression::NonBooleanExpression(NonBooleanExpression(resolve_literal(lhs, rhs_type)?, op, resolve_literal(rhs, lhs_type)?))
+        }
+        BooleanExpression::Binary(lhs, op, rhs) =>
+            BooleanExpression::Binary(Box::new(resolve_literals(*lhs, schema)?), op, Box::new(resolve_literals(*rhs, schema)?)),
+        BooleanExpression::Chain(op, operands) =>
+            BooleanExpression::Chain(op, operands.into_iter().map(|operand| resolve_literals(operand, schema)).collect::<Result<_, _>>()?),
+        BooleanExpression::Unary(op, value) => BooleanExpression::Unary(op, Box::new(resolve_literals(*value, schema)?)),
+        BooleanExpression::Group(value) => BooleanExpression::Group(Box::new(resolve_literals(*value, schema)?)),
+        expression => expression,
+    })
+}
+
+fn is_comparison(op: &BinaryOperator) -> bool {
+    matches!(op, BinaryOperator::Equals | BinaryOperator::NotEquals | BinaryOperator::LessThan | BinaryOperator::GreaterThan
+        | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual)
+}
+
+fn resolve_literal(value: Value, other: Option<ValueType>) -> Result<Value, EvalError> {
+    match (value, other) {
+        (Value::StringLiteral(s), Some(ValueType::DateTime)) => parse_datetime(&s).map(Value::DateTime)
+            .ok_or_else(|| EvalError::TypeMismatch(format!("Value should be an RFC 3339 timestamp like '2024-01-01T00:00:00Z': '{}'", s))),
+        #[cfg(feature = "decimal")]
+        (Value::IntegerLiteral(i), Some(ValueType::Decimal)) => Ok(Value::DecimalLiteral(i.into())),
+        (value, _) => Ok(value),
+    }
+}
+
+/// Checks the expression like check_types() but collects the errors of all conditions instead of stopping at the first.
+/// Every undeclared identifier of a condition is reported, the types of a condition are only checked if all its identifiers are declared.
+pub(crate) fn validate(expression: &BooleanExpression, schema: &HashMap<String, ValueType>, errors: &mut Vec<EvalError>) {
+    match expression {
+        BooleanExpression::Binary(lhs, _, rhs) => {
+            validate(lhs, schema, errors);
+            validate(rhs, schema, errors);
+        }
+        BooleanExpression::Chain(_, operands) => {
+            for operand in operands {
+                validate(operand, schema, errors);
+            }
+        }
+        BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => validate(value, schema, errors),
+        condition => {
+            let unknown: Vec<EvalError> = condition.identifiers().into_iter().filter_map(|ident| lookup(ident.name(), schema).err()).collect();
+            let found = if unknown.is_empty() { check_types(condition, schema).err().into_iter().collect() } else { unknown };
+            for error in found {
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+    }
+}
+
+fn check_comparison(nbe: &NonBooleanExpression, schema: &HashMap<String, ValueType>) -> Result<(), EvalError> {
+    if let Some(comparisons) = nbe.quantified_comparisons() {
+        return comparisons.iter().try_for_each(|comparison| check_comparison(comparison, schema));
+    }
+    let NonBooleanExpression(lhs, op, rhs) = nbe;
+    if let BinaryOperator::Dynamic(ident) = op {
+        if lookup(ident.name(), schema)? != ValueType::String {
+            return Err(EvalError::TypeMismatch(format!("Operator {} should be a string", op)));
+        }
+    }
+    let (lhs_type, rhs_type) = (value_type(lhs, schema)?, value_type(rhs, schema)?);
+    match (lhs_type, op, rhs_type) {
+        // the registered operator checks the types of its operands
+        (_, BinaryOperator::Custom(_), _) => return Ok(()),
+        (_, BinaryOperator::In, Some(ValueType::List) | None) | (Some(ValueType::List) | None, BinaryOperator::Contains, _) => return Ok(()),
+        (Some(ValueType::String), BinaryOperator::Contains, Some(ValueType::String) | None) => return Ok(()),
+        (_, BinaryOperator::In, Some(value_type)) => return Err(EvalError::TypeMismatch(format!("Operator in requires a list, but {} is a {}", rhs, value_type))),
+        (Some(value_type), BinaryOperator::Contains, _) =>
+            return Err(EvalError::TypeMismatch(format!("Operator contains requires a string or a list, but {} is a {}", lhs, value_type))),
+        _ => {}
+    }
+    let (Some(lhs_type), Some(rhs_type)) = (lhs_type, rhs_type) else {
+        return Ok(());
+    };
+    let supported = match op {
+        BinaryOperator::Equals | BinaryOperator::NotEquals => true,
+        BinaryOperator::Dynamic(_) => lhs_type != ValueType::List,
+        BinaryOperator::LessThan | BinaryOperator::GreaterThan | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual =>
+            lhs_type != ValueType::List,
+        BinaryOperator::RegexMatch | BinaryOperator::FullMatch | BinaryOperator::Like => lhs_type == ValueType::String,
+        _ => false,
+    };
+    if !supported {
+        return Err(EvalError::InvalidOperator(format!("Operator {} is not supported for {}: {}", op, lhs_type, nbe)));
+    }
+    if !compatible(lhs_type, rhs_type) {
+        return Err(EvalError::TypeMismatch(format!("Can not compare {} with {}: {}", lhs_type, rhs_type, nbe)));
+    }
+    Ok(())
+}
+
+fn check_numbers(values: &[&Value], schema: &HashMap<String, ValueType>) -> Result<(), EvalError> {
+    for value in values {
+        if let Some(value_type @ (ValueType::String | ValueType::Boolean | ValueType::List | ValueType::DateTime)) = value_type(value, schema)? {
+            return Err(EvalError::TypeMismatch(format!("Value should be a number, but {} is a {}", value, value_type)));
+        }
+    }
+    Ok(())
+}
+
+/// Records the types of the identifiers which the usage in the expression determines, see BooleanExpression::infer_types().
+pub(crate) fn infer_types(expression: &BooleanExpression, types: &mut HashMap<String, ValueType>) -> Result<(), EvalError> {
+    match expression {
+        BooleanExpression::Boolean(_) | BooleanExpression::Predicate(..) | BooleanExpression::Within(..) => Ok(()),
+        BooleanExpression::Identifier(ident) => record(ident.name(), ValueType::Boolean, types),
+        BooleanExpression::NonBooleanExpression(NonBooleanExpression(lhs, op, rhs)) => match op {
+            BinaryOperator::RegexMatch | BinaryOperator::FullMatch | BinaryOperator::Like => {
+                infer_value(lhs, Some(ValueType::String), types)?;
+                infer_value(rhs, Some(ValueType::String), types)
+            }
+            BinaryOperator::In => {
+                let item_type = match rhs {
+                    Value::List(items) => common_type(items),
+                    _ => None,
+                };
+                infer_value(lhs, item_type, types)?;
+                infer_value(rhs, Some(ValueType::List), types)
+            }
+            // the left side is a string or a list
+            BinaryOperator::Contains => infer_value(rhs, None, types),
+            BinaryOperator::All(op) | BinaryOperator::Any(op) => {
+                let item_type = match (op.as_ref(), rhs) {
+                    (BinaryOperator::RegexMatch | BinaryOperator::FullMatch | BinaryOperator::Like, _) => Some(ValueType::String),
+                    (_, Value::List(items)) => common_type(items),
+                    _ => None,
+                };
+                infer_value(lhs, item_type, types)
+            }
+            // the registered operator determines the types of its operands
+            BinaryOperator::Custom(_) => {
+                infer_value(lhs, None, types)?;
+                infer_value(rhs, None, types)
+            }
+            _ => {
+                if let BinaryOperator::Dynamic(ident) = op {
+                    record(ident.name(), ValueType::String, types)?;
+                }
+                if let (Value::List(lhs), Value::List(rhs)) = (lhs, rhs) {
+                    for (lhs, rhs) in lhs.iter().zip(rhs) {
+                        infer_value(lhs, static_type(rhs), types)?;
+                        infer_value(rhs, static_type(lhs), types)?;
+                    }
+                    return Ok(());
+                }
+                infer_value(lhs, static_type(rhs), types)?;
+                infer_value(rhs, static_type(lhs), types)
+            }
+        },
+        BooleanExpression::Between(value, lower, upper) => {
+            let number_type = static_type(value).or(static_type(lower)).or(static_type(upper));
+            for value in [value, lower, upper] {
+                infer_value(value, number_type, types)?;
+            }
+            Ok(())
+        }
+        BooleanExpression::Binary(lhs, _, rhs) => {
+            infer_types(lhs, types)?;
+            infer_types(rhs, types)
+        }
+        BooleanExpression::Chain(_, operands) => operands.iter().try_for_each(|operand| infer_types(operand, types)),
+        BooleanExpression::Unary(_, value) | BooleanExpression::Group(value) => infer_types(value, types),
+    }
+}
+
+/// Records the expected type for the identifiers of the value.
+fn infer_value(value: &Value, expected: Option<ValueType>, types: &mut HashMap<String, ValueType>) -> Result<(), EvalError> {
+    match value {
+        Value::Identifier(ident) => match expected {
+            Some(value_type) => record(ident.name(), value_type, types),
+            None => Ok(()),
+        },
+        Value::Index(ident, _) => record(ident.name(), ValueType::List, types),
+        Value::Arithmetic(lhs, _, rhs) => {
+            // only numbers propagate, datetimes are calculated with duration strings
+            let number_type = static_type(value).or(expected).filter(|value_type| is_number(*value_type));
+            infer_value(lhs, number_type, types)?;
+            infer_value(rhs, number_type, types)
+        }
+        Value::Fallback(lhs, rhs) => {
+            infer_value(lhs, expected, types)?;
+            infer_value(rhs, expected, types)
+        }
+        Value::List(items) => {
+            for item in items {
+                infer_value(item, None, types)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The type of a value made of literals, an arithmetic value has the type of its literal operands.
+fn static_type(value: &Value) -> Option<ValueType> {
+    match value {
+        Value::Identifier(_) | Value::Index(..) | Value::Call(..) | Value::Fallback(..) => None,
+        Value::Arithmetic(lhs, _, rhs) => static_type(lhs).or(static_type(rhs)).filter(|value_type| is_number(*value_type)),
+        value => value_type(value, &HashMap::new()).ok().flatten(),
+    }
+}
+
+/// The type of all literal elements if they have the same one.
+fn common_type(items: &[Value]) -> Option<ValueType> {
+    let mut item_types = items.iter().map(static_type);
+    let first = item_types.next()??;
+    item_types.all(|item_type| item_type == Some(first)).then_some(first)
+}
+
+fn is_number(value_type: ValueType) -> bool {
+    match value_type {
+        ValueType::Integer | ValueType::Float => true,
+        #[cfg(feature = "decimal")]
+        ValueType::Decimal => true,
+        _ => false,
+    }
+}
+
+/// Records the type of the identifier, integers and decimals are compatible and the identifier becomes a decimal.
+fn record(name: &str, value_type: ValueType, types: &mut HashMap<String, ValueType>) -> Result<(), EvalError> {
+    match types.get(name) {
+        None => {
+            types.insert(name.to_string(), value_type);
+        }
+        Some(existing) if *existing == value_type => {}
+        #[cfg(feature = "decimal")]
+        Some(ValueType::Integer) if value_type == ValueType::Decimal => {
+            types.insert(name.to_string(), value_type);
+        }
+        #[cfg(feature = "decimal")]
+        Some(ValueType::Decimal) if value_type == ValueType::Integer => {}
+        Some(existing) => return Err(EvalError::TypeMismatch(format!("Identifier {} is used as {} and as {}", name, existing, value_type))),
+    }
+    Ok(())
+}
+
+/// The type of the value or None if it is only known at runtime.
+fn value_type(value: &Value, schema: &HashMap<String, ValueType>) -> Result<Option<ValueType>, EvalError> {
+    Ok(match value {
+        Value::Identifier(ident) => Some(lookup(ident.name(), schema)?),
+        Value::Index(ident, _) => match lookup(ident.name(), schema)? {
+            ValueType::List => None,
+            value_type => return Err(EvalError::TypeMismatch(format!("Identifier {} should be a list, but is a {}", ident, value_type))),
+        },
+        Value::Arithmetic(lhs, op, rhs) => match (value_type(lhs, schema)?, value_type(rhs, schema)?) {
+            (Some(ValueType::Integer), Some(ValueType::Integer)) => Some(ValueType::Integer),
+            (Some(ValueType::Float), Some(ValueType::Float)) if *op != BinaryOperator::Modulo => Some(ValueType::Float),
+            (Some(ValueType::Float), Some(ValueType::Integer)) if *op == BinaryOperator::Power => Some(ValueType::Float),
+            (Some(ValueType::DateTime), Some(ValueType::DateTime)) if *op == BinaryOperator::Subtract => Some(ValueType::Integer),
+            (Some(ValueType::DateTime), Some(ValueType::String)) if matches!(op, BinaryOperator::Add | BinaryOperator::Subtract) => Some(ValueType::DateTime),
+            (Some(ValueType::String), Some(ValueType::DateTime)) if *op == BinaryOperator::Add => Some(ValueType::DateTime),
+            #[cfg(feature = "decimal")]
+            (Some(ValueType::Decimal | ValueType::Integer), Some(ValueType::Decimal | ValueType::Integer)) if !matches!(op, BinaryOperator::Modulo | BinaryOperator::Power) =>
+                Some(ValueType::Decimal),
+            (Some(lhs_type), Some(rhs_type)) =>
+                return Err(EvalError::TypeMismatch(format!("Operator {} is not supported for {} and {}: {}", op, lhs_type, rhs_type, value))),
+            _ => None,
+        },
+        Value::Fallback(lhs, rhs) => match (value_type(lhs, schema)?, value_type(rhs, schema)?) {
+            (Some(lhs_type), Some(rhs_type)) if lhs_type == rhs_type => Some(lhs_type),
+            _ => None,
+        },
+        Value::Call(_, args) => {
+            for arg in args {
+                value_type(arg, schema)?;
+            }
+            None
+        }
+        value => value.value_type(),
+    })
+}
+
+/// Whether both types can be compared, integers are converted to decimals and strings to datetimes.
+fn compatible(lhs: ValueType, rhs: ValueType) -> bool {
+    match (lhs, rhs) {
+        (ValueType::DateTime, ValueType::String) | (ValueType::String, ValueType::DateTime) => true,
+        #[cfg(feature = "decimal")]
+        (ValueType::Decimal, ValueType::Integer) | (ValueType::Integer, ValueType::Decimal) => true,
+        (lhs, rhs) => lhs == rhs,
+    }
+}
+
+fn lookup(name: &str, schema: &HashMap<String, ValueType>) -> Result<ValueType, EvalError> {
+    schema.get(name).copied().ok_or_else(|| EvalError::UnknownIdentifier(format!("Identifier not found in schema: {}", name)))
+}
+
+#[test]
+fn test_infer_types() {
+    let infer = |expr: &str| crate::parse(expr).unwrap().infer_types();
+    let types: HashMap<String, ValueType> = [
+        ("age", ValueType::Integer),
+        ("name", ValueType::String),
+    ].into_iter().map(|(name, value_type)| (name.to_string(), value_type)).collect();
+    assert_eq!(infer("age > 18 && name =~ 'a'"), Ok(types));
+    let types = infer("active && score + 1.5 > limit && level in (1, 2) && tags[0] == 'x' && (x, y) == ('a', 2.0) && a < b").unwrap();
+    assert_eq!(types.get("active"), Some(&ValueType::Boolean));
+    assert_eq!(types.get("score"), Some(&ValueType::Float));
+    assert_eq!(types.get("level"), Some(&ValueType::Integer));
+    assert_eq!(types.get("tags"), Some(&ValueType::List));
+    assert_eq!(types.get("x"), Some(&ValueType::String));
+    assert_eq!(types.get("y"), Some(&ValueType::Float));
+    assert_eq!(types.get("a"), None);
+    assert_eq!(types.get("limit"), Some(&ValueType::Float));
+    assert_eq!(
+        infer("age > 18 && age =~ '1'"),
+        Err(EvalError::TypeMismatch("Identifier age is used as integer and as string".to_string()))
+    );
+}
+
+#[test]
+fn test_check_types() {
+    let schema: HashMap<String, ValueType> = [
+        ("name", ValueType::String),
+        ("age", ValueType::Integer),
+        ("active", ValueType::Boolean),
+        ("tags", ValueType::List),
+    ].into_iter().map(|(name, value_type)| (name.to_string(), value_type)).collect();
+    let check = |expr: &str| check_types(&crate::parse(expr).unwrap(), &schema);
+    assert_eq!(check("name =~ '^a' && age + 1 > 18 && active && tags[0] == 'x'"), Ok(()));
+    assert_eq!(check("age between 18 and 65 && age % 2 is zero"), Ok(()));
+    assert!(matches!(check("name < 5"), Err(EvalError::TypeMismatch(_))));
+    assert!(matches!(check("tags < tags"), Err(EvalError::InvalidOperator(_))));
+    assert!(matches!(check("age =~ '1'"), Err(EvalError::InvalidOperator(_))));
+    assert!(matches!(check("name"), Err(EvalError::TypeMismatch(_))));
+    assert!(matches!(check("name + 1 > 2"), Err(EvalError::TypeMismatch(_))));
+    assert!(matches!(check("unknown == 1"), Err(EvalError::UnknownIdentifier(_))));
+    assert_eq!(check("age > all (1, age) && name =~ any ('a', 'b')"), Ok(()));
+    assert!(matches!(check("name > any (1, 2)"), Err(EvalError::TypeMismatch(_))));
+}
@@ -1,23 +1,427 @@
-use nom::{branch::alt, bytes::complete::{tag, take_while1}, character::complete::char, combinator::{map, map_res}, sequence::{delimited, tuple}, IResult};
+use std::{borrow::Cow, cell::{Cell, RefCell}, cmp::Ordering, fmt, hash::{Hash, Hasher}, ops::Range};
 
-use crate::ContextValue;
+use nom::{branch::alt, bytes::complete::{tag, take_while, take_while1}, character::complete::{char, i64, multispace0, one_of, satisfy}, combinator::{all_consuming, map, map_opt, map_res, not, opt, recognize}, multi::{many0, separated_list0}, sequence::{delimited, preceded, terminated, tuple}, IResult};
 
+use crate::{context::ContextProvider, datetime::{format_datetime, parse_datetime}, error::message, expression::check_value_depth, operator::{is_builtin_keyword, is_operator_keyword, parse_duration, BinaryOperator}, ContextValue, EvalEnv, EvalError, EvalOptions, ParseOptions, ValueType};
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
-pub(crate) enum Value{
+
+/// An operand of a comparison, either a literal or an identifier resolved from the context.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value{
   Identifier(Identifier),
   StringLiteral(String),
   IntegerLiteral(i64),
   FloatLiteral(f64),
   Boolean(bool),
+  /// An element of a list in the context, written as `name[index]`.
+  /// Negative indices count from the end of the list, `name[-1]` is the last element.
+  Index(Identifier, i64),
+  /// A list from the context or a list literal like `(1, 2, 3)` after `in`.
+  List(Vec<Value>),
+  /// A calculation which is resolved to a number before the comparison, e.g. `index % 5`.
+  Arithmetic(Box<Value>, BinaryOperator, Box<Value>),
+  /// A call of a builtin function, e.g. `sha256(payload)`.
+  Call(Identifier, Vec<Value>),
+  /// The left value if it can be resolved from the context, otherwise the right one, written as `nickname ?: username`
+  /// or `nickname ?? username`. A context has no null values, a missing key takes the place of null.
+  Fallback(Box<Value>, Box<Value>),
+  /// A point in time from the context as seconds since the unix epoch, string literals compared to it are parsed as RFC 3339 timestamps.
+  DateTime(i64),
+  /// An exact decimal number written as `dec'1.10'`, requires the `decimal` feature.
+  #[cfg(feature = "decimal")]
+  DecimalLiteral(rust_decimal::Decimal),
+}
+/// The bits floats are compared and hashed by, `-0.0` has the bits of `0.0` and all NaNs have the same bits.
+fn canonical_bits(f: f64) -> u64 {
+    if f.is_nan() {
+        f64::NAN.to_bits()
+    } else if f == 0.0 {
+        0.0f64.to_bits()
+    } else {
+        f.to_bits()
+    }
+}
+
+/// Values are compared by their structure, floats by their canonical bits so that NaN is equal to itself
+/// and the comparison is an equivalence which agrees with Hash.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Identifier(lhs), Value::Identifier(rhs)) => lhs == rhs,
+            (Value::StringLiteral(lhs), Value::StringLiteral(rhs)) => lhs == rhs,
+            (Value::IntegerLiteral(lhs), Value::IntegerLiteral(rhs)) | (Value::DateTime(lhs), Value::DateTime(rhs)) => lhs == rhs,
+            (Value::FloatLiteral(lhs), Value::FloatLiteral(rhs)) => canonical_bits(*lhs) == canonical_bits(*rhs),
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => lhs == rhs,
+            (Value::Index(lhs, lhs_index), Value::Index(rhs, rhs_index)) => lhs == rhs && lhs_index == rhs_index,
+            (Value::List(lhs), Value::List(rhs)) => lhs == rhs,
+            (Value::Arithmetic(lhs, lhs_op, lhs_rhs), Value::Arithmetic(rhs, rhs_op, rhs_rhs)) => lhs == rhs && lhs_op == rhs_op && lhs_rhs == rhs_rhs,
+            (Value::Call(lhs, lhs_args), Value::Call(rhs, rhs_args)) => lhs == rhs && lhs_args == rhs_args,
+            (Value::Fallback(lhs, lhs_fallback), Value::Fallback(rhs, rhs_fallback)) => lhs == rhs && lhs_fallback == rhs_fallback,
+            #[cfg(feature = "decimal")]
+            (Value::DecimalLiteral(lhs), Value::DecimalLiteral(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+/// Orders values of the same variant by their content and different variants in the order of their declaration.
+/// All NaNs are ordered equal to each other like they are compared, but unordered to other floats.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Identifier(lhs), Value::Identifier(rhs)) => lhs.partial_cmp(rhs),
+            (Value::StringLiteral(lhs), Value::StringLiteral(rhs)) => lhs.partial_cmp(rhs),
+            (Value::IntegerLiteral(lhs), Value::IntegerLiteral(rhs)) | (Value::DateTime(lhs), Value::DateTime(rhs)) => lhs.partial_cmp(rhs),
+            (Value::FloatLiteral(lhs), Value::FloatLiteral(rhs)) if lhs.is_nan() && rhs.is_nan() => Some(Ordering::Equal),
+            (Value::FloatLiteral(lhs), Value::FloatLiteral(rhs)) => lhs.partial_cmp(rhs),
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => lhs.partial_cmp(rhs),
+            (Value::Index(lhs, lhs_index), Value::Index(rhs, rhs_index)) => (lhs, lhs_index).partial_cmp(&(rhs, rhs_index)),
+            (Value::List(lhs), Value::List(rhs)) => lhs.partial_cmp(rhs),
+            (Value::Arithmetic(lhs, lhs_op, lhs_rhs), Value::Arithmetic(rhs, rhs_op, rhs_rhs)) => (lhs, lhs_op, lhs_rhs).partial_cmp(&(rhs, rhs_op, rhs_rhs)),
+            (Value::Call(lhs, lhs_args), Value::Call(rhs, rhs_args)) => (lhs, lhs_args).partial_cmp(&(rhs, rhs_args)),
+            (Value::Fallback(lhs, lhs_fallback), Value::Fallback(rhs, rhs_fallback)) => (lhs, lhs_fallback).partial_cmp(&(rhs, rhs_fallback)),
+            #[cfg(feature = "decimal")]
+            (Value::DecimalLiteral(lhs), Value::DecimalLiteral(rhs)) => lhs.partial_cmp(rhs),
+            (lhs, rhs) => lhs.variant_index().partial_cmp(&rhs.variant_index()),
+        }
+    }
 }
+
+/// Floats are hashed by their canonical bits like they are compared, so equal values have equal hashes.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Identifier(ident) => ident.hash(state),
+            Value::StringLiteral(s) => s.hash(state),
+            Value::IntegerLiteral(i) | Value::DateTime(i) => i.hash(state),
+            Value::FloatLiteral(f) => canonical_bits(*f).hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Index(ident, index) => (ident, index).hash(state),
+            Value::List(items) => items.hash(state),
+            Value::Arithmetic(lhs, op, rhs) => (lhs, op, rhs).hash(state),
+            Value::Call(name, args) => (name, args).hash(state),
+            Value::Fallback(lhs, rhs) => (lhs, rhs).hash(state),
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(d) => d.hash(state),
+        }
+    }
+}
+
 impl Value {
-    pub(crate) fn use_context(self, context: &std::collections::HashMap<String, ContextValue>) -> Result<Value, String> {
+    pub(crate) fn use_context(self, context: &dyn ContextProvider) -> Result<Value, EvalError> {
+        self.resolve(&EvalEnv::new(context, &EvalOptions::default()))
+    }
+    /// Resolves the value like use_context() and calls functions with the functions of the environment.
+    pub(crate) fn resolve(self, env: &EvalEnv) -> Result<Value, EvalError> {
+        let context = env.context;
         match self {
             Value::Identifier(identifier) => identifier.use_context(context),
+            Value::Index(identifier, index) => match identifier.use_context(context)? {
+                Value::List(mut items) => {
+                    let position = if index < 0 { items.len() as i64 + index } else { index };
+                    if position < 0 || position >= items.len() as i64 {
+                        return Err(EvalError::IndexOutOfBounds(message(|| format!("Index {} is out of bounds for {} with length {}", index, identifier, items.len()))));
+                    }
+                    Ok(items.swap_remove(position as usize))
+                }
+                _ => Err(EvalError::TypeMismatch(message(|| format!("Value should be a list: {}", identifier)))),
+            },
+            Value::Arithmetic(lhs, op, rhs) => {
+                // a divisor from the context is kept to name it in the error if it is zero
+                let divisor = (op == BinaryOperator::Modulo && !rhs.is_constant()).then(|| rhs.clone());
+                calculate(lhs.resolve(env)?, &op, rhs.resolve(env)?).map_err(|err| match (err, divisor) {
+                    (EvalError::DivisionByZero(msg), Some(divisor)) => EvalError::DivisionByZero(message(|| format!("{}, {} is 0", msg, divisor))),
+                    (err, _) => err,
+                })
+            }
+            Value::Call(name, args) => {
+                let args = args.into_iter().map(|arg| arg.resolve(env)).collect::<Result<Vec<_>, _>>()?;
+                env.call(&name, args)
+            }
+            Value::List(items) => Ok(Value::List(items.into_iter().map(|item| item.resolve(env)).collect::<Result<_, _>>()?)),
+            Value::Fallback(lhs, rhs) => match lhs.resolve(env) {
+                Err(EvalError::UnknownIdentifier(_) | EvalError::IndexOutOfBounds(_)) => rhs.resolve(env),
+                result => result,
+            },
+            _ => Ok(self),
+        }
+    }
+    /// Replaces the identifiers for which known() returns true with their value from the context.
+    pub(crate) fn substitute(self, context: &crate::Context, known: &dyn Fn(&Identifier) -> bool) -> Result<Value, EvalError> {
+        match self {
+            Value::Identifier(ref ident) | Value::Index(ref ident, _) if known(ident) => self.use_context(context),
+            Value::Arithmetic(lhs, op, rhs) =>
+                Ok(Value::Arithmetic(Box::new(lhs.substitute(context, known)?), op, Box::new(rhs.substitute(context, known)?))),
+            Value::Call(name, args) =>
+                Ok(Value::Call(name, args.into_iter().map(|arg| arg.substitute(context, known)).collect::<Result<_, _>>()?)),
+            Value::Fallback(lhs, rhs) =>
+                Ok(Value::Fallback(Box::new(lhs.substitute(context, known)?), Box::new(rhs.substitute(context, known)?))),
+            Value::List(items) => Ok(Value::List(items.into_iter().map(|item| item.substitute(context, known)).collect::<Result<_, _>>()?)),
             _ => Ok(self),
         }
     }
+    pub(crate) fn rename_identifiers(self, f: &dyn Fn(&str) -> String) -> Value {
+        match self {
+            Value::Identifier(ident) => Value::Identifier(ident.rename(f)),
+            Value::Index(ident, index) => Value::Index(ident.rename(f), index),
+            Value::Arithmetic(lhs, op, rhs) => Value::Arithmetic(Box::new(lhs.rename_identifiers(f)), op, Box::new(rhs.rename_identifiers(f))),
+            Value::Call(name, args) => Value::Call(name, args.into_iter().map(|arg| arg.rename_identifiers(f)).collect()),
+            Value::Fallback(lhs, rhs) => Value::Fallback(Box::new(lhs.rename_identifiers(f)), Box::new(rhs.rename_identifiers(f))),
+            Value::List(items) => Value::List(items.into_iter().map(|item| item.rename_identifiers(f)).collect()),
+            value => value,
+        }
+    }
+    /// The position of the variant in the declaration of Value, used to order different variants.
+    fn variant_index(&self) -> usize {
+        match self {
+            Value::Identifier(_) => 0,
+            Value::StringLiteral(_) => 1,
+            Value::IntegerLiteral(_) => 2,
+            Value::FloatLiteral(_) => 3,
+            Value::Boolean(_) => 4,
+            Value::Index(..) => 5,
+            Value::List(_) => 6,
+            Value::Arithmetic(..) => 7,
+            Value::Call(..) => 8,
+            Value::Fallback(..) => 9,
+            Value::DateTime(_) => 10,
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(_) => 11,
+        }
+    }
+    /// The number of nested values including this one, a calculation is one level deeper than its operands.
+    pub(crate) fn depth(&self) -> usize {
+        match self {
+            Value::List(items) => 1 + items.iter().map(Value::depth).max().unwrap_or(0),
+            Value::Arithmetic(lhs, _, rhs) | Value::Fallback(lhs, rhs) => 1 + lhs.depth().max(rhs.depth()),
+            _ => 1,
+        }
+    }
+    /// Whether the value is made of literals only, function calls are never constant since they may be registered.
+    pub(crate) fn is_constant(&self) -> bool {
+        match self {
+            Value::StringLiteral(_) | Value::IntegerLiteral(_) | Value::FloatLiteral(_) | Value::Boolean(_) | Value::DateTime(_) => true,
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(_) => true,
+            Value::List(items) => items.iter().all(Value::is_constant),
+            Value::Arithmetic(lhs, _, rhs) => lhs.is_constant() && rhs.is_constant(),
+            Value::Identifier(_) | Value::Index(..) | Value::Call(..) | Value::Fallback(..) => false,
+        }
+    }
+    /// Converts a resolved value back to a context value, maps become lists of `(key, value)` tuples.
+    pub(crate) fn into_context_value(self) -> Result<ContextValue, EvalError> {
+        Ok(match self {
+            Value::StringLiteral(s) => ContextValue::String(s),
+            Value::IntegerLiteral(i) => ContextValue::Integer(i),
+            Value::FloatLiteral(f) => ContextValue::Float(f),
+            Value::Boolean(b) => ContextValue::Boolean(b),
+            Value::DateTime(timestamp) => ContextValue::DateTime(timestamp),
+            Value::List(items) => ContextValue::List(items.into_iter().map(Value::into_context_value).collect::<Result<_, _>>()?),
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(d) => ContextValue::Decimal(d),
+            value => return Err(EvalError::TypeMismatch(message(|| format!("Value is not resolved: {}", value)))),
+        })
+    }
+    /// The name of the type of a resolved value, used in error messages.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::StringLiteral(_) => "string",
+            Value::IntegerLiteral(_) => "integer",
+            Value::FloatLiteral(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::List(_) => "list",
+            Value::DateTime(_) => "datetime",
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(_) => "decimal",
+            Value::Identifier(_) | Value::Index(..) | Value::Call(..) | Value::Fallback(..) | Value::Arithmetic(..) => "unresolved value",
+        }
+    }
+    /// The type of a literal or resolved value, None for identifiers, calls and calculations
+    /// whose type is only known once they are resolved.
+    pub fn value_type(&self) -> Option<ValueType> {
+        match self {
+            Value::StringLiteral(_) => Some(ValueType::String),
+            Value::IntegerLiteral(_) => Some(ValueType::Integer),
+            Value::FloatLiteral(_) => Some(ValueType::Float),
+            Value::Boolean(_) => Some(ValueType::Boolean),
+            Value::List(_) => Some(ValueType::List),
+            Value::DateTime(_) => Some(ValueType::DateTime),
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(_) => Some(ValueType::Decimal),
+            Value::Identifier(_) | Value::Index(..) | Value::Call(..) | Value::Fallback(..) | Value::Arithmetic(..) => None,
+        }
+    }
+    /// The literal patterns of the regex functions the value calls, so they can be compiled up front.
+    pub(crate) fn regex_patterns(&self) -> Vec<String> {
+        match self {
+            Value::Call(name, args) => {
+                let pattern = match (name.name(), args.as_slice()) {
+                    ("replace", [_, Value::StringLiteral(pattern), _]) => Some(pattern.clone()),
+                    _ => None,
+                };
+                args.iter().flat_map(Value::regex_patterns).chain(pattern).collect()
+            }
+            Value::Arithmetic(lhs, _, rhs) | Value::Fallback(lhs, rhs) => lhs.regex_patterns().into_iter().chain(rhs.regex_patterns()).collect(),
+            Value::List(items) => items.iter().flat_map(Value::regex_patterns).collect(),
+            _ => vec![],
+        }
+    }
+    /// The byte range in the parsed expression from the first to the last identifier of the value, see Identifier::span().
+    /// None if the value has no parsed identifiers, e.g. a literal.
+    pub fn span(&self) -> Option<Range<usize>> {
+        covering_span(self.identifiers())
+    }
+    pub(crate) fn identifiers(&self) -> Vec<&Identifier> {
+        match self {
+            Value::Identifier(identifier) | Value::Index(identifier, _) => vec![identifier],
+            Value::Arithmetic(lhs, _, rhs) => lhs.identifiers().into_iter().chain(rhs.identifiers()).collect(),
+            Value::Call(_, args) => args.iter().flat_map(Value::identifiers).collect(),
+            Value::Fallback(lhs, rhs) => lhs.identifiers().into_iter().chain(rhs.identifiers()).collect(),
+            Value::List(items) => items.iter().flat_map(Value::identifiers).collect(),
+            _ => vec![],
+        }
+    }
+    pub(crate) fn string_literals(&self) -> Vec<&str> {
+        match self {
+            Value::StringLiteral(s) => vec![s.as_str()],
+            Value::Arithmetic(lhs, _, rhs) | Value::Fallback(lhs, rhs) => lhs.string_literals().into_iter().chain(rhs.string_literals()).collect(),
+            Value::Call(_, items) | Value::List(items) => items.iter().flat_map(Value::string_literals).collect(),
+            _ => vec![],
+        }
+    }
+    pub(crate) fn contains_identifier(&self, name: &str) -> bool {
+        match self {
+            Value::Identifier(identifier) | Value::Index(identifier, _) => identifier.name() == name,
+            Value::Arithmetic(lhs, _, rhs) | Value::Fallback(lhs, rhs) => lhs.contains_identifier(name) || rhs.contains_identifier(name),
+            Value::Call(_, items) | Value::List(items) => items.iter().any(|item| item.contains_identifier(name)),
+            _ => false,
+        }
+    }
+}
+
+/// The range from the start of the first to the end of the last span of the identifiers.
+pub(crate) fn covering_span<'a>(identifiers: impl IntoIterator<Item = &'a Identifier>) -> Option<Range<usize>> {
+    identifiers.into_iter().filter_map(Identifier::span).reduce(|lhs, rhs| lhs.start.min(rhs.start)..lhs.end.max(rhs.end))
+}
+
+/// Applies an arithmetic operator to two resolved values.
+fn calculate(lhs: Value, op: &BinaryOperator, rhs: Value) -> Result<Value, EvalError> {
+    #[cfg(feature = "decimal")]
+    let (lhs, rhs) = coerce_decimals(lhs, rhs);
+    match (lhs, op, rhs) {
+        (Value::IntegerLiteral(lhs), BinaryOperator::Modulo, Value::IntegerLiteral(0)) =>
+            Err(EvalError::DivisionByZero(message(|| format!("Modulo by zero: {} % 0", lhs)))),
+        // the remainder always fits, only i64::MIN % -1 wraps and its remainder is 0 anyway
+        (Value::IntegerLiteral(lhs), BinaryOperator::Modulo, Value::IntegerLiteral(rhs)) => Ok(Value::IntegerLiteral(lhs.wrapping_rem(rhs))),
+        // an integer power is only an integer for non-negative exponents, a float base gives fractions
+        (Value::IntegerLiteral(lhs), BinaryOperator::Power, Value::IntegerLiteral(rhs)) if rhs < 0 =>
+            Err(EvalError::TypeMismatch(message(|| format!("Exponent of an integer should not be negative: {} ** {}, use a float base like {}.0", lhs, rhs, lhs)))),
+        // powers of 0, 1 and -1 repeat, so they don't overflow for exponents beyond u32 either
+        (Value::IntegerLiteral(lhs @ -1..=1), BinaryOperator::Power, Value::IntegerLiteral(rhs)) if u32::try_from(rhs).is_err() =>
+            Ok(Value::IntegerLiteral(if lhs == -1 && rhs % 2 == 0 { 1 } else { lhs })),
+        (Value::IntegerLiteral(lhs), BinaryOperator::Power, Value::IntegerLiteral(rhs)) => u32::try_from(rhs).ok().and_then(|exponent| lhs.checked_pow(exponent))
+            .map(Value::IntegerLiteral).ok_or_else(|| EvalError::Overflow(message(|| format!("Integer overflow: {} ** {}", lhs, rhs)))),
+        (Value::FloatLiteral(lhs), BinaryOperator::Power, Value::IntegerLiteral(rhs)) => Ok(Value::FloatLiteral(lhs.powf(rhs as f64))),
+        (Value::IntegerLiteral(lhs), op, Value::IntegerLiteral(rhs)) => match op {
+            BinaryOperator::Add => lhs.checked_add(rhs),
+            BinaryOperator::Subtract => lhs.checked_sub(rhs),
+            _ => lhs.checked_mul(rhs),
+        }.map(Value::IntegerLiteral).ok_or_else(|| EvalError::Overflow(message(|| format!("Integer overflow: {} {} {}", lhs, op, rhs)))),
+        (Value::FloatLiteral(lhs), op, Value::FloatLiteral(rhs)) if *op != BinaryOperator::Modulo => Ok(Value::FloatLiteral(match op {
+            BinaryOperator::Add => lhs + rhs,
+            BinaryOperator::Subtract => lhs - rhs,
+            BinaryOperator::Power => lhs.powf(rhs),
+            _ => lhs * rhs,
+        })),
+        #[cfg(feature = "decimal")]
+        (Value::DecimalLiteral(lhs), op, Value::DecimalLiteral(rhs)) if !matches!(op, BinaryOperator::Modulo | BinaryOperator::Power) => match op {
+            BinaryOperator::Add => lhs.checked_add(rhs),
+            BinaryOperator::Subtract => lhs.checked_sub(rhs),
+            _ => lhs.checked_mul(rhs),
+        }.map(Value::DecimalLiteral).ok_or_else(|| EvalError::Overflow(message(|| format!("Decimal overflow: {} {} {}", lhs, op, rhs)))),
+        (Value::DateTime(lhs), BinaryOperator::Subtract, Value::DateTime(rhs)) => lhs.checked_sub(rhs).map(Value::IntegerLiteral)
+            .ok_or_else(|| EvalError::Overflow(message(|| format!("Datetime overflow: {} - {}", Value::DateTime(lhs), Value::DateTime(rhs))))),
+        (Value::DateTime(timestamp), op @ (BinaryOperator::Add | BinaryOperator::Subtract), Value::StringLiteral(duration))
+        | (Value::StringLiteral(duration), op @ BinaryOperator::Add, Value::DateTime(timestamp)) => {
+            let seconds = parse_duration(&duration).and_then(|duration| i64::try_from(duration.as_secs()).ok())
+                .ok_or_else(|| EvalError::TypeMismatch(message(|| format!("Value should be a duration like '7d': '{}'", duration))))?;
+            match op {
+                BinaryOperator::Add => timestamp.checked_add(seconds),
+                _ => timestamp.checked_sub(seconds),
+            }.map(Value::DateTime).ok_or_else(|| EvalError::Overflow(message(|| format!("Datetime overflow: {} {} '{}'", Value::DateTime(timestamp), op, duration))))
+        }
+        (lhs, BinaryOperator::Modulo, rhs) => Err(EvalError::TypeMismatch(message(|| format!("Operator % requires integers: {} % {}", lhs, rhs)))),
+        (lhs, op, rhs) => Err(EvalError::TypeMismatch(message(|| format!("Operator {} requires numbers of the same type: {} {} {}", op, lhs, op, rhs)))),
+    }
+}
+
+/// Parses a string compared to a datetime as an RFC 3339 timestamp.
+pub(crate) fn coerce_datetimes(lhs: Value, rhs: Value) -> Result<(Value, Value), EvalError> {
+    let parse = |s: String| parse_datetime(&s).map(Value::DateTime)
+        .ok_or_else(|| EvalError::TypeMismatch(message(|| format!("Value should be an RFC 3339 timestamp like '2024-01-01T00:00:00Z': '{}'", s))));
+    Ok(match (lhs, rhs) {
+        (Value::DateTime(lhs), Value::StringLiteral(rhs)) => (Value::DateTime(lhs), parse(rhs)?),
+        (Value::StringLiteral(lhs), Value::DateTime(rhs)) => (parse(lhs)?, Value::DateTime(rhs)),
+        values => values,
+    })
+}
+
+/// Parses a string compared to an integer or a float as a number of the same type, see EvalOptions::coerce_strings.
+pub(crate) fn coerce_numbers(lhs: Value, rhs: Value) -> Result<(Value, Value), EvalError> {
+    let parse = |s: String, other: &Value| match other {
+        Value::IntegerLiteral(_) => s.trim().parse().map(Value::IntegerLiteral).map_err(|_| EvalError::TypeMismatch(message(|| format!("Value should be an integer: '{}'", s)))),
+        _ => s.trim().parse().map(Value::FloatLiteral).map_err(|_| EvalError::TypeMismatch(message(|| format!("Value should be a float: '{}'", s)))),
+    };
+    Ok(match (lhs, rhs) {
+        (lhs @ (Value::IntegerLiteral(_) | Value::FloatLiteral(_)), Value::StringLiteral(rhs)) => {
+            let rhs = parse(rhs, &lhs)?;
+            (lhs, rhs)
+        }
+        (Value::StringLiteral(lhs), rhs @ (Value::IntegerLiteral(_) | Value::FloatLiteral(_))) => (parse(lhs, &rhs)?, rhs),
+        values => values,
+    })
+}
+
+/// Converts an integer to a decimal if the other value is a decimal, integers can always be represented exactly.
+#[cfg(feature = "decimal")]
+pub(crate) fn coerce_decimals(lhs: Value, rhs: Value) -> (Value, Value) {
+    match (lhs, rhs) {
+        (Value::IntegerLiteral(i), rhs @ Value::DecimalLiteral(_)) => (Value::DecimalLiteral(i.into()), rhs),
+        (lhs @ Value::DecimalLiteral(_), Value::IntegerLiteral(i)) => (lhs, Value::DecimalLiteral(i.into())),
+        values => values,
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Identifier(identifier) => write!(f, "{}", identifier),
+            Value::StringLiteral(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Value::IntegerLiteral(i) => write!(f, "{}", i),
+            // Display never uses an exponent, a whole number gets a fractional part (1.0 instead of 1) so the literal parses as a float again
+            Value::FloatLiteral(x) if x.is_finite() && x.fract() == 0.0 => write!(f, "{}.0", x),
+            Value::FloatLiteral(x) => write!(f, "{}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Index(identifier, index) => write!(f, "{}[{}]", identifier, index),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            Value::Arithmetic(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+            Value::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Value::Fallback(lhs, rhs) => write!(f, "{} ?: {}", lhs, rhs),
+            Value::DateTime(timestamp) => write!(f, "'{}'", format_datetime(*timestamp)),
+            #[cfg(feature = "decimal")]
+            Value::DecimalLiteral(d) => write!(f, "dec'{}'", d),
+        }
+    }
 }
 
 impl From<&ContextValue> for Value {
@@ -27,75 +431,489 @@ impl From<&ContextValue> for Value {
             ContextValue::Integer(i) => Value::IntegerLiteral(*i),
             ContextValue::Float(f) => Value::FloatLiteral(*f),
             ContextValue::Boolean(b) => Value::Boolean(*b),
+            ContextValue::DateTime(timestamp) => Value::DateTime(*timestamp),
+            ContextValue::List(items) => Value::List(items.iter().map(Value::from).collect()),
+            ContextValue::Map(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                Value::List(entries.into_iter().map(|(key, value)| Value::List(vec![Value::StringLiteral(key.clone()), value.into()])).collect())
+            }
+            #[cfg(feature = "decimal")]
+            ContextValue::Decimal(d) => Value::DecimalLiteral(*d),
+        }
+    }
+}
+
+/// Moves strings and lists instead of cloning them like From<&ContextValue>.
+impl From<ContextValue> for Value {
+    fn from(value: ContextValue) -> Self {
+        match value {
+            ContextValue::String(s) => Value::StringLiteral(s),
+            ContextValue::List(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            ContextValue::Map(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                Value::List(entries.into_iter().map(|(key, value)| Value::List(vec![Value::StringLiteral(key), value.into()])).collect())
+            }
+            value => (&value).into(),
         }
     }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
-pub(crate) struct Identifier(String);
+/// Converts a value returned by ContextProvider::get(), only a borrowed value is cloned.
+impl From<Cow<'_, ContextValue>> for Value {
+    fn from(value: Cow<'_, ContextValue>) -> Self {
+        match value {
+            Cow::Borrowed(value) => value.into(),
+            Cow::Owned(value) => value.into(),
+        }
+    }
+}
+
+/// The name of a value which is looked up in the context.
+/// An identifier parsed from an expression knows its position in the source, see span().
+/// The position is not part of the identity, identifiers with the same name are equal.
+#[derive(Clone)]
+pub struct Identifier(String, Option<Box<Range<usize>>>);
 impl Identifier {
-    pub(crate) fn use_context(&self, context: &std::collections::HashMap<String, ContextValue>) -> Result<Value, String> {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+    /// The byte range of the identifier in the parsed expression including the backticks of a quoted identifier,
+    /// e.g. to underline it in an editor. None if the identifier was not parsed but created, e.g. with Identifier::from().
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.1.as_deref().cloned()
+    }
+    /// Whether the identifier is written like a constant, in upper case letters, digits and underscores.
+    pub(crate) fn is_constant(&self) -> bool {
+        self.0.chars().any(|c| c.is_ascii_uppercase()) && self.0.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+    }
+    pub(crate) fn rename(self, f: &dyn Fn(&str) -> String) -> Identifier {
+        Identifier(f(&self.0), self.1)
+    }
+    pub(crate) fn use_context(&self, context: &dyn ContextProvider) -> Result<Value, EvalError> {
+        let not_found = |name: &str| EvalError::UnknownIdentifier(message(|| format!("Identifier not found in context: {}", name)));
         if let Some(val) = context.get(&self.0) {
-            Ok(val.into())
-        } else {
-            Err(format!("Identifier not found in context: {}", &self.0))
+            return Ok(val.into());
         }
+        // a dotted name which is not a key itself is looked up through nested maps
+        let mut segments = self.0.split('.');
+        let first = segments.next().unwrap_or_default();
+        let root = context.get(first).filter(|_| first.len() < self.0.len()).ok_or_else(|| not_found(&self.0))?;
+        // only the value at the end of the path is converted, the maps on the way are borrowed
+        let (mut value, mut path_len) = (root.as_ref(), first.len());
+        for segment in segments {
+            let ContextValue::Map(map) = value else {
+                return Err(EvalError::TypeMismatch(message(|| format!("Value should be a map: {}", &self.0[..path_len]))));
+            };
+            path_len += 1 + segment.len();
+            value = map.get(segment).ok_or_else(|| not_found(&self.0[..path_len]))?;
+        }
+        Ok(value.into())
+    }
+}
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match all_consuming(plain_identifier)(&self.0) {
+            Ok(_) => write!(f, "{}", self.0),
+            Err(_) => write!(f, "`{}`", self.0),
+        }
+    }
+}
+impl fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Identifier").field(&self.0).finish()
+    }
+}
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl Eq for Identifier {}
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+impl Hash for Identifier {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
     }
 }
 impl From<&str> for Identifier {
     fn from(value: &str) -> Self {
-        Identifier(value.to_string())
+        Identifier(value.to_string(), None)
     }
 }
 impl From<String> for Identifier {
     fn from(value: String) -> Self {
-        Identifier(value)
+        Identifier(value, None)
+    }
+}
+// only the name is serialized, like a newtype struct
+#[cfg(feature = "serde")]
+impl serde::Serialize for Identifier {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct("Identifier", &self.0)
     }
 }
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Identifier {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "Identifier")]
+        struct Name(String);
+        Name::deserialize(deserializer).map(|Name(name)| Identifier(name, None))
+    }
+}
+
 
+/// The digits of a number with an optional minus sign, `-5` is a literal and not a calculation.
+fn signed_digits(input: &str) -> IResult<&str, &str> {
+    recognize(preceded(opt(char('-')), take_while1(|c: char| c.is_ascii_digit())))(input)
+}
 
+/// An integer like `5` or `-5`, the suffix `i` like in `5i` states the type explicitly.
 pub(crate) fn integer(input: &str) -> IResult<&str, Value> {
-    alt((map(take_while1(|c: char| c.is_ascii_digit()), |s: &str| Value::IntegerLiteral(s.parse::<i64>().unwrap())),
-    identifier))(input)
+    alt((map_res(terminated(signed_digits, opt(number_suffix('i'))), |s: &str| s.parse::<i64>().map(Value::IntegerLiteral)),
+    variable))(input)
 }
 
+/// A float like `5.0` or `-5.0`, the suffix `f` like in `5f` makes a whole number a float.
 pub(crate) fn float(input: &str) -> IResult<&str, Value> {
     alt((map(
         tuple((
-            take_while1(|c: char| c.is_ascii_digit()),
+            signed_digits,
             char('.'),
             take_while1(|c: char| c.is_ascii_digit()),
+            opt(number_suffix('f')),
         )),
-        |(int, _, frac)| {
+        |(int, _, frac, _)| {
             Value::FloatLiteral(format!("{}.{}", int, frac).parse::<f64>().unwrap())
         },
-    ), identifier))(input)
+    ),
+    map_res(terminated(signed_digits, number_suffix('f')), |s: &str| s.parse::<f64>().map(Value::FloatLiteral)),
+    variable))(input)
 }
 
+/// The suffix of a number which forces its type, it can't be followed by the characters of an identifier.
+fn number_suffix(suffix: char) -> impl Fn(&str) -> IResult<&str, char> {
+    move |input| terminated(char(suffix), not(satisfy(|c: char| c.is_alphanumeric() || c == '_' || c == '.')))(input)
+}
+
+/// A string like `'normal'`, a quote in it is written twice like in `'O''Brien'`.
 pub(crate) fn string(input: &str) -> IResult<&str, Value> {
-    alt((map(delimited(char('\''), take_while1(|c: char| c != '\''), char('\'')), |s: &str| Value::StringLiteral(s.to_string())), identifier))(input)
+    let content = recognize(many0(alt((take_while1(|c: char| c != '\''), tag("''")))));
+    alt((map(delimited(char('\''), content, char('\'')), |s: &str| Value::StringLiteral(s.replace("''", "'"))), variable))(input)
 }
 
 pub(crate) fn identifier(input: &str) -> IResult<&str, Value> {
-    map_res(take_while1(|c: char| c.is_ascii_alphabetic() || c == '.' || c == '_'), |s: &str| {
-        if s == "true" || s == "false" {
-            return Err(format!("Identifier should not be true or false: {}", s))
-        } 
-        Ok(Value::Identifier(Identifier(s.to_string())))
+    alt((quoted_identifier, plain_identifier))(input)
+}
+
+/// An identifier written in backticks like `` `order total` ``, for context keys which are not valid identifiers.
+fn quoted_identifier(input: &str) -> IResult<&str, Value> {
+    let (rest, quoted) = recognize(delimited(char('`'), take_while1(|c: char| c != '`'), char('`')))(input)?;
+    Ok((rest, Value::Identifier(Identifier(quoted[1..quoted.len() - 1].to_string(), source_span(quoted).map(Box::new)))))
+}
+
+fn plain_identifier(input: &str) -> IResult<&str, Value> {
+    let head = take_while1(|c: char| c.is_ascii_alphabetic() || c == '.' || c == '_');
+    let tail = take_while(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_');
+    map_res(recognize(tuple((head, tail))), |s: &str| {
+        if boolean_keyword(s).is_some() {
+            return Err(format!("Identifier should not be a boolean keyword: {}", s))
+        }
+        if is_operator_keyword(s) {
+            return Err(format!("Identifier should not be an operator keyword: {}", s))
+        }
+        Ok(Value::Identifier(Identifier(s.to_string(), source_span(s).map(Box::new))))
+    })(input)
+}
+
+/// An identifier which is optionally indexed, e.g. `scores` or `scores[0]`.
+pub(crate) fn variable(input: &str) -> IResult<&str, Value> {
+    alt((map_res(tuple((identifier, delimited(char('['), i64, char(']')))), |(value, index)| match value {
+        Value::Identifier(identifier) => Ok(Value::Index(identifier, index)),
+        _ => Err(format!("Only identifiers can be indexed: {:?}", value)),
+    }), identifier))(input)
+}
+
+/// A decimal literal like `dec'1.10'`, the scale of the literal is kept.
+pub(crate) fn decimal(input: &str) -> IResult<&str, Value> {
+    map_res(preceded(tag("dec"), delimited(char('\''), take_while1(|c: char| c != '\''), char('\''))), parse_decimal)(input)
+}
+
+#[cfg(feature = "decimal")]
+fn parse_decimal(s: &str) -> Result<Value, String> {
+    rust_decimal::Decimal::from_str_exact(s).map(Value::DecimalLiteral).map_err(|err| format!("Invalid decimal {}: {}", s, err))
+}
+
+#[cfg(not(feature = "decimal"))]
+fn parse_decimal(s: &str) -> Result<Value, String> {
+    Err(format!("Decimal literal {} requires the decimal feature", s))
+}
+
+/// A number or a calculation with numbers like `index % 5` or `price + 1`.
+/// `**` binds tighter than `%` and `*`, which bind tighter than `+` and `-`. `**` is joined to the right, the others to the left.
+/// Calls and strings are operands too, for datetime arithmetic like `now() - '7d'`.
+pub(crate) fn arithmetic(input: &str) -> IResult<&str, Value> {
+    let number = |input| alt((call, decimal, float, integer, string))(input);
+    let factor = |input| power(input, number);
+    let term = |input| fold_arithmetic(input, factor, "%*");
+    fold_arithmetic(input, term, "+-")
+}
+
+/// A base optionally raised to a power, `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+fn power<'a>(input: &'a str, operand: impl Fn(&'a str) -> IResult<&'a str, Value> + Copy) -> IResult<&'a str, Value> {
+    let (input, first) = operand(input)?;
+    let (input, mut rest) = many0(preceded(delimited(multispace0, tag("**"), multispace0), operand))(input)?;
+    // folded from the right, the last operand is the innermost exponent
+    let Some(last) = rest.pop() else { return Ok((input, first)) };
+    std::iter::once(first).chain(rest).rev().try_fold((input, last), |(input, exponent), base| {
+        let value = Value::Arithmetic(Box::new(base), BinaryOperator::Power, Box::new(exponent));
+        check_value_depth(input, &value)?;
+        Ok((input, value))
+    })
+}
+
+fn fold_arithmetic<'a>(input: &'a str, operand: impl Fn(&'a str) -> IResult<&'a str, Value> + Copy, symbols: &'static str) -> IResult<&'a str, Value> {
+    let (input, first) = operand(input)?;
+    // a `*` followed by another one is the power operator, which power() already parsed if it is valid
+    let op = map_res(terminated(one_of(symbols), not(char('*'))), |symbol: char| BinaryOperator::try_from(symbol.to_string().as_str()));
+    let (input, rest) = many0(tuple((delimited(multispace0, op, multispace0), operand)))(input)?;
+    rest.into_iter().try_fold((input, first), |(input, lhs), (op, rhs)| {
+        let value = Value::Arithmetic(Box::new(lhs), op, Box::new(rhs));
+        check_value_depth(input, &value)?;
+        Ok((input, value))
+    })
+}
+
+/// A function call like `sha256(payload)`, the arguments are literals or identifiers.
+pub(crate) fn call(input: &str) -> IResult<&str, Value> {
+    let argument = |input| delimited(multispace0, alt((float, integer, string)), multispace0)(input);
+    map_res(tuple((identifier, delimited(char('('), separated_list0(char(','), argument), char(')')))), |(name, args)| match name {
+        Value::Identifier(name) => Ok(Value::Call(name, args)),
+        _ => Err(format!("Only identifiers can be called: {:?}", name)),
     })(input)
 }
 
+/// A value optionally followed by fallbacks, `a ?: b ?: c` uses the first value which resolves.
+/// `?:` binds looser than `%` and `+` but tighter than comparisons, `??` is the same operator.
+pub(crate) fn fallback(input: &str) -> IResult<&str, Value> {
+    let (input, first) = arithmetic(input)?;
+    let (input, rest) = many0(preceded(tuple((multispace0, alt((tag("?:"), tag("??"))), multispace0)), arithmetic))(input)?;
+    rest.into_iter().try_fold((input, first), |(input, lhs), rhs| {
+        let value = Value::Fallback(Box::new(lhs), Box::new(rhs));
+        check_value_depth(input, &value)?;
+        Ok((input, value))
+    })
+}
+
 pub(crate) fn boolean(input: &str) -> IResult<&str, Value> {
-    alt((map(alt((tag("true"), tag("false"))), |c: &str| Value::Boolean(c == "true")), identifier))(input)
+    let word = recognize(tuple((take_while1(|c: char| c.is_ascii_alphabetic()), take_while(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_'))));
+    alt((map_opt(word, |word| boolean_keyword(word).map(Value::Boolean)), identifier))(input)
+}
+
+thread_local! {
+    /// The options of the running parse, they change the boolean keywords and declare custom operators.
+    static PARSE_OPTIONS: RefCell<Option<ParseOptions>> = const { RefCell::new(None) };
+    /// The address and the length of the source of the running parse, the parsers get slices of it.
+    static SOURCE: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Runs the parser on the source so identifiers record their position in it.
+/// The source must have the same byte offsets as the input, comments are replaced by whitespace of the same length.
+pub(crate) fn with_source<T>(source: &str, parse: impl FnOnce() -> T) -> T {
+    let previous = SOURCE.with(|current| current.replace(Some((source.as_ptr() as usize, source.len()))));
+    let result = parse();
+    SOURCE.with(|current| current.set(previous));
+    result
+}
+
+/// The byte range of the slice in the source of the running parse, None if it is not a part of it.
+fn source_span(slice: &str) -> Option<Range<usize>> {
+    let (address, len) = SOURCE.with(Cell::get)?;
+    let start = (slice.as_ptr() as usize).checked_sub(address)?;
+    (start + slice.len() <= len).then(|| start..start + slice.len())
+}
+
+/// Runs the parser with the options in effect.
+pub(crate) fn with_parse_options<T>(options: &ParseOptions, parse: impl FnOnce() -> T) -> T {
+    let previous = PARSE_OPTIONS.with(|current| current.replace(Some(options.clone())));
+    let result = parse();
+    PARSE_OPTIONS.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// The value of `true`, `false` or one of the keywords the parse options add.
+fn boolean_keyword(word: &str) -> Option<bool> {
+    match word {
+        "true" => return Some(true),
+        "false" => return Some(false),
+        _ => {}
+    }
+    PARSE_OPTIONS.with(|options| {
+        let options = options.borrow();
+        let options = options.as_ref()?;
+        let matches = |keyword: &str| if options.case_insensitive_booleans { keyword.eq_ignore_ascii_case(word) } else { keyword == word };
+        let value = [("true", "false")].into_iter().chain(options.boolean_aliases.iter().map(|(yes, no)| (yes.as_str(), no.as_str())))
+            .find_map(|(yes, no)| if matches(yes) { Some(true) } else if matches(no) { Some(false) } else { None });
+        value
+    })
+}
+
+/// Whether the word is the keyword of a custom operator declared by the parse options.
+pub(crate) fn is_custom_operator(word: &str) -> bool {
+    PARSE_OPTIONS.with(|options| options.borrow().as_ref().is_some_and(|options| options.custom_operators.iter().any(|name| name == word)))
+}
+
+/// Fails with EvalError::InvalidOption if a custom operator of the parse options in effect is a builtin or boolean keyword.
+pub(crate) fn check_custom_operators() -> Result<(), EvalError> {
+    let custom_operators = PARSE_OPTIONS.with(|options| options.borrow().as_ref().map(|options| options.custom_operators.clone()).unwrap_or_default());
+    for name in custom_operators {
+        if is_builtin_keyword(&name) {
+            return Err(EvalError::InvalidOption(format!("Custom operator can not be a builtin keyword: {}", name)));
+        }
+        if boolean_keyword(&name).is_some() {
+            return Err(EvalError::InvalidOption(format!("Custom operator can not be a boolean keyword: {}", name)));
+        }
+    }
+    Ok(())
 }
 
 #[test]
 fn test_value() {
     // Test conversion of various values to the corresponding enum value.
-    assert_eq!(identifier("foo").unwrap().1, Value::Identifier(Identifier("foo".to_string())));
+    assert_eq!(identifier("foo").unwrap().1, Value::Identifier(Identifier::from("foo")));
     assert_eq!(string("'foo'").unwrap().1, Value::StringLiteral("foo".to_string()));
     assert_eq!(integer("1").unwrap().1, Value::IntegerLiteral(1));
     assert_eq!(float("1.0").unwrap().1, Value::FloatLiteral(1.0));
     assert_eq!(boolean("true").unwrap().1, Value::Boolean(true));
     assert_eq!(boolean("false").unwrap().1, Value::Boolean(false));
+    assert_eq!(integer("foo[2]").unwrap().1, Value::Index(Identifier::from("foo"), 2));
+    assert_eq!(string("foo[-1]").unwrap().1, Value::Index(Identifier::from("foo"), -1));
+}
+
+#[test]
+fn test_value_type() {
+    assert_eq!(Value::StringLiteral("a".to_string()).value_type(), Some(ValueType::String));
+    assert_eq!(Value::FloatLiteral(1.0).value_type(), Some(ValueType::Float));
+    assert_eq!(Value::List(vec![]).value_type(), Some(ValueType::List));
+    assert_eq!(arithmetic("1 + 2").unwrap().1.value_type(), None);
+    assert_eq!(identifier("a").unwrap().1.value_type(), None);
+}
+
+#[test]
+fn test_hash() {
+    let hash = |value: &Value| {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(Value::FloatLiteral(0.0), Value::FloatLiteral(-0.0));
+    assert_eq!(hash(&Value::FloatLiteral(0.0)), hash(&Value::FloatLiteral(-0.0)));
+    assert_eq!(hash(&Value::FloatLiteral(f64::NAN)), hash(&Value::FloatLiteral(-f64::NAN)));
+    assert_eq!(Value::FloatLiteral(f64::NAN), Value::FloatLiteral(-f64::NAN));
+    assert_eq!(Value::FloatLiteral(f64::NAN).partial_cmp(&Value::FloatLiteral(f64::NAN)), Some(Ordering::Equal));
+    assert_ne!(Value::FloatLiteral(f64::NAN), Value::FloatLiteral(1.0));
+    assert!(Value::IntegerLiteral(2) < Value::FloatLiteral(1.0));
+}
+
+#[test]
+fn test_boolean_keywords() {
+    assert_eq!(boolean("trueish").unwrap().1, Value::Identifier(Identifier::from("trueish")));
+    assert_eq!(boolean("YES").unwrap().1, Value::Identifier(Identifier::from("YES")));
+    let lenient = ParseOptions::lenient();
+    with_parse_options(&lenient, || {
+        assert_eq!(boolean("YES").unwrap().1, Value::Boolean(true));
+        assert_eq!(boolean("Off").unwrap().1, Value::Boolean(false));
+        assert_eq!(boolean("False").unwrap().1, Value::Boolean(false));
+        assert!(identifier("on").is_err());
+        assert_eq!(boolean("online").unwrap().1, Value::Identifier(Identifier::from("online")));
+    });
+    let aliases = ParseOptions { boolean_aliases: vec![("ja".to_string(), "nein".to_string())], ..Default::default() };
+    with_parse_options(&aliases, || {
+        assert_eq!(boolean("nein").unwrap().1, Value::Boolean(false));
+        assert_eq!(boolean("Nein").unwrap().1, Value::Identifier(Identifier::from("Nein")));
+        assert_eq!(boolean("TRUE").unwrap().1, Value::Identifier(Identifier::from("TRUE")));
+    });
+    assert_eq!(boolean("nein").unwrap().1, Value::Identifier(Identifier::from("nein")));
+}
+
+#[test]
+fn test_arithmetic() {
+    let value = arithmetic("index % 5 % 2").unwrap().1;
+    let index = Box::new(Value::Identifier(Identifier::from("index")));
+    let inner = Value::Arithmetic(index, BinaryOperator::Modulo, Box::new(Value::IntegerLiteral(5)));
+    assert_eq!(value, Value::Arithmetic(Box::new(inner), BinaryOperator::Modulo, Box::new(Value::IntegerLiteral(2))));
+    assert_eq!(value.to_string(), "index % 5 % 2");
+    assert_eq!(arithmetic("1.5").unwrap().1, Value::FloatLiteral(1.5));
+}
+
+#[test]
+fn test_fallback() {
+    let mut context = crate::Context::new();
+    context.insert("username".to_string(), ContextValue::String("ada".to_string()));
+    let value = fallback("nickname ?: username ?: 'anonymous'").unwrap().1;
+    assert_eq!(value.to_string(), "nickname ?: username ?: 'anonymous'");
+    assert_eq!(value.clone().use_context(&context), Ok(Value::StringLiteral("ada".to_string())));
+    context.insert("nickname".to_string(), ContextValue::String("lovelace".to_string()));
+    assert_eq!(value.use_context(&context), Ok(Value::StringLiteral("lovelace".to_string())));
+    let value = fallback("missing ?: other").unwrap().1;
+    assert!(matches!(value.use_context(&context), Err(EvalError::UnknownIdentifier(_))));
+}
+
+#[test]
+fn test_number_suffix() {
+    assert_eq!(float("5f"), Ok(("", Value::FloatLiteral(5.0))));
+    assert_eq!(float("5.5f"), Ok(("", Value::FloatLiteral(5.5))));
+    assert_eq!(integer("5i"), Ok(("", Value::IntegerLiteral(5))));
+    assert_eq!(integer("5 in"), Ok((" in", Value::IntegerLiteral(5))));
+    assert_eq!(integer("5if"), Ok(("if", Value::IntegerLiteral(5))));
+    assert!(float("5").is_err());
+    assert!(float("5fi").is_err());
+    let context = std::collections::HashMap::new();
+    assert_eq!(crate::evaluate("5f == 5.0", &context), Ok(true));
+    assert_eq!(crate::evaluate("5i == 5", &context), Ok(true));
+    assert_eq!(crate::evaluate("7i % 2i == 1 && 1f + 2f == 3.0", &context), Ok(true));
+    assert_eq!(crate::parse("x == 5f").unwrap().to_string(), "x == 5.0");
+    assert_eq!(crate::evaluate("x > 1f", &context).unwrap_err().code(), crate::EvalErrorCode::UnknownIdentifier);
+}
+
+#[test]
+fn test_integer_overflow() {
+    assert!(integer("99999999999999999999").is_err());
+    let context = std::collections::HashMap::new();
+    let calculate_str = |input| arithmetic(input).unwrap().1.use_context(&context);
+    assert_eq!(calculate_str("9223372036854775807 * 2"), Err(EvalError::Overflow("Integer overflow: 9223372036854775807 * 2".to_string())));
+    assert_eq!(calculate_str("9223372036854775807 + 1"), Err(EvalError::Overflow("Integer overflow: 9223372036854775807 + 1".to_string())));
+    assert_eq!(calculate_str("0 - 9223372036854775807 - 2"), Err(EvalError::Overflow("Integer overflow: -9223372036854775807 - 2".to_string())));
+    assert_eq!(calculate_str("0 - 9223372036854775807 - 1"), Ok(Value::IntegerLiteral(i64::MIN)));
+    assert_eq!(calculate_str("4611686018427387903 * 2"), Ok(Value::IntegerLiteral(9223372036854775806)));
+}
+
+#[test]
+fn test_call() {
+    assert_eq!(identifier("sha256(").unwrap(), ("(", Value::Identifier(Identifier::from("sha256"))));
+    assert!(identifier("1a").is_err());
+    let value = call("sha256( payload , 'x')").unwrap().1;
+    let args = vec![Value::Identifier(Identifier::from("payload")), Value::StringLiteral("x".to_string())];
+    assert_eq!(value, Value::Call(Identifier::from("sha256"), args));
+    assert_eq!(value.to_string(), "sha256(payload, 'x')");
+    assert_eq!(string("''").unwrap().1, Value::StringLiteral(String::new()));
+}
+
+#[test]
+fn test_index_use_context() {
+    let mut context = std::collections::HashMap::new();
+    context.insert("scores".to_string(), ContextValue::List(vec![ContextValue::Integer(95), ContextValue::Integer(42)]));
+    context.insert("name".to_string(), ContextValue::String("foo".to_string()));
+    let index = |i| Value::Index(Identifier::from("scores"), i);
+    assert_eq!(index(0).use_context(&context), Ok(Value::IntegerLiteral(95)));
+    assert_eq!(index(-1).use_context(&context), Ok(Value::IntegerLiteral(42)));
+    assert_eq!(index(2).use_context(&context), Err(EvalError::IndexOutOfBounds("Index 2 is out of bounds for scores with length 2".to_string())));
+    assert_eq!(index(-3).use_context(&context), Err(EvalError::IndexOutOfBounds("Index -3 is out of bounds for scores with length 2".to_string())));
+    assert!(matches!(Value::Index(Identifier::from("name"), 0).use_context(&context), Err(EvalError::TypeMismatch(_))));
 }
\ No newline at end of file